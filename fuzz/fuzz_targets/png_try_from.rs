@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pngme::Png;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(png) = Png::try_from(data) {
+        assert_eq!(png.as_bytes().unwrap(), data);
+    }
+});
@@ -0,0 +1,222 @@
+use crate::header::ImageHeader;
+
+/// Where each of the seven Adam7 passes starts, and how far apart its
+/// pixels are: `(x, y, dx, dy)`.
+const PASSES: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// One of the reduced images an interlaced image is stored as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pass {
+    index: usize,
+    width: u32,
+    height: u32,
+    bits_per_pixel: usize,
+}
+
+impl Pass {
+    /// Which pass this is, from 0 to 6.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    /// Bytes in each scanline of the pass, without the filter type byte.
+    pub fn row_bytes(&self) -> usize {
+        (self.width as usize * self.bits_per_pixel).div_ceil(8)
+    }
+    /// Bytes the pass takes up in the inflated image data, filter type
+    /// bytes included.
+    pub fn raw_size(&self) -> Option<usize> {
+        (self.width as usize)
+            .checked_mul(self.bits_per_pixel)?
+            .div_ceil(8)
+            .checked_add(1)?
+            .checked_mul(self.height as usize)
+    }
+
+    /// The position in the full image of pixel `(x, y)` of the pass.
+    fn position(&self, x: u32, y: u32) -> (u32, u32) {
+        let (x0, y0, dx, dy) = PASSES[self.index];
+        (x0 + x * dx, y0 + y * dy)
+    }
+}
+
+/// The passes of an image of this size that hold any pixels. Small images
+/// leave some passes empty, and those are left out of the image data.
+pub fn passes(header: &ImageHeader) -> Vec<Pass> {
+    PASSES
+        .iter()
+        .enumerate()
+        .map(|(index, &(x0, y0, dx, dy))| Pass {
+            index,
+            width: (header.width().saturating_sub(x0)).div_ceil(dx),
+            height: (header.height().saturating_sub(y0)).div_ceil(dy),
+            bits_per_pixel: header.bits_per_pixel(),
+        })
+        .filter(|pass| pass.width > 0 && pass.height > 0)
+        .collect()
+}
+
+/// Gathers the pixels of `pass` out of the full-size unfiltered `pixels`,
+/// as rows of [`Pass::row_bytes`] bytes.
+pub fn extract(header: &ImageHeader, pixels: &[u8], pass: &Pass) -> Vec<u8> {
+    let row_bytes: usize = header.row_bytes();
+    let mut reduced: Vec<u8> = vec![0; pass.row_bytes() * pass.height as usize];
+    for y in 0..pass.height {
+        for x in 0..pass.width {
+            let (from_x, from_y) = pass.position(x, y);
+            let from: &[u8] = &pixels[from_y as usize * row_bytes..];
+            let to: &mut [u8] = &mut reduced[y as usize * pass.row_bytes()..];
+            copy_pixel(from, from_x, to, x, pass.bits_per_pixel);
+        }
+    }
+    reduced
+}
+
+/// Scatters the rows of `pass` in `reduced` to their places in the
+/// full-size `pixels`.
+pub fn insert(
+    header: &ImageHeader,
+    pixels: &mut [u8],
+    pass: &Pass,
+    reduced: &[u8],
+) {
+    let row_bytes: usize = header.row_bytes();
+    for y in 0..pass.height {
+        for x in 0..pass.width {
+            let (to_x, to_y) = pass.position(x, y);
+            let from: &[u8] = &reduced[y as usize * pass.row_bytes()..];
+            let to: &mut [u8] = &mut pixels[to_y as usize * row_bytes..];
+            copy_pixel(from, x, to, to_x, pass.bits_per_pixel);
+        }
+    }
+}
+
+/// Copies pixel `from_x` of the row `from` to pixel `to_x` of the row
+/// `to`. Pixels narrower than a byte are packed from the most significant
+/// bit down.
+fn copy_pixel(from: &[u8], from_x: u32, to: &mut [u8], to_x: u32, bits: usize) {
+    if bits >= 8 {
+        let bytes: usize = bits / 8;
+        let (from_x, to_x) = (from_x as usize * bytes, to_x as usize * bytes);
+        to[to_x..to_x + bytes].copy_from_slice(&from[from_x..from_x + bytes]);
+        return;
+    }
+
+    let mask: u8 = (1 << bits) - 1;
+    let (from_bit, to_bit) = (from_x as usize * bits, to_x as usize * bits);
+    let value: u8 = (from[from_bit / 8] >> (8 - bits - from_bit % 8)) & mask;
+    let shift: usize = 8 - bits - to_bit % 8;
+    to[to_bit / 8] = (to[to_bit / 8] & !(mask << shift)) | (value << shift);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::{ColorType, Interlace};
+
+    fn header(width: u32, height: u32, bit_depth: u8) -> ImageHeader {
+        ImageHeader::new(
+            width,
+            height,
+            bit_depth,
+            ColorType::Grayscale,
+            Interlace::Adam7,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_pass_sizes() {
+        let sizes: Vec<(u32, u32)> = passes(&header(8, 8, 8))
+            .iter()
+            .map(|pass| (pass.width(), pass.height()))
+            .collect();
+        assert_eq!(
+            sizes,
+            [(1, 1), (1, 1), (2, 1), (2, 2), (4, 2), (4, 4), (8, 4)]
+        );
+
+        let sizes: Vec<(usize, u32, u32)> = passes(&header(3, 2, 8))
+            .iter()
+            .map(|pass| (pass.index(), pass.width(), pass.height()))
+            .collect();
+        assert_eq!(sizes, [(0, 1, 1), (3, 1, 1), (5, 1, 1), (6, 3, 1)]);
+
+        assert_eq!(passes(&header(1, 1, 8)).len(), 1);
+    }
+
+    #[test]
+    fn test_every_pixel_once() {
+        let header = header(13, 11, 8);
+        let mut seen = [0u8; 13 * 11];
+        for pass in passes(&header) {
+            for y in 0..pass.height() {
+                for x in 0..pass.width() {
+                    let (x, y) = pass.position(x, y);
+                    seen[(y * 13 + x) as usize] += 1;
+                }
+            }
+        }
+        assert!(seen.iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn test_extract_and_insert() {
+        for bit_depth in [1, 2, 4, 8, 16] {
+            let header = header(13, 11, bit_depth);
+            let bits = header.bits_per_pixel();
+            let pixels: Vec<u8> = (0..header.row_bytes() * 11)
+                .map(|i| (i * 73 % 256) as u8)
+                .collect();
+
+            let mut rebuilt = vec![0; pixels.len()];
+            for pass in passes(&header) {
+                let reduced = extract(&header, &pixels, &pass);
+                assert_eq!(
+                    reduced.len(),
+                    pass.row_bytes() * pass.height() as usize
+                );
+                insert(&header, &mut rebuilt, &pass, &reduced);
+            }
+
+            // Padding bits at the ends of rows are not pixels, so compare
+            // pixel by pixel.
+            for row in 0..11 {
+                let start = row * header.row_bytes();
+                for x in 0..13 {
+                    let (mut a, mut b) = ([0u8; 2], [0u8; 2]);
+                    copy_pixel(&pixels[start..], x, &mut a, 0, bits);
+                    copy_pixel(&rebuilt[start..], x, &mut b, 0, bits);
+                    assert_eq!(a, b, "depth {} at ({}, {})", bit_depth, x, row);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_copy_sub_byte_pixels() {
+        let from = [0b1011_0010];
+        let mut to = [0];
+        copy_pixel(&from, 1, &mut to, 3, 2);
+        assert_eq!(to, [0b0000_0011]);
+        copy_pixel(&from, 0, &mut to, 0, 1);
+        assert_eq!(to, [0b1000_0011]);
+        copy_pixel(&from, 1, &mut to, 7, 1);
+        assert_eq!(to, [0b1000_0010]);
+        copy_pixel(&from, 1, &mut to, 0, 4);
+        assert_eq!(to, [0b0010_0010]);
+    }
+}
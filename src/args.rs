@@ -1,4 +1,4 @@
-use crate::chunk_type::ChunkType;
+use pngme::{Channels, ChunkType, Compression};
 use std::{path::PathBuf, str::FromStr};
 use structopt::StructOpt;
 
@@ -17,10 +17,97 @@ pub struct EncodeArgs {
         help = "Chunk type (like \"TeSt\")"
     )]
     pub chunk_type: ChunkType,
-    #[structopt(help = "Hidden message.")]
-    pub message: String,
+    #[structopt(
+        required_unless_one = &["file", "stdin"],
+        help = "Hidden message (leave out with --file or --stdin)"
+    )]
+    pub message: Option<String>,
     #[structopt(parse(from_os_str), help = "Output file path (optional)")]
     pub output_file: Option<PathBuf>,
+    #[structopt(flatten)]
+    pub options: MessageArgs,
+    #[structopt(
+        long,
+        help = "Split the message into chunks of at most this many bytes"
+    )]
+    pub fragment_size: Option<usize>,
+    #[structopt(
+        long,
+        parse(try_from_str = ChunkType::from_str),
+        conflicts_with = "after",
+        help = "Insert before the first chunk of this type (like \"IDAT\")"
+    )]
+    pub before: Option<ChunkType>,
+    #[structopt(
+        long,
+        parse(try_from_str = ChunkType::from_str),
+        help = "Insert after the last chunk of this type (like \"IHDR\")"
+    )]
+    pub after: Option<ChunkType>,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Sign the message with the Ed25519 secret key in this file"
+    )]
+    pub sign_key: Option<PathBuf>,
+    #[structopt(
+        long,
+        requires = "sign-key",
+        help = "Make the signature cover the image data too"
+    )]
+    pub sign_image: bool,
+    #[structopt(
+        long,
+        default_value = "Comment",
+        help = "Keyword of the text chunk to hide in, with chunk type iTXt"
+    )]
+    pub keyword: String,
+}
+
+// Where the hidden bytes come from and how they are encrypted, shared by
+// `encode` and `embed`.
+#[derive(StructOpt)]
+pub struct MessageArgs {
+    #[structopt(
+        long,
+        parse(from_os_str),
+        conflicts_with = "stdin",
+        help = "Hide the contents of this file instead of a message"
+    )]
+    pub file: Option<PathBuf>,
+    #[structopt(long, help = "Hide the bytes read from standard input")]
+    pub stdin: bool,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        conflicts_with = "output-file",
+        help = "Output file path, for use with --file or --stdin"
+    )]
+    pub out: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "Media type to record for the hidden file (like \"text/csv\")"
+    )]
+    pub media_type: Option<String>,
+    #[structopt(
+        long,
+        parse(try_from_str = Compression::from_str),
+        possible_values = &["zlib", "zstd", "brotli"],
+        help = "Compress the message first"
+    )]
+    pub compress: Option<Compression>,
+    #[structopt(
+        long,
+        conflicts_with = "recipient",
+        help = "Encrypt the message with a password (or PNGME_PASSWORD)"
+    )]
+    pub password: bool,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Encrypt the message to the public key in this file"
+    )]
+    pub recipient: Option<PathBuf>,
 }
 
 #[derive(StructOpt)]
@@ -32,6 +119,96 @@ pub struct DecodeArgs {
         help = "Chunk type (like \"TeSt\")"
     )]
     pub chunk_type: ChunkType,
+    #[structopt(flatten)]
+    pub options: OpenArgs,
+    #[structopt(
+        long,
+        default_value = "Comment",
+        help = "Keyword of the text chunks to look in, with chunk type iTXt"
+    )]
+    pub keyword: String,
+}
+
+// How hidden bytes are decrypted and where they go, shared by `decode`
+// and `extract`.
+#[derive(StructOpt)]
+pub struct OpenArgs {
+    #[structopt(
+        long,
+        conflicts_with = "identity",
+        help = "Decrypt the message with a password (or PNGME_PASSWORD)"
+    )]
+    pub password: bool,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Decrypt the message with the secret key in this file"
+    )]
+    pub identity: Option<PathBuf>,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Write the hidden bytes to this file instead of printing them"
+    )]
+    pub output: Option<PathBuf>,
+    #[structopt(
+        long,
+        default_value = "268435456",
+        help = "Refuse messages that decompress to more bytes than this"
+    )]
+    pub max_size: usize,
+}
+
+#[derive(StructOpt)]
+pub struct EmbedArgs {
+    #[structopt(parse(from_os_str), help = "Input file path")]
+    pub file_path: PathBuf,
+    #[structopt(
+        required_unless_one = &["file", "stdin"],
+        help = "Hidden message (leave out with --file or --stdin)"
+    )]
+    pub message: Option<String>,
+    #[structopt(parse(from_os_str), help = "Output file path (optional)")]
+    pub output_file: Option<PathBuf>,
+    #[structopt(flatten)]
+    pub options: MessageArgs,
+    #[structopt(flatten)]
+    pub lsb: LsbArgs,
+}
+
+#[derive(StructOpt)]
+pub struct ExtractArgs {
+    #[structopt(parse(from_os_str), help = "Path to the .png file")]
+    pub file_path: PathBuf,
+    #[structopt(flatten)]
+    pub options: OpenArgs,
+    #[structopt(flatten)]
+    pub lsb: LsbArgs,
+}
+
+// Which pixel bits carry the message. `extract` needs the values `embed`
+// was given.
+#[derive(StructOpt)]
+pub struct LsbArgs {
+    #[structopt(
+        long,
+        default_value = "1",
+        help = "Low bits of each channel to use, from 1 to 8"
+    )]
+    pub bits: u8,
+    #[structopt(
+        long,
+        default_value = "color",
+        parse(try_from_str = Channels::from_str),
+        possible_values = &["color", "alpha", "all"],
+        help = "Channels to hide the message in"
+    )]
+    pub channels: Channels,
+    #[structopt(
+        long,
+        help = "Scatter the message using a passphrase (or PNGME_KEY)"
+    )]
+    pub key: bool,
 }
 
 #[derive(StructOpt)]
@@ -39,10 +216,19 @@ pub struct RemoveArgs {
     #[structopt(parse(from_os_str), help = "Path to the .png file.")]
     pub file_path: PathBuf,
     #[structopt(
-        parse(try_from_str = ChunkType::from_str), 
+        parse(try_from_str = ChunkType::from_str),
+        required_unless = "index",
         help = "Chunk type (like \"TeSt\")"
     )]
-    pub chunk_type: ChunkType,
+    pub chunk_type: Option<ChunkType>,
+    #[structopt(
+        long,
+        conflicts_with = "index",
+        help = "Remove every chunk of this type, not just the first"
+    )]
+    pub all: bool,
+    #[structopt(long, help = "Remove the chunk at this position (0 is IHDR)")]
+    pub index: Option<usize>,
 }
 
 #[derive(StructOpt)]
@@ -51,6 +237,109 @@ pub struct PrintArgs {
     pub file_path: PathBuf,
 }
 
+#[derive(StructOpt)]
+pub struct KeygenArgs {
+    #[structopt(
+        parse(from_os_str),
+        help = "Secret key file to create (the public key goes in <FILE>.pub)"
+    )]
+    pub output_file: PathBuf,
+    #[structopt(
+        long,
+        help = "Generate an Ed25519 signing key instead of an X25519 key"
+    )]
+    pub signing: bool,
+}
+
+#[derive(StructOpt)]
+pub struct VerifyArgs {
+    #[structopt(parse(from_os_str), help = "Path to the .png file")]
+    pub file_path: PathBuf,
+    #[structopt(
+        parse(try_from_str = ChunkType::from_str),
+        help = "Chunk type (like \"TeSt\")"
+    )]
+    pub chunk_type: ChunkType,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "File with the Ed25519 public key of the signer"
+    )]
+    pub pubkey: PathBuf,
+}
+
+#[derive(StructOpt)]
+pub struct CapacityArgs {
+    #[structopt(parse(from_os_str), help = "Path to the .png file")]
+    pub file_path: PathBuf,
+}
+
+#[derive(StructOpt)]
+pub struct ScanArgs {
+    #[structopt(parse(from_os_str), help = "Path to the .png file")]
+    pub file_path: PathBuf,
+}
+
+#[derive(StructOpt)]
+pub struct TextArgs {
+    #[structopt(subcommand)]
+    pub command: TextCommand,
+}
+
+#[derive(StructOpt)]
+pub enum TextCommand {
+    #[structopt(about = "Print every tEXt, zTXt and iTXt chunk")]
+    List {
+        #[structopt(parse(from_os_str), help = "Path to the .png file")]
+        file_path: PathBuf,
+    },
+    #[structopt(about = "Print the text stored under a keyword")]
+    Get {
+        #[structopt(parse(from_os_str), help = "Path to the .png file")]
+        file_path: PathBuf,
+        #[structopt(help = "Keyword (like \"Title\")")]
+        keyword: String,
+    },
+    #[structopt(
+        about = "Store text under a keyword, replacing what was there"
+    )]
+    Set {
+        #[structopt(parse(from_os_str), help = "Path to the .png file")]
+        file_path: PathBuf,
+        #[structopt(help = "Keyword (like \"Title\")")]
+        keyword: String,
+        #[structopt(help = "Text to store")]
+        text: String,
+        #[structopt(long, help = "Compress the text (zTXt, or iTXt)")]
+        compress: bool,
+        #[structopt(
+            long,
+            help = "Store the text as UTF-8 in an iTXt chunk even if it is \
+                    Latin-1"
+        )]
+        international: bool,
+        #[structopt(
+            long,
+            requires = "international",
+            help = "Language of the text (like \"en-GB\")"
+        )]
+        language: Option<String>,
+        #[structopt(
+            long,
+            requires = "international",
+            help = "The keyword translated into the language of the text"
+        )]
+        translated_keyword: Option<String>,
+    },
+    #[structopt(about = "Remove the text stored under a keyword")]
+    Delete {
+        #[structopt(parse(from_os_str), help = "Path to the .png file")]
+        file_path: PathBuf,
+        #[structopt(help = "Keyword (like \"Title\")")]
+        keyword: String,
+    },
+}
+
 #[derive(StructOpt)]
 pub enum Subcommand {
     #[structopt(about = "Hide a secret message in a .png file")]
@@ -61,4 +350,22 @@ pub enum Subcommand {
     Remove(RemoveArgs),
     #[structopt(about = "Print the whole .png file")]
     Print(PrintArgs),
+    #[structopt(about = "Generate a key pair for --recipient/--identity")]
+    Keygen(KeygenArgs),
+    #[structopt(about = "Check the signatures of secret messages")]
+    Verify(VerifyArgs),
+    #[structopt(about = "Hide a secret message in the pixels of a .png file")]
+    Embed(EmbedArgs),
+    #[structopt(
+        about = "Show the secret message in the pixels of a .png file"
+    )]
+    Extract(ExtractArgs),
+    #[structopt(about = "Show how much each mode can hide in a .png file")]
+    Capacity(CapacityArgs),
+    #[structopt(about = "Look for signs of hidden data in a .png file")]
+    Scan(ScanArgs),
+    #[structopt(
+        about = "List, read and change the text chunks of a .png file"
+    )]
+    Text(TextArgs),
 }
@@ -2,10 +2,12 @@ use crate::{chunk_type::ChunkType, Error};
 use crc::{Crc, CRC_32_ISO_HDLC};
 use std::{
     convert::TryFrom,
-    error,
     fmt::{self, Display, Formatter},
+    str,
 };
 
+pub(crate) static CRC_PNG: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
 pub struct Chunk {
     chunk_type: ChunkType,
     data: Vec<u8>,
@@ -17,6 +19,7 @@ impl Chunk {
     pub const CRC_SIZE: usize = 4;
     pub const METADATA_SIZE: usize =
         Chunk::LENGTH_SIZE + Chunk::TYPE_SIZE + Chunk::CRC_SIZE;
+    pub const MAX_LENGTH: u32 = (1 << 31) - 1;
 
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Chunk {
         Chunk { chunk_type, data }
@@ -31,30 +34,16 @@ impl Chunk {
         &self.data
     }
     pub fn crc(&self) -> u32 {
-        let bytes: Vec<u8> = self
-            .chunk_type
-            .bytes()
-            .iter()
-            .cloned()
-            .chain(self.data.iter().cloned())
-            .collect();
-
-        const CRC_PNG: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
-
-        CRC_PNG.checksum(&bytes)
+        self.to_ref().crc()
     }
     pub fn data_as_string(&self) -> Result<String, Error> {
         Ok(String::from_utf8(self.data.to_vec())?)
     }
     pub fn as_bytes(&self) -> Vec<u8> {
-        self.length()
-            .to_be_bytes()
-            .iter()
-            .cloned()
-            .chain(self.chunk_type.bytes().iter().cloned())
-            .chain(self.data.iter().cloned())
-            .chain(self.crc().to_be_bytes().iter().cloned())
-            .collect()
+        self.to_ref().as_bytes()
+    }
+    pub fn to_ref(&self) -> ChunkRef<'_> {
+        ChunkRef::new(self.chunk_type, &self.data)
     }
 }
 
@@ -62,83 +51,134 @@ impl TryFrom<&[u8]> for Chunk {
     type Error = Error;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Ok(ChunkRef::try_from(value)?.into())
+    }
+}
+
+impl Display for Chunk {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        Display::fmt(&self.to_ref(), f)
+    }
+}
+
+/// A chunk whose data is borrowed from the buffer it was parsed out of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkRef<'a> {
+    chunk_type: ChunkType,
+    data: &'a [u8],
+}
+
+impl<'a> ChunkRef<'a> {
+    pub fn new(chunk_type: ChunkType, data: &'a [u8]) -> ChunkRef<'a> {
+        ChunkRef { chunk_type, data }
+    }
+    pub fn length(&self) -> u32 {
+        self.data.len() as u32
+    }
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+    pub fn crc(&self) -> u32 {
+        let mut digest = CRC_PNG.digest();
+        digest.update(&self.chunk_type.bytes());
+        digest.update(self.data);
+        digest.finalize()
+    }
+    pub fn data_as_str(&self) -> Result<&'a str, str::Utf8Error> {
+        str::from_utf8(self.data)
+    }
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> =
+            Vec::with_capacity(Chunk::METADATA_SIZE + self.data.len());
+        bytes.extend_from_slice(&self.length().to_be_bytes());
+        bytes.extend_from_slice(&self.chunk_type.bytes());
+        bytes.extend_from_slice(self.data);
+        bytes.extend_from_slice(&self.crc().to_be_bytes());
+        bytes
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for ChunkRef<'a> {
+    type Error = Error;
+
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
         if value.len() < Chunk::METADATA_SIZE {
-            return Err(Box::new(ChunkError::TooSmall));
+            return Err(Error::ChunkTooSmall {
+                offset: 0,
+                length: value.len(),
+            });
         }
 
-        let length: u32 =
-            u32::from_be_bytes(value[..Chunk::LENGTH_SIZE].try_into()?);
-        let chunk_type_bytes: [u8; 4] = value
-            [Chunk::LENGTH_SIZE..Chunk::LENGTH_SIZE + Chunk::TYPE_SIZE]
-            .try_into()?;
-        let chunk_type: ChunkType = ChunkType::try_from(chunk_type_bytes)?;
-        let data: Vec<u8> = value[Chunk::LENGTH_SIZE + Chunk::TYPE_SIZE
-            ..value.len() - Chunk::CRC_SIZE]
-            .to_vec();
-        let crc: u32 = u32::from_be_bytes(
-            value[value.len() - Chunk::CRC_SIZE..].try_into()?,
+        let length: u32 = read_u32(&value[..Chunk::LENGTH_SIZE]);
+
+        if length > Chunk::MAX_LENGTH {
+            return Err(Error::ChunkTooLong { offset: 0, length });
+        }
+
+        let mut chunk_type_bytes: [u8; 4] = [0; 4];
+        chunk_type_bytes.copy_from_slice(
+            &value[Chunk::LENGTH_SIZE..Chunk::LENGTH_SIZE + Chunk::TYPE_SIZE],
         );
+        let chunk_type: ChunkType = ChunkType::try_from(chunk_type_bytes)
+            .map_err(|e| Error::InvalidChunkType {
+                offset: Chunk::LENGTH_SIZE,
+                source: Box::new(e),
+            })?;
+        let data: &[u8] = &value[Chunk::LENGTH_SIZE + Chunk::TYPE_SIZE
+            ..value.len() - Chunk::CRC_SIZE];
+        let crc: u32 = read_u32(&value[value.len() - Chunk::CRC_SIZE..]);
 
         if data.len() != length as usize {
-            return Err(Box::new(ChunkError::TooSmallData));
+            return Err(Error::DataLengthMismatch {
+                offset: 0,
+                chunk_type,
+                declared: length,
+                actual: data.len(),
+            });
         }
 
         let new: Self = Self { chunk_type, data };
 
-        if new.crc() != crc {
-            return Err(Box::new(ChunkError::InvalidCrc));
+        let computed: u32 = new.crc();
+
+        if computed != crc {
+            return Err(Error::InvalidCrc {
+                offset: 0,
+                chunk_type,
+                stored: crc,
+                computed,
+            });
         }
 
         Ok(new)
     }
 }
 
-impl Display for Chunk {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        let chunk_type: String =
-            match String::from_utf8(self.chunk_type.bytes().to_vec()) {
-                Ok(val) => val,
-                Err(_) => String::from("No hidden message!"),
-            };
-        let chunk: String = match self.data_as_string() {
-            Ok(val) => {
-                if !val.is_empty() {
-                    val
-                } else {
-                    String::from("No hidden message!")
-                }
-            }
-            Err(_) => String::from("No hidden message!"),
-        };
-        write!(f, "{}", format_args!("{}: {}", chunk_type, chunk))
+impl From<ChunkRef<'_>> for Chunk {
+    fn from(value: ChunkRef<'_>) -> Self {
+        Chunk::new(value.chunk_type, value.data.to_vec())
     }
 }
 
-#[derive(Debug)]
-pub enum ChunkError {
-    TooSmall,
-    TooSmallData,
-    InvalidCrc,
-}
-
-impl error::Error for ChunkError {}
-
-impl Display for ChunkError {
+impl Display for ChunkRef<'_> {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        match self {
-            ChunkError::TooSmall => {
-                write!(f, "Invalid length of chunk!")
-            }
-            ChunkError::TooSmallData => {
-                write!(f, "Invalid data length!")
-            }
-            ChunkError::InvalidCrc => {
-                write!(f, "Invalid crc!")
-            }
-        }
+        let chunk: &str = match self.data_as_str() {
+            Ok(val) if !val.is_empty() => val,
+            _ => "No hidden message!",
+        };
+        write!(f, "{}: {}", self.chunk_type, chunk)
     }
 }
 
+pub(crate) fn read_u32(bytes: &[u8]) -> u32 {
+    let mut buf: [u8; 4] = [0; 4];
+    buf.copy_from_slice(&bytes[..4]);
+    u32::from_be_bytes(buf)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -253,6 +293,69 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    fn test_invalid_crc_error() {
+        let mut chunk_data: Vec<u8> = testing_chunk().as_bytes();
+        let last: usize = chunk_data.len() - 1;
+        chunk_data[last] ^= 1;
+
+        match Chunk::try_from(chunk_data.as_ref()) {
+            Err(Error::InvalidCrc {
+                offset,
+                chunk_type,
+                stored,
+                computed,
+            }) => {
+                assert_eq!(offset, 0);
+                assert_eq!(chunk_type.to_string(), "RuSt");
+                assert_eq!(stored, 2882656335);
+                assert_eq!(computed, 2882656334);
+            }
+            _ => panic!("expected a CRC error"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_chunk_type_error() {
+        let mut chunk_data: Vec<u8> = testing_chunk().as_bytes();
+        chunk_data[Chunk::LENGTH_SIZE] = b'1';
+
+        let err = Chunk::try_from(chunk_data.as_ref()).err().unwrap();
+
+        assert_eq!(err.offset(), Some(Chunk::LENGTH_SIZE));
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
+    #[test]
+    fn test_chunk_ref_borrows_data() {
+        let chunk_data: Vec<u8> = testing_chunk().as_bytes();
+        let chunk = ChunkRef::try_from(chunk_data.as_ref()).unwrap();
+
+        assert_eq!(chunk.length(), 42);
+        assert_eq!(chunk.crc(), 2882656334);
+        assert_eq!(
+            chunk.data().as_ptr(),
+            chunk_data[Chunk::LENGTH_SIZE + Chunk::TYPE_SIZE..].as_ptr()
+        );
+        assert_eq!(
+            chunk.data_as_str().unwrap(),
+            "This is where your secret message will be!"
+        );
+        assert_eq!(chunk.as_bytes(), chunk_data);
+        assert_eq!(chunk.to_string(), testing_chunk().to_string());
+    }
+
+    #[test]
+    fn test_chunk_too_long() {
+        let mut chunk_data: Vec<u8> = testing_chunk().as_bytes();
+        chunk_data[..Chunk::LENGTH_SIZE]
+            .copy_from_slice(&u32::MAX.to_be_bytes());
+
+        let chunk = Chunk::try_from(chunk_data.as_ref());
+
+        assert!(matches!(chunk, Err(Error::ChunkTooLong { .. })));
+    }
+
     #[test]
     pub fn test_chunk_trait_impls() {
         let data_length: u32 = 42;
@@ -1,12 +1,11 @@
 use crate::Error;
 use std::{
     convert::TryFrom,
-    error,
     fmt::{self, Display, Formatter},
     str::{self, FromStr},
 };
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub struct ChunkType {
     value: [u8; 4],
 }
@@ -36,13 +35,7 @@ impl TryFrom<[u8; 4]> for ChunkType {
     type Error = Error;
 
     fn try_from(value: [u8; 4]) -> Result<Self, Self::Error> {
-        let valid_chars: bool = value
-            .iter()
-            .all(|&b| b.is_ascii_alphabetic());
-
-        if !valid_chars {
-            return Err(Box::new(ChunkTypeError::InvalidChar));
-        }
+        check_chars(&value)?;
 
         Ok(ChunkType { value })
     }
@@ -53,20 +46,17 @@ impl FromStr for ChunkType {
 
     fn from_str(value: &str) -> Result<Self, Self::Err> {
         if value.len() != 4 {
-            return Err(Box::new(ChunkTypeError::ByteLength));
+            return Err(Error::ChunkTypeLength {
+                length: value.len(),
+            });
         }
 
-        let valid_chars: bool = value
-            .bytes()
-            .all(|b| b.is_ascii_alphabetic());
+        check_chars(value.as_bytes())?;
 
-        if !valid_chars {
-            return Err(Box::new(ChunkTypeError::InvalidChar));
-        }
+        let mut bytes: [u8; 4] = [0; 4];
+        bytes.copy_from_slice(value.as_bytes());
 
-        Ok(ChunkType {
-            value: value.as_bytes().try_into()?,
-        })
+        Ok(ChunkType { value: bytes })
     }
 }
 
@@ -76,24 +66,13 @@ impl Display for ChunkType {
     }
 }
 
-#[derive(Debug)]
-pub enum ChunkTypeError {
-    ByteLength,
-    InvalidChar,
-}
-
-impl error::Error for ChunkTypeError {}
-
-impl Display for ChunkTypeError {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        match self {
-            ChunkTypeError::ByteLength => {
-                write!(f, "Invalid length of chunk type!")
-            }
-            ChunkTypeError::InvalidChar => {
-                write!(f, "Invalid character in chunk type!")
-            }
-        }
+fn check_chars(bytes: &[u8]) -> Result<(), Error> {
+    match bytes.iter().position(|b| !b.is_ascii_alphabetic()) {
+        Some(index) => Err(Error::ChunkTypeByte {
+            byte: bytes[index],
+            index,
+        }),
+        None => Ok(()),
     }
 }
 
@@ -181,6 +160,21 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    pub fn test_chunk_type_errors() {
+        assert!(matches!(
+            ChunkType::from_str("Ru1t"),
+            Err(Error::ChunkTypeByte {
+                byte: b'1',
+                index: 2
+            })
+        ));
+        assert!(matches!(
+            ChunkType::from_str("RuStY"),
+            Err(Error::ChunkTypeLength { length: 5 })
+        ));
+    }
+
     #[test]
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
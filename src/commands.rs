@@ -1,11 +1,36 @@
-use crate::{
-    args::{DecodeArgs, EncodeArgs, PrintArgs, RemoveArgs, Subcommand},
-    chunk::Chunk,
-    png::Png,
-    Result,
+use crate::args::{
+    CapacityArgs, DecodeArgs, EmbedArgs, EncodeArgs, ExtractArgs, KeygenArgs,
+    LsbArgs, MessageArgs, OpenArgs, PrintArgs, RemoveArgs, ScanArgs,
+    Subcommand, TextArgs, TextCommand, VerifyArgs,
 };
+use pngme::{
+    envelope,
+    fragment::{self, Fragment, Reassembler},
+    lsb,
+    payload::{self, Payload},
+    scan::{self, Report},
+    signature::{self, ImageDigest, ImageHasher},
+    Channels, Chunk, ChunkReader, ChunkRef, ChunkType, ChunkWriter, EmbedMode,
+    Error, Identity, ImageHeader, LsbOptions, Png, Recipient, Result,
+    Signature, SignatureStatus, SigningKey, TextChunk, VerifyingKey,
+};
+
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process,
+};
+use zeroize::Zeroizing;
 
-use std::{convert::TryFrom, fs, path::PathBuf};
+const PASSWORD_VAR: &str = "PNGME_PASSWORD";
+const KEY_VAR: &str = "PNGME_KEY";
+// How many names `create_temp` tries before giving up.
+const MAX_TEMP_ATTEMPTS: u32 = 100;
+
+type FileReader = ChunkReader<BufReader<File>>;
+type FileWriter = ChunkWriter<BufWriter<File>>;
 
 pub fn run(subcmd: Subcommand) -> Result<()> {
     match subcmd {
@@ -13,55 +38,883 @@ pub fn run(subcmd: Subcommand) -> Result<()> {
         Subcommand::Decode(args) => decode(args),
         Subcommand::Remove(args) => remove(args),
         Subcommand::Print(args) => print(args),
+        Subcommand::Keygen(args) => keygen(args),
+        Subcommand::Verify(args) => verify(args),
+        Subcommand::Embed(args) => embed(args),
+        Subcommand::Extract(args) => extract(args),
+        Subcommand::Capacity(args) => capacity(args),
+        Subcommand::Scan(args) => scan(args),
+        Subcommand::Text(args) => text(args),
     }
 }
 
 fn encode(args: EncodeArgs) -> Result<()> {
-    let input_bytes: Vec<u8> = fs::read(&args.file_path)?;
-    let output: PathBuf = match args.output_file {
-        Some(val) => val,
-        None => args.file_path,
+    let (message, output): (Vec<u8>, PathBuf) = read_message(
+        &args.file_path,
+        &args.message,
+        &args.output_file,
+        &args.options,
+    )?;
+    let file: File = File::open(&args.file_path)?;
+    let mut reader: FileReader =
+        ChunkReader::new_validated(BufReader::new(file))?;
+    let payload: Vec<u8> = seal(message, &args.options)?;
+    // Payloads too big for one chunk are split even without
+    // --fragment-size.
+    let pieces: Vec<Vec<u8>> = match args.fragment_size {
+        None if payload.len() <= Chunk::MAX_LENGTH as usize => vec![payload],
+        size => fragment::split(&payload, size.unwrap_or(Fragment::MAX_SIZE))?
+            .iter()
+            .map(Fragment::as_bytes)
+            .collect(),
     };
-    let mut img: Png = Png::try_from(input_bytes.as_slice())?;
-    let chunk: Chunk =
-        Chunk::new(args.chunk_type, args.message.as_bytes().to_vec());
-    img.append_chunk(chunk);
-    fs::write(output, img.as_bytes())?;
-    Ok(())
+    let signer: Option<(SigningKey, Option<ImageDigest>)> = match &args.sign_key
+    {
+        Some(path) => {
+            let file: Zeroizing<String> =
+                Zeroizing::new(fs::read_to_string(path)?);
+            let digest: Option<ImageDigest> = if args.sign_image {
+                Some(read_image_digest(&args.file_path)?)
+            } else {
+                None
+            };
+            Some((file.parse()?, digest))
+        }
+        None => None,
+    };
+    let mut chunks: Vec<Chunk> = Vec::new();
+    for piece in pieces {
+        // In an iTXt chunk the message passes for an ordinary comment.
+        let chunk: Chunk = if is_itxt(args.chunk_type) {
+            TextChunk::hide(&args.keyword, &piece).to_chunk()?
+        } else {
+            Chunk::new(args.chunk_type, piece)
+        };
+        if let Some((key, digest)) = &signer {
+            let signature: Signature = Signature::sign(key, &chunk, *digest);
+            chunks.push(chunk);
+            chunks.push(signature.to_chunk());
+        } else {
+            chunks.push(chunk);
+        }
+    }
+    let iend: ChunkType = ChunkType::try_from(*b"IEND")?;
+    let before: Option<ChunkType> = match (args.before, args.after) {
+        (None, None) => Some(iend),
+        (before, _) => before,
+    };
+    write_atomically(&output, true, |writer| {
+        let mut inserted: bool = false;
+        let mut after_run: bool = false;
+        while let Some(header) = reader.next_header()? {
+            let at_before: bool = Some(header.chunk_type) == before;
+            let is_after: bool = Some(header.chunk_type) == args.after;
+            // The chunk goes after the end of the run of `--after` chunks,
+            // which keeps consecutive IDAT chunks together.
+            if !inserted && (at_before || (after_run && !is_after)) {
+                chunks.iter().try_for_each(|c| writer.write_chunk(c))?;
+                inserted = true;
+            }
+            after_run = is_after;
+            writer.copy_chunk(&mut reader, &header)?;
+        }
+        if !inserted && after_run {
+            chunks.iter().try_for_each(|c| writer.write_chunk(c))?;
+            inserted = true;
+        }
+        if !inserted {
+            let anchor: ChunkType = before.or(args.after).unwrap_or(iend);
+            return Err(Error::ChunkNotFound {
+                chunk_type: anchor.to_string(),
+            });
+        }
+        Ok(())
+    })
 }
 
 fn decode(args: DecodeArgs) -> Result<()> {
-    let input_bytes: Vec<u8> = fs::read(args.file_path)?;
-    let img: Png = Png::try_from(input_bytes.as_slice())?;
-    let chunks: Vec<&Chunk> = img.chunk_by_type(&args.chunk_type.to_string());
-    if chunks.len() == 0 {
+    let mut reader: FileReader = open_reader(&args.file_path)?;
+    let keys: Keys = Keys::load(&args.options)?;
+    let mut messages: Vec<Vec<u8>> = Vec::new();
+    let mut reassembler: Reassembler = Reassembler::new();
+    let mut data: Vec<u8> = Vec::new();
+    while let Some(header) = reader.next_header()? {
+        if header.chunk_type != args.chunk_type {
+            continue;
+        }
+        reader.read_chunk_data_into(&mut data)?;
+        let hidden: Vec<u8> = if is_itxt(header.chunk_type) {
+            let chunk: Chunk = Chunk::new(header.chunk_type, data.clone());
+            if !TextChunk::has_keyword(&chunk, &args.keyword) {
+                continue;
+            }
+            // Text chunks that hold real or broken text are not messages.
+            match TextChunk::try_from(&chunk).ok().and_then(|t| t.reveal()) {
+                Some(bytes) => bytes,
+                None => continue,
+            }
+        } else {
+            data.clone()
+        };
+        // Data that only looks like a fragment is a message of its own, as
+        // in `print_chunk`.
+        if fragment::is_fragment(&hidden) {
+            if let Ok(fragment) = Fragment::try_from(&hidden[..]) {
+                reassembler.push(fragment)?;
+                continue;
+            }
+        }
+        messages.push(hidden);
+    }
+    // Split payloads come after whole ones, since their fragments may be
+    // spread anywhere in the file.
+    messages.extend(reassembler.finish()?);
+
+    if messages.is_empty() {
         println!("No chunks found by this type.");
-        return Ok(());
     }
-    for chunk in chunks {
-        println!("{}", chunk);
+    if args.options.output.is_some() && messages.len() > 1 {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "found {} messages, but --output can only hold one",
+                messages.len()
+            ),
+        )));
+    }
+    for message in messages {
+        let plaintext: Zeroizing<Vec<u8>> = keys.open(message)?;
+        if let Some(path) = &args.options.output {
+            return write_message(path, &plaintext, args.options.max_size);
+        }
+        print_message(args.chunk_type, &plaintext, args.options.max_size)?;
     }
     Ok(())
 }
 
 fn remove(args: RemoveArgs) -> Result<()> {
-    let input_bytes = fs::read(&args.file_path)?;
-    let mut img = Png::try_from(input_bytes.as_slice())?;
-    match img.remove_chunk(&args.chunk_type.to_string()) {
-        Ok(chunk) => {
-            fs::write(&args.file_path, img.as_bytes())?;
-            println!("Removed chunk: {}", chunk);
+    let mut reader: FileReader = open_reader(&args.file_path)?;
+    let mut removed: Vec<Chunk> = Vec::new();
+    write_atomically(&args.file_path, true, |writer| {
+        let mut index: usize = 0;
+        while let Some(header) = reader.next_header()? {
+            // A type given together with an index guards against
+            // removing the wrong chunk.
+            let matches: bool = args.index.is_none_or(|i| i == index)
+                && args.chunk_type.is_none_or(|t| t == header.chunk_type);
+            if matches && (args.all || removed.is_empty()) {
+                let data: Vec<u8> = reader.read_chunk_data()?;
+                removed.push(Chunk::new(header.chunk_type, data));
+            } else {
+                writer.copy_chunk(&mut reader, &header)?;
+            }
+            index += 1;
+        }
+        match (removed.is_empty(), args.index, args.chunk_type) {
+            (false, _, _) => Ok(()),
+            (true, Some(wanted), _) if wanted >= index => {
+                Err(Error::IndexOutOfRange {
+                    index: wanted,
+                    length: index,
+                })
+            }
+            (true, _, chunk_type) => Err(Error::ChunkNotFound {
+                chunk_type: chunk_type
+                    .map(|t| t.to_string())
+                    .unwrap_or_default(),
+            }),
         }
-        Err(e) => println!("Error: {}", e),
+    })?;
+    for chunk in removed {
+        println!("Removed chunk: {}", chunk);
     }
     Ok(())
 }
 
 fn print(args: PrintArgs) -> Result<()> {
-    let input_bytes: Vec<u8> = fs::read(args.file_path)?;
-    let img: Png = Png::try_from(input_bytes.as_slice())?;
-    for chunk in img.chunks().iter() {
-        println!("{}", chunk);
+    let mut reader: FileReader = open_reader(&args.file_path)?;
+    let mut data: Vec<u8> = Vec::new();
+    while let Some(header) = reader.next_header()? {
+        reader.read_chunk_data_into(&mut data)?;
+        if header.chunk_type.bytes() == ImageHeader::CHUNK_TYPE {
+            // A broken header is worth seeing rather than stopping at.
+            match ImageHeader::try_from(&data[..]) {
+                Ok(info) => println!("{}: {}", header.chunk_type, info),
+                Err(e) => println!(
+                    "{}: {} (Error: {})",
+                    header.chunk_type,
+                    hex::encode(&data),
+                    e
+                ),
+            }
+        } else {
+            print_chunk(header.chunk_type, &data, Payload::DEFAULT_MAX_SIZE);
+        }
+    }
+    Ok(())
+}
+
+fn keygen(args: KeygenArgs) -> Result<()> {
+    let (secret, public_key): (Zeroizing<String>, String) = if args.signing {
+        let key: SigningKey = SigningKey::generate();
+        (
+            Zeroizing::new(key.to_string()),
+            key.verifying_key().to_string(),
+        )
+    } else {
+        let identity: Identity = Identity::generate();
+        (
+            Zeroizing::new(identity.to_string()),
+            identity.recipient().to_string(),
+        )
+    };
+    let mut public: PathBuf = args.output_file.clone();
+    public.as_mut_os_string().push(".pub");
+
+    let mut options: OpenOptions = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut secret_file: File = options.open(&args.output_file)?;
+    // Both files are created before either is written, so an existing
+    // public key file leaves no secret key behind.
+    let mut public_file: File = match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&public)
+    {
+        Ok(file) => file,
+        Err(e) => {
+            drop(secret_file);
+            let _ = fs::remove_file(&args.output_file);
+            return Err(e.into());
+        }
+    };
+    writeln!(secret_file, "# public key: {}", public_key)?;
+    writeln!(secret_file, "{}", *secret)?;
+    writeln!(public_file, "{}", public_key)?;
+
+    println!("Public key: {}", public_key);
+    Ok(())
+}
+
+fn verify(args: VerifyArgs) -> Result<()> {
+    let key: VerifyingKey = fs::read_to_string(&args.pubkey)?.parse()?;
+    let png: Png =
+        Png::from_reader(BufReader::new(File::open(&args.file_path)?))?;
+    let statuses: Vec<SignatureStatus> =
+        signature::verify(&png, &args.chunk_type.to_string(), &key);
+
+    if statuses.is_empty() {
+        println!("No chunks found by this type.");
+    }
+    for (index, status) in statuses.iter().enumerate() {
+        println!("{} #{}: {}", args.chunk_type, index + 1, status);
+    }
+    Ok(())
+}
+
+fn embed(args: EmbedArgs) -> Result<()> {
+    let (message, output): (Vec<u8>, PathBuf) = read_message(
+        &args.file_path,
+        &args.message,
+        &args.output_file,
+        &args.options,
+    )?;
+    let options: LsbOptions = lsb_options(&args.lsb, true)?;
+    let mut png: Png = Png::from_reader_validated(BufReader::new(File::open(
+        &args.file_path,
+    )?))?;
+    lsb::embed(&mut png, &seal(message, &args.options)?, &options)?;
+
+    write_atomically(&output, true, |writer| {
+        png.chunks().iter().try_for_each(|c| writer.write_chunk(c))
+    })
+}
+
+fn extract(args: ExtractArgs) -> Result<()> {
+    let options: LsbOptions = lsb_options(&args.lsb, false)?;
+    let keys: Keys = Keys::load(&args.options)?;
+    let png: Png =
+        Png::from_reader(BufReader::new(File::open(&args.file_path)?))?;
+    let plaintext: Zeroizing<Vec<u8>> =
+        keys.open(lsb::extract(&png, &options)?)?;
+
+    match &args.options.output {
+        Some(path) => write_message(path, &plaintext, args.options.max_size),
+        None => {
+            println!("{}", describe(&plaintext, args.options.max_size)?);
+            Ok(())
+        }
+    }
+}
+
+fn capacity(args: CapacityArgs) -> Result<()> {
+    let png: Png =
+        Png::from_reader(BufReader::new(File::open(&args.file_path)?))?;
+    println!("{}", png.header_info()?);
+
+    let mut modes: Vec<EmbedMode> = vec![EmbedMode::Chunk];
+    for bits in [1, 2, 4] {
+        modes.push(EmbedMode::Lsb(LsbOptions::new(bits, Channels::Color)?));
+    }
+    modes.push(EmbedMode::Lsb(LsbOptions::new(1, Channels::Alpha)?));
+
+    for mode in modes {
+        match png.capacity(&mode) {
+            Ok(bytes) => println!("{}: {} bytes", mode, bytes),
+            // One mode not fitting the image says nothing about the others.
+            Err(
+                e @ (Error::UnsupportedImage { .. }
+                | Error::InvalidLsbOptions { .. }),
+            ) => println!("{}: {}", mode, e),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn scan(args: ScanArgs) -> Result<()> {
+    let report: Report = scan::scan(&fs::read(&args.file_path)?)?;
+    println!("{}", report);
+    Ok(())
+}
+
+fn text(args: TextArgs) -> Result<()> {
+    match args.command {
+        TextCommand::List { file_path } => {
+            let png: Png = load_png(&file_path)?;
+            let texts = png
+                .chunks()
+                .iter()
+                .filter(|c| TextChunk::is_text_chunk(c.chunk_type()));
+            // A broken chunk is reported without hiding the others.
+            for chunk in texts {
+                match TextChunk::try_from(chunk) {
+                    Ok(text) => println!("{}: {}", text.chunk_type(), text),
+                    Err(e) => {
+                        println!("{}: (Error: {})", chunk.chunk_type(), e)
+                    }
+                }
+            }
+            Ok(())
+        }
+        TextCommand::Get { file_path, keyword } => {
+            let png: Png = load_png(&file_path)?;
+            let texts: Vec<&Chunk> = png
+                .chunks()
+                .iter()
+                .filter(|c| TextChunk::has_keyword(c, &keyword))
+                .collect();
+            if texts.is_empty() {
+                return Err(Error::KeywordNotFound { keyword });
+            }
+            for chunk in texts {
+                match TextChunk::try_from(chunk) {
+                    Ok(text) => println!("{}", text.text()),
+                    Err(e) => {
+                        println!("{}: (Error: {})", chunk.chunk_type(), e)
+                    }
+                }
+            }
+            Ok(())
+        }
+        TextCommand::Set {
+            file_path,
+            keyword,
+            text,
+            compress,
+            international,
+            language,
+            translated_keyword,
+        } => {
+            let text: TextChunk = if international {
+                TextChunk::International {
+                    keyword,
+                    compressed: compress,
+                    language: language.unwrap_or_default(),
+                    translated_keyword: translated_keyword.unwrap_or_default(),
+                    text,
+                }
+            } else {
+                TextChunk::new(&keyword, &text, compress)
+            };
+            let mut png: Png = load_png(&file_path)?;
+            png.set_text(&text)?;
+            write_atomically(&file_path, true, |writer| {
+                png.chunks().iter().try_for_each(|c| writer.write_chunk(c))
+            })
+        }
+        TextCommand::Delete { file_path, keyword } => {
+            let mut png: Png = load_png(&file_path)?;
+            let removed: Vec<Chunk> = png.remove_text(&keyword)?;
+            write_atomically(&file_path, true, |writer| {
+                png.chunks().iter().try_for_each(|c| writer.write_chunk(c))
+            })?;
+            for chunk in removed {
+                match TextChunk::try_from(&chunk) {
+                    Ok(text) => {
+                        println!("Removed {}: {}", text.chunk_type(), text)
+                    }
+                    // Broken text can still be deleted.
+                    Err(_) => println!("Removed {}", chunk.chunk_type()),
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Prints a chunk as text, or describes it if it holds a file or a piece
+/// of one. A payload made from a compressed message prints as the message.
+/// Data that only looks like a payload or a fragment is printed as it is,
+/// so one odd chunk does not stop a listing.
+fn print_chunk(chunk_type: ChunkType, data: &[u8], limit: usize) {
+    if payload::is_payload(data) {
+        if let Ok(text) = describe(data, limit) {
+            println!("{}: {}", chunk_type, text);
+            return;
+        }
+    } else if fragment::is_fragment(data) {
+        if let Ok(fragment) = Fragment::try_from(data) {
+            println!("{}: {}", chunk_type, fragment);
+            return;
+        }
+    }
+    println!("{}", ChunkRef::new(chunk_type, data));
+}
+
+/// Prints a decoded message like [`print_chunk`], except that a payload
+/// that fails to parse is an error: the user asked for this one.
+fn print_message(
+    chunk_type: ChunkType,
+    data: &[u8],
+    limit: usize,
+) -> Result<()> {
+    if payload::is_payload(data) {
+        println!("{}: {}", chunk_type, describe(data, limit)?);
+    } else {
+        println!("{}", ChunkRef::new(chunk_type, data));
+    }
+    Ok(())
+}
+
+/// Returns hidden bytes as text: the message of a message payload, a
+/// description of a file payload, or the bytes themselves.
+fn describe(data: &[u8], limit: usize) -> Result<String> {
+    if !payload::is_payload(data) {
+        return Ok(String::from_utf8_lossy(data).into_owned());
     }
+    let payload: Payload = Payload::parse(data, limit)?;
+    Ok(match std::str::from_utf8(payload.data()) {
+        Ok(text) if payload.is_message() => text.to_string(),
+        _ => payload.to_string(),
+    })
+}
+
+/// Writes hidden bytes to `path`, unwrapping them first if they hold a
+/// file payload.
+fn write_message(path: &Path, plaintext: &[u8], limit: usize) -> Result<()> {
+    let contents: &[u8] = if payload::is_payload(plaintext) {
+        &Payload::parse(plaintext, limit)?.into_data()
+    } else {
+        plaintext
+    };
+    fs::write(path, contents)?;
+    println!("Wrote {} bytes to {}", contents.len(), path.display());
     Ok(())
 }
+
+/// Gathers the bytes to hide and the path to write the result to.
+///
+/// A plain message is stored as is. `--file`, `--stdin`, `--media-type` or
+/// `--compress` store a [`Payload`] instead. With `--file` or `--stdin`
+/// there is no message, and the output file is named with `--out`.
+fn read_message(
+    file_path: &Path,
+    message: &Option<String>,
+    output_file: &Option<PathBuf>,
+    args: &MessageArgs,
+) -> Result<(Vec<u8>, PathBuf)> {
+    let is_file: bool = args.file.is_some()
+        || args.stdin
+        || args.media_type.is_some()
+        || args.compress.is_some();
+    if message.is_some() && (args.file.is_some() || args.stdin) {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "a message cannot be given with --file or --stdin",
+        )));
+    }
+    let output: PathBuf = args
+        .out
+        .clone()
+        .or_else(|| output_file.clone())
+        .unwrap_or_else(|| file_path.to_path_buf());
+
+    if !is_file {
+        let message: &str = message.as_deref().unwrap_or_default();
+        return Ok((message.as_bytes().to_vec(), output));
+    }
+
+    let mut payload: Payload = if let Some(path) = &args.file {
+        Payload::from_path(path, fs::read(path)?)
+    } else if args.stdin {
+        let mut data: Vec<u8> = Vec::new();
+        io::stdin().read_to_end(&mut data)?;
+        Payload::new(data)
+    } else {
+        let message: &str = message.as_deref().unwrap_or_default();
+        Payload::message(message)
+    };
+    if let Some(media_type) = &args.media_type {
+        payload = payload.with_media_type(media_type)?;
+    }
+    if let Some(compression) = args.compress {
+        payload = payload.with_compression(compression);
+    }
+    Ok((payload.to_bytes()?, output))
+}
+
+/// Encrypts `message` as `--password` or `--recipient` ask, if they do.
+fn seal(message: Vec<u8>, args: &MessageArgs) -> Result<Vec<u8>> {
+    if args.password {
+        let password: Zeroizing<String> = read_password(true)?;
+        envelope::seal_with_password(&message, password.as_bytes())
+    } else if let Some(path) = &args.recipient {
+        let recipient: Recipient = fs::read_to_string(path)?.parse()?;
+        envelope::seal_for_recipient(&message, &recipient)
+    } else {
+        Ok(message)
+    }
+}
+
+/// The secrets `--password` or `--identity` supply, read once up front so
+/// several messages can be opened with one prompt.
+struct Keys {
+    password: Option<Zeroizing<String>>,
+    identity: Option<Identity>,
+}
+
+impl Keys {
+    fn load(args: &OpenArgs) -> Result<Keys> {
+        let password: Option<Zeroizing<String>> = if args.password {
+            Some(read_password(false)?)
+        } else {
+            None
+        };
+        let identity: Option<Identity> = match &args.identity {
+            Some(path) => {
+                let file: Zeroizing<String> =
+                    Zeroizing::new(fs::read_to_string(path)?);
+                Some(file.parse()?)
+            }
+            None => None,
+        };
+        Ok(Keys { password, identity })
+    }
+
+    /// Decrypts `message`, or passes it through if no secret was given.
+    fn open(&self, message: Vec<u8>) -> Result<Zeroizing<Vec<u8>>> {
+        Ok(Zeroizing::new(match (&self.password, &self.identity) {
+            (Some(password), _) => {
+                envelope::open_with_password(&message, password.as_bytes())?
+            }
+            (_, Some(identity)) => {
+                envelope::open_with_identity(&message, identity)?
+            }
+            (None, None) => message,
+        }))
+    }
+}
+
+/// Builds the embedding options, asking for the `--key` passphrase if it
+/// is needed, twice when `confirm` is set.
+fn lsb_options(args: &LsbArgs, confirm: bool) -> Result<LsbOptions> {
+    let options: LsbOptions = LsbOptions::new(args.bits, args.channels)?;
+    if !args.key {
+        return Ok(options);
+    }
+    let passphrase: Zeroizing<String> = read_passphrase(confirm)?;
+    Ok(options.with_key(passphrase.as_bytes()))
+}
+
+/// Digests the IDAT data of the image at `path` for a signature.
+fn read_image_digest(path: &Path) -> Result<ImageDigest> {
+    let mut reader: FileReader = open_reader(path)?;
+    let mut hasher: ImageHasher = ImageHasher::new();
+    let mut buf: [u8; 8 * 1024] = [0; 8 * 1024];
+    while let Some(header) = reader.next_header()? {
+        if header.chunk_type.bytes() != *b"IDAT" {
+            continue;
+        }
+        loop {
+            let read: usize = reader.read_data(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+        }
+    }
+    Ok(hasher.finish())
+}
+
+/// Takes the password from `PNGME_PASSWORD` if set, otherwise prompts for
+/// it on the terminal, asking twice when `confirm` is set.
+fn read_password(confirm: bool) -> Result<Zeroizing<String>> {
+    read_secret(PASSWORD_VAR, "password", confirm)
+}
+
+/// Like [`read_password`], for the `--key` passphrase and `PNGME_KEY`.
+fn read_passphrase(confirm: bool) -> Result<Zeroizing<String>> {
+    read_secret(KEY_VAR, "passphrase", confirm)
+}
+
+fn read_secret(
+    var: &str,
+    name: &str,
+    confirm: bool,
+) -> Result<Zeroizing<String>> {
+    if let Ok(secret) = env::var(var) {
+        return Ok(Zeroizing::new(secret));
+    }
+
+    let prompt: String =
+        format!("{}{}: ", name[..1].to_uppercase(), &name[1..]);
+    let secret: Zeroizing<String> =
+        Zeroizing::new(rpassword::prompt_password(prompt)?);
+    if confirm {
+        let again: Zeroizing<String> = Zeroizing::new(
+            rpassword::prompt_password(format!("Confirm {}: ", name))?,
+        );
+        if *again != *secret {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}s do not match", name),
+            )));
+        }
+    }
+    Ok(secret)
+}
+
+fn load_png(path: &Path) -> Result<Png> {
+    Png::from_reader(BufReader::new(File::open(path)?))
+}
+
+fn is_itxt(chunk_type: ChunkType) -> bool {
+    chunk_type.bytes() == *b"iTXt"
+}
+
+fn open_reader(path: &Path) -> Result<FileReader> {
+    ChunkReader::new(BufReader::new(File::open(path)?))
+}
+
+/// Streams a new image into a temporary file next to `output` and moves it
+/// into place once `write` succeeds, so `output` may also be the input.
+/// The new file keeps the permissions of the one it replaces. With
+/// `validate` set, a result that breaks the chunk ordering rules is
+/// refused.
+fn write_atomically(
+    output: &Path,
+    validate: bool,
+    write: impl FnOnce(&mut FileWriter) -> Result<()>,
+) -> Result<()> {
+    let (temp, file): (PathBuf, File) = create_temp(output)?;
+
+    let result: Result<()> = copy_permissions(output, &file)
+        .and_then(|()| {
+            let file: BufWriter<File> = BufWriter::new(file);
+            if validate {
+                ChunkWriter::new_validated(file)
+            } else {
+                ChunkWriter::new(file)
+            }
+        })
+        .and_then(|mut writer| {
+            write(&mut writer)?;
+            writer.finish()?;
+            Ok(())
+        });
+
+    match result {
+        Ok(()) => Ok(fs::rename(&temp, output)?),
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+/// Creates a temporary file in the directory of `output` under a name no
+/// other file has, so neither an unrelated file nor a concurrent run on
+/// the same image is overwritten.
+fn create_temp(output: &Path) -> Result<(PathBuf, File)> {
+    let mut attempt: u32 = 0;
+    loop {
+        let mut temp: PathBuf = output.to_path_buf();
+        temp.as_mut_os_string().push(format!(
+            ".{}-{}.tmp",
+            process::id(),
+            attempt
+        ));
+        match OpenOptions::new().write(true).create_new(true).open(&temp) {
+            Ok(file) => return Ok((temp, file)),
+            Err(e)
+                if e.kind() == io::ErrorKind::AlreadyExists
+                    && attempt < MAX_TEMP_ATTEMPTS =>
+            {
+                attempt += 1
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Gives `file` the permissions of `output`, if it exists, before anything
+/// is written to it.
+fn copy_permissions(output: &Path, file: &File) -> Result<()> {
+    match fs::metadata(output) {
+        Ok(metadata) => Ok(file.set_permissions(metadata.permissions())?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    /// Writes a small image with `chunks` before IEND to a file of its own
+    /// in the temporary directory.
+    fn testing_file(name: &str, chunks: Vec<Chunk>) -> PathBuf {
+        let header: ImageHeader = ImageHeader::new(
+            1,
+            1,
+            8,
+            pngme::ColorType::Grayscale,
+            pngme::Interlace::None,
+        )
+        .unwrap();
+        let mut png: Png = Png::from_chunks(vec![
+            header.to_chunk(),
+            Chunk::new(ChunkType::try_from(*b"IDAT").unwrap(), Vec::new()),
+            Chunk::new(ChunkType::try_from(*b"IEND").unwrap(), Vec::new()),
+        ]);
+        for chunk in chunks {
+            png.insert_chunk_before("IEND", chunk).unwrap();
+        }
+
+        let path: PathBuf = env::temp_dir().join(format!(
+            "pngme-{}-{}.png",
+            process::id(),
+            name
+        ));
+        fs::write(&path, png.as_bytes().unwrap()).unwrap();
+        path
+    }
+
+    fn test_chunk(data: Vec<u8>) -> Chunk {
+        Chunk::new(ChunkType::try_from(*b"TeSt").unwrap(), data)
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let path: PathBuf = testing_file("atomic", Vec::new());
+        let mut stale: PathBuf = path.clone();
+        stale.as_mut_os_string().push(".tmp");
+        fs::write(&stale, b"not ours").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+                .unwrap();
+        }
+
+        let mut reader: FileReader = open_reader(&path).unwrap();
+        write_atomically(&path, true, |writer| {
+            while let Some(header) = reader.next_header()? {
+                writer.copy_chunk(&mut reader, &header)?;
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(fs::read(&stale).unwrap(), b"not ours");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode: u32 = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert!(Png::from_reader_validated(File::open(&path).unwrap()).is_ok());
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&stale).unwrap();
+    }
+
+    #[test]
+    fn test_keygen_leaves_no_orphaned_secret() {
+        let path: PathBuf =
+            env::temp_dir().join(format!("pngme-{}-keygen.key", process::id()));
+        let mut public: PathBuf = path.clone();
+        public.as_mut_os_string().push(".pub");
+        fs::write(&public, b"someone else's key").unwrap();
+
+        let args = KeygenArgs::from_iter(["keygen".as_ref(), path.as_os_str()]);
+        let result: Result<()> = keygen(args);
+        let secret_exists: bool = path.exists();
+        assert_eq!(fs::read(&public).unwrap(), b"someone else's key");
+        fs::remove_file(&public).unwrap();
+
+        assert!(matches!(result, Err(Error::Io(_))));
+        assert!(!secret_exists);
+    }
+
+    #[test]
+    fn test_decode_output_refuses_many_messages() {
+        let payloads: Vec<Chunk> = ["one", "two"]
+            .iter()
+            .map(|text| Payload::new(text.as_bytes().to_vec()))
+            .map(|payload| test_chunk(payload.to_bytes().unwrap()))
+            .collect();
+        let path: PathBuf = testing_file("decode-many", payloads);
+        let output: PathBuf = path.with_extension("out");
+        let args = DecodeArgs::from_iter([
+            "decode".as_ref(),
+            path.as_os_str(),
+            "TeSt".as_ref(),
+            "--output".as_ref(),
+            output.as_os_str(),
+        ]);
+
+        let result: Result<()> = decode(args);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(Error::Io(e)) => assert!(e.to_string().contains("found 2")),
+            other => panic!("expected an error, got {:?}", other),
+        }
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn test_decode_keeps_data_that_only_looks_like_a_fragment() {
+        let mut data: Vec<u8> = fragment::MAGIC.to_vec();
+        data.extend_from_slice(b" is just how this message starts");
+        let path: PathBuf =
+            testing_file("decode-fragment", vec![test_chunk(data.clone())]);
+        let output: PathBuf = path.with_extension("out");
+        let args = DecodeArgs::from_iter([
+            "decode".as_ref(),
+            path.as_os_str(),
+            "TeSt".as_ref(),
+            "--output".as_ref(),
+            output.as_os_str(),
+        ]);
+
+        let result: Result<()> = decode(args);
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+        assert_eq!(fs::read(&output).unwrap(), data);
+        fs::remove_file(&output).unwrap();
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{zlib, Error, Result};
+use std::{
+    fmt::{self, Display, Formatter},
+    io::{Read, Write},
+    str::FromStr,
+};
+
+const BROTLI_BUFFER_SIZE: usize = 4096;
+const BROTLI_QUALITY: u32 = 9;
+const BROTLI_WINDOW: u32 = 22;
+
+/// How the data of a [`crate::Payload`] is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Compression {
+    #[default]
+    None,
+    Zlib,
+    Zstd,
+    Brotli,
+}
+
+impl Compression {
+    /// The id stored in a payload header.
+    pub fn id(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zlib => 1,
+            Compression::Zstd => 2,
+            Compression::Brotli => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Compression> {
+        match id {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Zlib),
+            2 => Some(Compression::Zstd),
+            3 => Some(Compression::Brotli),
+            _ => None,
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Zlib => Ok(zlib::deflate(data)),
+            Compression::Zstd => Ok(zstd::encode_all(data, 0)?),
+            Compression::Brotli => {
+                let mut compressed: Vec<u8> = Vec::new();
+                let mut encoder = brotli::CompressorWriter::new(
+                    &mut compressed,
+                    BROTLI_BUFFER_SIZE,
+                    BROTLI_QUALITY,
+                    BROTLI_WINDOW,
+                );
+                encoder.write_all(data)?;
+                drop(encoder);
+                Ok(compressed)
+            }
+        }
+    }
+
+    /// Decompresses `data`, giving up as soon as the output would pass
+    /// `limit` bytes so a small chunk cannot expand into gigabytes. zlib
+    /// goes through [`zlib::inflate`], which reports its own errors.
+    pub fn decompress(&self, data: &[u8], limit: usize) -> Result<Vec<u8>> {
+        let decoder: Box<dyn Read + '_> = match self {
+            Compression::None => Box::new(data),
+            Compression::Zlib => return zlib::inflate(data, limit),
+            Compression::Zstd => Box::new(zstd::Decoder::new(data)?),
+            Compression::Brotli => {
+                Box::new(brotli::Decompressor::new(data, BROTLI_BUFFER_SIZE))
+            }
+        };
+
+        let mut output: Vec<u8> = Vec::new();
+        decoder
+            .take(limit as u64 + 1)
+            .read_to_end(&mut output)
+            .map_err(|_| Error::InvalidPayload {
+                reason: "compressed data is corrupt",
+            })?;
+
+        if output.len() > limit {
+            return Err(Error::DecompressedTooLarge { limit });
+        }
+
+        Ok(output)
+    }
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "zlib" => Ok(Compression::Zlib),
+            "zstd" => Ok(Compression::Zstd),
+            "brotli" => Ok(Compression::Brotli),
+            _ => Err(Error::UnknownCompression {
+                name: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name: &str = match self {
+            Compression::None => "none",
+            Compression::Zlib => "zlib",
+            Compression::Zstd => "zstd",
+            Compression::Brotli => "brotli",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Compression; 4] = [
+        Compression::None,
+        Compression::Zlib,
+        Compression::Zstd,
+        Compression::Brotli,
+    ];
+
+    fn testing_data() -> Vec<u8> {
+        b"This is where your secret message will be! ".repeat(100)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = testing_data();
+        for compression in ALL {
+            let compressed = compression.compress(&data).unwrap();
+            let decompressed =
+                compression.decompress(&compressed, data.len()).unwrap();
+
+            assert_eq!(decompressed, data, "{}", compression);
+            if compression != Compression::None {
+                assert!(compressed.len() < data.len() / 10, "{}", compression);
+            }
+        }
+    }
+
+    #[test]
+    fn test_output_limit() {
+        let data = testing_data();
+        for compression in ALL {
+            let compressed = compression.compress(&data).unwrap();
+            assert!(matches!(
+                compression.decompress(&compressed, data.len() - 1),
+                Err(Error::DecompressedTooLarge { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_corrupt_data() {
+        for compression in [Compression::Zlib, Compression::Zstd] {
+            let mut compressed = compression.compress(&testing_data()).unwrap();
+            compressed.truncate(compressed.len() / 2);
+            assert!(compression.decompress(&compressed, 1 << 20).is_err());
+        }
+    }
+
+    #[test]
+    fn test_names_and_ids() {
+        for compression in ALL {
+            assert_eq!(
+                Compression::from_str(&compression.to_string()).unwrap(),
+                compression
+            );
+            assert_eq!(
+                Compression::from_id(compression.id()),
+                Some(compression)
+            );
+        }
+        assert!(Compression::from_str("lzma").is_err());
+        assert_eq!(Compression::from_id(4), None);
+    }
+}
@@ -0,0 +1,523 @@
+use crate::{
+    keys::{Identity, Recipient},
+    Error, Result,
+};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey, SharedSecret};
+use zeroize::Zeroizing;
+
+/// Marks chunk data as an encrypted envelope rather than plain text.
+pub const MAGIC: [u8; 4] = *b"PGME";
+pub const VERSION: u8 = 1;
+
+const PREFIX_SIZE: usize = MAGIC.len() + 3;
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+const KEY_SIZE: usize = 32;
+const PASSWORD_FIELDS_SIZE: usize = 3 * 4 + SALT_SIZE;
+const RECIPIENT_FIELDS_SIZE: usize = 32;
+const RECIPIENT_INFO: &[u8] = b"pngme x25519 v1";
+
+// Upper bounds on the Argon2 cost read back from an envelope, so a crafted
+// chunk cannot make decode allocate much or spin for long. They allow four
+// times the defaults that encode uses.
+const MAX_MEMORY_KIB: u32 = 4 * Params::DEFAULT_M_COST;
+const MAX_ITERATIONS: u32 = 4 * Params::DEFAULT_T_COST;
+const MAX_PARALLELISM: u32 = 4 * Params::DEFAULT_P_COST;
+
+/// How the key that seals an envelope is obtained.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum KeyMode {
+    /// Derived from a passphrase with Argon2id.
+    Password,
+    /// Agreed between a one-off X25519 key and the recipient's public key.
+    Recipient,
+}
+
+impl KeyMode {
+    fn id(&self) -> u8 {
+        match self {
+            KeyMode::Password => 1,
+            KeyMode::Recipient => 2,
+        }
+    }
+    fn from_id(id: u8) -> Option<KeyMode> {
+        match id {
+            1 => Some(KeyMode::Password),
+            2 => Some(KeyMode::Recipient),
+            _ => None,
+        }
+    }
+}
+
+/// The AEAD an envelope is sealed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Cipher {
+    ChaCha20Poly1305,
+}
+
+impl Cipher {
+    fn id(&self) -> u8 {
+        match self {
+            Cipher::ChaCha20Poly1305 => 1,
+        }
+    }
+    fn from_id(id: u8) -> Option<Cipher> {
+        match id {
+            1 => Some(Cipher::ChaCha20Poly1305),
+            _ => None,
+        }
+    }
+}
+
+/// Argon2id cost parameters, stored in the envelope next to the salt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl KdfParams {
+    pub(crate) fn derive_key(
+        &self,
+        password: &[u8],
+        salt: &[u8],
+    ) -> Result<Zeroizing<[u8; KEY_SIZE]>> {
+        if self.memory_kib > MAX_MEMORY_KIB
+            || self.iterations > MAX_ITERATIONS
+            || self.parallelism > MAX_PARALLELISM
+        {
+            return Err(Error::InvalidEnvelope {
+                reason: "key derivation cost is too high",
+            });
+        }
+
+        let params: Params = Params::new(
+            self.memory_kib,
+            self.iterations,
+            self.parallelism,
+            Some(KEY_SIZE),
+        )
+        .map_err(|_| Error::InvalidEnvelope {
+            reason: "invalid key derivation parameters",
+        })?;
+        let mut key: Zeroizing<[u8; KEY_SIZE]> = Zeroizing::new([0; KEY_SIZE]);
+
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(password, salt, key.as_mut())
+            .map_err(|_| Error::InvalidEnvelope {
+                reason: "invalid key derivation parameters",
+            })?;
+
+        Ok(key)
+    }
+}
+
+/// Returns `true` if `data` starts like an envelope.
+pub fn is_envelope(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Encrypts `plaintext` under a key derived from `password`.
+pub fn seal_with_password(
+    plaintext: &[u8],
+    password: &[u8],
+) -> Result<Vec<u8>> {
+    seal_with_password_params(plaintext, password, KdfParams::default())
+}
+
+pub fn seal_with_password_params(
+    plaintext: &[u8],
+    password: &[u8],
+    params: KdfParams,
+) -> Result<Vec<u8>> {
+    let mut salt: [u8; SALT_SIZE] = [0; SALT_SIZE];
+    OsRng.fill_bytes(&mut salt);
+    let key: Zeroizing<[u8; KEY_SIZE]> = params.derive_key(password, &salt)?;
+
+    let mut header: Vec<u8> = prefix(KeyMode::Password);
+    header.extend_from_slice(&params.memory_kib.to_be_bytes());
+    header.extend_from_slice(&params.iterations.to_be_bytes());
+    header.extend_from_slice(&params.parallelism.to_be_bytes());
+    header.extend_from_slice(&salt);
+
+    seal(header, &key, plaintext)
+}
+
+/// Decrypts an envelope made by [`seal_with_password`]. Fails with
+/// [`Error::DecryptionFailed`] if the password is wrong or the envelope has
+/// been tampered with.
+pub fn open_with_password(envelope: &[u8], password: &[u8]) -> Result<Vec<u8>> {
+    let fields: &[u8] = check_prefix(envelope, KeyMode::Password)?;
+
+    if fields.len() < PASSWORD_FIELDS_SIZE {
+        return Err(Error::InvalidEnvelope {
+            reason: "envelope is truncated",
+        });
+    }
+
+    let params: KdfParams = KdfParams {
+        memory_kib: read_u32(&fields[0..4]),
+        iterations: read_u32(&fields[4..8]),
+        parallelism: read_u32(&fields[8..12]),
+    };
+    let salt: &[u8] = &fields[12..PASSWORD_FIELDS_SIZE];
+    let key: Zeroizing<[u8; KEY_SIZE]> = params.derive_key(password, salt)?;
+
+    open(envelope, PREFIX_SIZE + PASSWORD_FIELDS_SIZE, &key)
+}
+
+/// Encrypts `plaintext` so only the holder of the identity behind
+/// `recipient` can read it.
+pub fn seal_for_recipient(
+    plaintext: &[u8],
+    recipient: &Recipient,
+) -> Result<Vec<u8>> {
+    let ephemeral: EphemeralSecret = EphemeralSecret::random_from_rng(OsRng);
+    let ephemeral_public: PublicKey = PublicKey::from(&ephemeral);
+    let shared: SharedSecret = ephemeral.diffie_hellman(recipient.key());
+    let key: Zeroizing<[u8; KEY_SIZE]> =
+        recipient_key(&shared, &ephemeral_public, recipient.key())?;
+
+    let mut header: Vec<u8> = prefix(KeyMode::Recipient);
+    header.extend_from_slice(ephemeral_public.as_bytes());
+
+    seal(header, &key, plaintext)
+}
+
+/// Decrypts an envelope made by [`seal_for_recipient`]. Fails with
+/// [`Error::DecryptionFailed`] if it was sealed to someone else or has been
+/// tampered with.
+pub fn open_with_identity(
+    envelope: &[u8],
+    identity: &Identity,
+) -> Result<Vec<u8>> {
+    let fields: &[u8] = check_prefix(envelope, KeyMode::Recipient)?;
+
+    let ephemeral_public: [u8; RECIPIENT_FIELDS_SIZE] = fields
+        .get(..RECIPIENT_FIELDS_SIZE)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(Error::InvalidEnvelope {
+            reason: "envelope is truncated",
+        })?;
+    let ephemeral_public: PublicKey = PublicKey::from(ephemeral_public);
+    let shared: SharedSecret =
+        identity.secret().diffie_hellman(&ephemeral_public);
+    let key: Zeroizing<[u8; KEY_SIZE]> =
+        recipient_key(&shared, &ephemeral_public, identity.recipient().key())?;
+
+    open(envelope, PREFIX_SIZE + RECIPIENT_FIELDS_SIZE, &key)
+}
+
+/// Turns an X25519 shared secret into an AEAD key, binding both public
+/// keys into the derivation.
+fn recipient_key(
+    shared: &SharedSecret,
+    ephemeral: &PublicKey,
+    recipient: &PublicKey,
+) -> Result<Zeroizing<[u8; KEY_SIZE]>> {
+    // A low-order public key forces an all-zero secret that anyone can
+    // compute, so such keys are refused.
+    if !shared.was_contributory() {
+        return Err(Error::DecryptionFailed);
+    }
+
+    let mut salt: [u8; 64] = [0; 64];
+    salt[..32].copy_from_slice(ephemeral.as_bytes());
+    salt[32..].copy_from_slice(recipient.as_bytes());
+
+    let mut key: Zeroizing<[u8; KEY_SIZE]> = Zeroizing::new([0; KEY_SIZE]);
+    Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+        .expand(RECIPIENT_INFO, key.as_mut())
+        .map_err(|_| Error::EncryptionFailed)?;
+
+    Ok(key)
+}
+
+fn prefix(mode: KeyMode) -> Vec<u8> {
+    let mut header: Vec<u8> = MAGIC.to_vec();
+    header.push(VERSION);
+    header.push(mode.id());
+    header.push(Cipher::ChaCha20Poly1305.id());
+    header
+}
+
+/// Checks magic, version, key mode and cipher, returning the bytes after
+/// them.
+fn check_prefix(envelope: &[u8], expected: KeyMode) -> Result<&[u8]> {
+    if !is_envelope(envelope) {
+        return Err(Error::InvalidEnvelope {
+            reason: "data is not an encrypted envelope",
+        });
+    }
+
+    if envelope.len() < PREFIX_SIZE {
+        return Err(Error::InvalidEnvelope {
+            reason: "envelope is truncated",
+        });
+    }
+
+    if envelope[4] != VERSION {
+        return Err(Error::InvalidEnvelope {
+            reason: "unsupported envelope version",
+        });
+    }
+
+    match KeyMode::from_id(envelope[5]) {
+        Some(mode) if mode == expected => {}
+        Some(_) => {
+            return Err(Error::InvalidEnvelope {
+                reason: "envelope was sealed with a different kind of key",
+            })
+        }
+        None => {
+            return Err(Error::InvalidEnvelope {
+                reason: "unsupported key mode",
+            })
+        }
+    }
+
+    if Cipher::from_id(envelope[6]).is_none() {
+        return Err(Error::InvalidEnvelope {
+            reason: "unsupported cipher",
+        });
+    }
+
+    Ok(&envelope[PREFIX_SIZE..])
+}
+
+/// Appends a fresh nonce to `header` and seals `plaintext` after it, with
+/// the whole header as associated data so none of it can be altered.
+fn seal(
+    mut header: Vec<u8>,
+    key: &[u8; KEY_SIZE],
+    plaintext: &[u8],
+) -> Result<Vec<u8>> {
+    let cipher: ChaCha20Poly1305 = ChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce: Nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    header.extend_from_slice(&nonce);
+
+    let ciphertext: Vec<u8> = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad: &header,
+            },
+        )
+        .map_err(|_| Error::EncryptionFailed)?;
+    header.extend_from_slice(&ciphertext);
+
+    Ok(header)
+}
+
+/// Opens an envelope whose key-specific fields end at `fields_end`.
+fn open(
+    envelope: &[u8],
+    fields_end: usize,
+    key: &[u8; KEY_SIZE],
+) -> Result<Vec<u8>> {
+    let header_end: usize = fields_end + NONCE_SIZE;
+
+    if envelope.len() < header_end {
+        return Err(Error::InvalidEnvelope {
+            reason: "envelope is truncated",
+        });
+    }
+
+    let cipher: ChaCha20Poly1305 = ChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce: &Nonce = Nonce::from_slice(&envelope[fields_end..header_end]);
+
+    cipher
+        .decrypt(
+            nonce,
+            Payload {
+                msg: &envelope[header_end..],
+                aad: &envelope[..header_end],
+            },
+        )
+        .map_err(|_| Error::DecryptionFailed)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap parameters so the tests do not spend their time in Argon2.
+    fn testing_params() -> KdfParams {
+        KdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        }
+    }
+
+    fn testing_envelope() -> Vec<u8> {
+        seal_with_password_params(
+            b"This is where your secret message will be!",
+            b"hunter2",
+            testing_params(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_password_round_trip() {
+        let envelope = testing_envelope();
+        let plaintext = open_with_password(&envelope, b"hunter2").unwrap();
+
+        assert!(is_envelope(&envelope));
+        assert_eq!(plaintext, b"This is where your secret message will be!");
+    }
+
+    #[test]
+    fn test_wrong_password() {
+        let envelope = testing_envelope();
+        let result = open_with_password(&envelope, b"hunter3");
+
+        assert!(matches!(result, Err(Error::DecryptionFailed)));
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let envelope = testing_envelope();
+
+        for index in [PREFIX_SIZE + 12, envelope.len() - 1, envelope.len() - 20]
+        {
+            let mut tampered = envelope.clone();
+            tampered[index] ^= 1;
+            assert!(open_with_password(&tampered, b"hunter2").is_err());
+        }
+    }
+
+    #[test]
+    fn test_recipient_round_trip() {
+        let identity = Identity::generate();
+        let envelope =
+            seal_for_recipient(b"For your eyes only", &identity.recipient())
+                .unwrap();
+        let plaintext = open_with_identity(&envelope, &identity).unwrap();
+
+        assert_eq!(plaintext, b"For your eyes only");
+    }
+
+    #[test]
+    fn test_wrong_identity() {
+        let envelope = seal_for_recipient(
+            b"For your eyes only",
+            &Identity::generate().recipient(),
+        )
+        .unwrap();
+        let result = open_with_identity(&envelope, &Identity::generate());
+
+        assert!(matches!(result, Err(Error::DecryptionFailed)));
+    }
+
+    #[test]
+    fn test_key_mode_mismatch() {
+        let identity = Identity::generate();
+        let envelope =
+            seal_for_recipient(b"hi", &identity.recipient()).unwrap();
+
+        assert!(matches!(
+            open_with_password(&envelope, b"hunter2"),
+            Err(Error::InvalidEnvelope { .. })
+        ));
+        assert!(matches!(
+            open_with_identity(&testing_envelope(), &identity),
+            Err(Error::InvalidEnvelope { .. })
+        ));
+    }
+
+    #[test]
+    fn test_low_order_key_is_refused() {
+        let identity = Identity::generate();
+        let mut envelope =
+            seal_for_recipient(b"hi", &identity.recipient()).unwrap();
+        envelope[PREFIX_SIZE..PREFIX_SIZE + 32].fill(0);
+
+        assert!(matches!(
+            open_with_identity(&envelope, &identity),
+            Err(Error::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_malformed_envelopes() {
+        let envelope = testing_envelope();
+
+        for end in 0..PREFIX_SIZE + PASSWORD_FIELDS_SIZE + NONCE_SIZE {
+            assert!(matches!(
+                open_with_password(&envelope[..end], b"hunter2"),
+                Err(Error::InvalidEnvelope { .. })
+            ));
+        }
+
+        let mut future = envelope.clone();
+        future[4] = VERSION + 1;
+        assert!(matches!(
+            open_with_password(&future, b"hunter2"),
+            Err(Error::InvalidEnvelope {
+                reason: "unsupported envelope version"
+            })
+        ));
+
+        let mut expensive = envelope;
+        expensive[PREFIX_SIZE..PREFIX_SIZE + 4]
+            .copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(
+            open_with_password(&expensive, b"hunter2"),
+            Err(Error::InvalidEnvelope { .. })
+        ));
+    }
+
+    #[test]
+    fn test_kdf_cost_limits() {
+        let too_costly = |params: KdfParams| {
+            matches!(
+                params.derive_key(b"hunter2", &[0; SALT_SIZE]),
+                Err(Error::InvalidEnvelope {
+                    reason: "key derivation cost is too high"
+                })
+            )
+        };
+        let default = KdfParams::default();
+
+        assert!(too_costly(KdfParams {
+            memory_kib: MAX_MEMORY_KIB + 1,
+            ..default
+        }));
+        assert!(too_costly(KdfParams {
+            iterations: MAX_ITERATIONS + 1,
+            ..default
+        }));
+        assert!(too_costly(KdfParams {
+            parallelism: MAX_PARALLELISM + 1,
+            ..default
+        }));
+        assert!(!too_costly(testing_params()));
+    }
+}
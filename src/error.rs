@@ -0,0 +1,424 @@
+use crate::{
+    chunk::Chunk, chunk_type::ChunkType, header::ColorType,
+    structure::Violation,
+};
+use std::{
+    error,
+    fmt::{self, Display, Formatter},
+    io,
+    string::FromUtf8Error,
+};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Every failure the library can report.
+///
+/// Offsets are byte positions from the start of the buffer that was being
+/// parsed, so an error coming out of `Png::try_from` points into the whole
+/// file while one coming out of `Chunk::try_from` points into the chunk.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// A chunk type string was not exactly four bytes long.
+    ChunkTypeLength { length: usize },
+    /// A chunk type contained a byte that is not an ASCII letter.
+    ChunkTypeByte { byte: u8, index: usize },
+    /// The type field of the chunk at `offset` is not a valid chunk type.
+    InvalidChunkType { offset: usize, source: Box<Error> },
+    /// Fewer bytes than the length, type and CRC fields need.
+    ChunkTooSmall { offset: usize, length: usize },
+    /// The input ends inside the length field of the chunk at `offset`.
+    TruncatedLength { offset: usize, available: usize },
+    /// The input ends before the type, data and CRC of the chunk at
+    /// `offset` are complete.
+    TruncatedData {
+        offset: usize,
+        needed: usize,
+        available: usize,
+    },
+    /// The length field exceeds the 2^31-1 bytes the spec allows.
+    ChunkTooLong { offset: usize, length: u32 },
+    /// The length field disagrees with the amount of data present.
+    DataLengthMismatch {
+        offset: usize,
+        chunk_type: ChunkType,
+        declared: u32,
+        actual: usize,
+    },
+    /// Chunk data was written at `offset` without a chunk being started.
+    NoOpenChunk { offset: usize },
+    /// The stored CRC does not match the one computed over type and data.
+    InvalidCrc {
+        offset: usize,
+        chunk_type: ChunkType,
+        stored: u32,
+        computed: u32,
+    },
+    /// The input is shorter than the PNG signature.
+    FileTooSmall { length: usize },
+    /// The input does not start with the PNG signature.
+    InvalidSignature { found: [u8; 8] },
+    /// A chunk position lies past the end of the chunk list.
+    IndexOutOfRange { index: usize, length: usize },
+    /// No chunk of the requested type exists.
+    ChunkNotFound { chunk_type: String },
+    /// The image has no IHDR chunk.
+    MissingImageHeader,
+    /// IHDR data is not 13 bytes long.
+    ImageHeaderLength { length: usize },
+    /// Width or height is zero or larger than 2^31-1.
+    InvalidDimensions { width: u32, height: u32 },
+    /// IHDR names a color type the spec does not define.
+    InvalidColorType { value: u8 },
+    /// The bit depth is not allowed for the color type.
+    InvalidBitDepth {
+        color_type: ColorType,
+        bit_depth: u8,
+    },
+    /// IHDR names a compression method other than deflate.
+    InvalidCompressionMethod { value: u8 },
+    /// IHDR names a filter method other than adaptive filtering.
+    InvalidFilterMethod { value: u8 },
+    /// IHDR names an interlace method other than none or Adam7.
+    InvalidInterlaceMethod { value: u8 },
+    /// The chunk at position `index` breaks a chunk ordering rule.
+    InvalidStructure {
+        index: usize,
+        chunk_type: Option<ChunkType>,
+        violation: Violation,
+    },
+    /// Chunk data is not a well-formed encrypted envelope this version can
+    /// open.
+    InvalidEnvelope { reason: &'static str },
+    /// A key could not be parsed.
+    InvalidKey { reason: &'static str },
+    /// Chunk data is not a well-formed file payload.
+    InvalidPayload { reason: &'static str },
+    /// A payload would decompress to more than `limit` bytes.
+    DecompressedTooLarge { limit: usize },
+    /// A compression algorithm name is not recognized.
+    UnknownCompression { name: String },
+    /// Chunk data is not a well-formed payload fragment, or the fragments
+    /// of a payload do not fit together.
+    InvalidFragment { reason: &'static str },
+    /// Fragment `index` (counting from 0) of a payload split into `total`
+    /// fragments was not found.
+    MissingFragment { index: u32, total: u32 },
+    /// A signature chunk is malformed.
+    InvalidSignatureChunk { reason: &'static str },
+    /// A signature does not match its key, payload or image.
+    SignatureMismatch,
+    /// Sealing a payload failed.
+    EncryptionFailed,
+    /// The key is wrong, or the envelope has been tampered with.
+    DecryptionFailed,
+    /// The image data does not inflate to the size IHDR implies.
+    InvalidImageData { reason: &'static str },
+    /// Scanline `row` starts with a filter type the spec does not define.
+    InvalidFilterType { row: usize, value: u8 },
+    /// The operation does not handle this kind of image.
+    UnsupportedImage { reason: &'static str },
+    /// Hiding `needed` bytes in the pixels takes more room than the
+    /// `available` bytes the image offers.
+    InsufficientCapacity { needed: usize, available: usize },
+    /// The pixels do not carry a hidden message.
+    NoHiddenData,
+    /// The bits or channels chosen for pixel embedding are not usable.
+    InvalidLsbOptions { reason: &'static str },
+    /// A channel selection name is not recognized.
+    UnknownChannels { name: String },
+    /// A zlib stream is malformed or fails its Adler-32 check.
+    InvalidZlibStream { reason: &'static str },
+    /// Image data cannot be split into IDAT chunks of `size` bytes.
+    InvalidIdatSize { size: usize },
+    /// PLTE or tRNS do not describe a usable palette.
+    InvalidPalette { reason: &'static str },
+    /// The pixel does not suit the image it is written to.
+    InvalidPixel { reason: &'static str },
+    /// There is no pixel at `(x, y)`.
+    PixelOutOfBounds { x: u32, y: u32 },
+    /// A tEXt, zTXt or iTXt chunk is malformed, or cannot be encoded.
+    InvalidTextChunk { reason: &'static str },
+    /// No text chunk has the keyword asked for.
+    KeywordNotFound { keyword: String },
+    /// Chunk data was expected to be UTF-8 but is not.
+    Utf8(FromUtf8Error),
+    /// Reading or writing failed.
+    Io(io::Error),
+}
+
+impl Error {
+    /// Byte offset of the failure, if it is tied to a position.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            Error::InvalidChunkType { offset, .. }
+            | Error::ChunkTooSmall { offset, .. }
+            | Error::TruncatedLength { offset, .. }
+            | Error::TruncatedData { offset, .. }
+            | Error::ChunkTooLong { offset, .. }
+            | Error::DataLengthMismatch { offset, .. }
+            | Error::NoOpenChunk { offset }
+            | Error::InvalidCrc { offset, .. } => Some(*offset),
+            _ => None,
+        }
+    }
+
+    /// Type of the chunk the failure was found in, if it could be read.
+    pub fn chunk_type(&self) -> Option<&ChunkType> {
+        match self {
+            Error::DataLengthMismatch { chunk_type, .. }
+            | Error::InvalidCrc { chunk_type, .. } => Some(chunk_type),
+            Error::InvalidStructure { chunk_type, .. } => chunk_type.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Moves a chunk-relative offset to be relative to an enclosing buffer
+    /// in which the chunk starts at `base`.
+    pub(crate) fn at_offset(mut self, base: usize) -> Error {
+        match &mut self {
+            Error::InvalidChunkType { offset, .. }
+            | Error::ChunkTooSmall { offset, .. }
+            | Error::TruncatedLength { offset, .. }
+            | Error::TruncatedData { offset, .. }
+            | Error::ChunkTooLong { offset, .. }
+            | Error::DataLengthMismatch { offset, .. }
+            | Error::NoOpenChunk { offset }
+            | Error::InvalidCrc { offset, .. } => *offset += base,
+            _ => {}
+        }
+        self
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::InvalidChunkType { source, .. } => Some(source.as_ref()),
+            Error::Utf8(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Error::ChunkTypeLength { length } => {
+                write!(f, "Invalid length of chunk type: {} bytes!", length)
+            }
+            Error::ChunkTypeByte { byte, index } => write!(
+                f,
+                "Invalid character {:#04x} at index {} in chunk type!",
+                byte, index
+            ),
+            Error::InvalidChunkType { offset, .. } => {
+                write!(f, "Invalid chunk type at offset {:#x}!", offset)
+            }
+            Error::ChunkTooSmall { offset, length } => write!(
+                f,
+                "Invalid length of chunk at offset {:#x}: {} bytes!",
+                offset, length
+            ),
+            Error::TruncatedLength { offset, available } => write!(
+                f,
+                "Truncated chunk length at offset {:#x}: {} of 4 bytes!",
+                offset, available
+            ),
+            Error::TruncatedData {
+                offset,
+                needed,
+                available,
+            } => write!(
+                f,
+                "Truncated chunk at offset {:#x}: needed {} bytes, found {}!",
+                offset, needed, available
+            ),
+            Error::ChunkTooLong { offset, length } => write!(
+                f,
+                "Chunk length {} at offset {:#x} exceeds 2^31-1 bytes!",
+                length, offset
+            ),
+            Error::DataLengthMismatch {
+                offset,
+                chunk_type,
+                declared,
+                actual,
+            } => write!(
+                f,
+                "Invalid data length in {} chunk at offset {:#x}: declared \
+                 {}, found {}!",
+                chunk_type, offset, declared, actual
+            ),
+            Error::NoOpenChunk { offset } => write!(
+                f,
+                "Chunk data written at offset {:#x} without a chunk!",
+                offset
+            ),
+            Error::InvalidCrc {
+                offset,
+                chunk_type,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "Invalid crc in {} chunk at offset {:#x}: stored {:#010x}, \
+                 computed {:#010x}!",
+                chunk_type, offset, stored, computed
+            ),
+            Error::FileTooSmall { length } => {
+                write!(f, "Invalid Png size: {} bytes!", length)
+            }
+            Error::InvalidSignature { .. } => write!(f, "Invalid header!"),
+            Error::IndexOutOfRange { index, length } => write!(
+                f,
+                "Chunk index {} is out of range for {} chunks!",
+                index, length
+            ),
+            Error::ChunkNotFound { chunk_type } => {
+                write!(f, "No chunk found with type {}!", chunk_type)
+            }
+            Error::MissingImageHeader => write!(f, "Missing IHDR chunk!"),
+            Error::ImageHeaderLength { length } => {
+                write!(f, "Invalid IHDR length: {} bytes!", length)
+            }
+            Error::InvalidDimensions { width, height } => {
+                write!(f, "Invalid image dimensions {}x{}!", width, height)
+            }
+            Error::InvalidColorType { value } => {
+                write!(f, "Invalid color type {}!", value)
+            }
+            Error::InvalidBitDepth {
+                color_type,
+                bit_depth,
+            } => write!(
+                f,
+                "Invalid bit depth {} for {} images!",
+                bit_depth, color_type
+            ),
+            Error::InvalidCompressionMethod { value } => {
+                write!(f, "Invalid compression method {}!", value)
+            }
+            Error::InvalidFilterMethod { value } => {
+                write!(f, "Invalid filter method {}!", value)
+            }
+            Error::InvalidInterlaceMethod { value } => {
+                write!(f, "Invalid interlace method {}!", value)
+            }
+            Error::InvalidStructure {
+                index,
+                chunk_type: Some(chunk_type),
+                violation,
+            } => write!(
+                f,
+                "Invalid {} chunk at position {}: {}!",
+                chunk_type, index, violation
+            ),
+            Error::InvalidStructure {
+                chunk_type: None,
+                violation,
+                ..
+            } => write!(f, "Invalid chunk structure: {}!", violation),
+            Error::InvalidEnvelope { reason } => {
+                write!(f, "Invalid encrypted message: {}!", reason)
+            }
+            Error::InvalidKey { reason } => {
+                write!(f, "Invalid key: {}!", reason)
+            }
+            Error::InvalidPayload { reason } => {
+                write!(f, "Invalid file payload: {}!", reason)
+            }
+            Error::DecompressedTooLarge { limit } => write!(
+                f,
+                "Payload would decompress to more than {} bytes!",
+                limit
+            ),
+            Error::UnknownCompression { name } => write!(
+                f,
+                "Unknown compression {:?}: use zlib, zstd or brotli!",
+                name
+            ),
+            Error::InvalidFragment { reason } => {
+                write!(f, "Invalid payload fragment: {}!", reason)
+            }
+            Error::MissingFragment { index, total } => write!(
+                f,
+                "Payload fragment {} of {} is missing!",
+                index + 1,
+                total
+            ),
+            Error::InvalidSignatureChunk { reason } => {
+                write!(f, "Invalid signature chunk: {}!", reason)
+            }
+            Error::SignatureMismatch => write!(f, "Signature does not match!"),
+            Error::EncryptionFailed => write!(f, "Encryption failed!"),
+            Error::DecryptionFailed => {
+                write!(f, "Decryption failed: wrong key or tampered message!")
+            }
+            Error::InvalidImageData { reason } => {
+                write!(f, "Invalid image data: {}!", reason)
+            }
+            Error::InvalidFilterType { row, value } => {
+                write!(f, "Invalid filter type {} on scanline {}!", value, row)
+            }
+            Error::UnsupportedImage { reason } => {
+                write!(f, "Unsupported image: {}!", reason)
+            }
+            Error::InsufficientCapacity { needed, available } => write!(
+                f,
+                "Message needs {} bytes but the image only holds {}!",
+                needed, available
+            ),
+            Error::NoHiddenData => {
+                write!(f, "No hidden message found in the pixel data!")
+            }
+            Error::InvalidLsbOptions { reason } => {
+                write!(f, "Invalid embedding options: {}!", reason)
+            }
+            Error::UnknownChannels { name } => write!(
+                f,
+                "Unknown channels {:?}: use color, alpha or all!",
+                name
+            ),
+            Error::InvalidZlibStream { reason } => {
+                write!(f, "Invalid zlib stream: {}!", reason)
+            }
+            Error::InvalidIdatSize { size } => write!(
+                f,
+                "IDAT chunks must hold between 1 and {} bytes, not {}!",
+                Chunk::MAX_LENGTH,
+                size
+            ),
+            Error::InvalidPalette { reason } => {
+                write!(f, "Invalid palette: {}!", reason)
+            }
+            Error::InvalidPixel { reason } => {
+                write!(f, "Invalid pixel: {}!", reason)
+            }
+            Error::PixelOutOfBounds { x, y } => {
+                write!(f, "There is no pixel at ({}, {})!", x, y)
+            }
+            Error::InvalidTextChunk { reason } => {
+                write!(f, "Invalid text chunk: {}!", reason)
+            }
+            Error::KeywordNotFound { keyword } => {
+                write!(f, "No text chunk found with keyword {}!", keyword)
+            }
+            Error::Utf8(e) => write!(f, "Invalid UTF-8 data: {}", e),
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(value: FromUtf8Error) -> Self {
+        Error::Utf8(value)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Error::Io(value)
+    }
+}
@@ -0,0 +1,513 @@
+use crate::{
+    header::{ColorType, ImageHeader},
+    zlib, Error, Result,
+};
+use std::{
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+};
+
+/// The per-scanline filters of PNG filter method 0.
+///
+/// Each filter predicts a byte from its neighbours to the left (`a`),
+/// above (`b`) and above-left (`c`), at a distance of one whole pixel, and
+/// stores the difference.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    None,
+    Sub,
+    Up,
+    Average,
+    Paeth,
+}
+
+impl FilterType {
+    pub const ALL: [FilterType; 5] = [
+        FilterType::None,
+        FilterType::Sub,
+        FilterType::Up,
+        FilterType::Average,
+        FilterType::Paeth,
+    ];
+
+    /// The byte that starts a scanline filtered this way.
+    pub fn value(&self) -> u8 {
+        match self {
+            FilterType::None => 0,
+            FilterType::Sub => 1,
+            FilterType::Up => 2,
+            FilterType::Average => 3,
+            FilterType::Paeth => 4,
+        }
+    }
+
+    fn predict(&self, a: u8, b: u8, c: u8) -> u8 {
+        match self {
+            FilterType::None => 0,
+            FilterType::Sub => a,
+            FilterType::Up => b,
+            FilterType::Average => ((a as u16 + b as u16) / 2) as u8,
+            FilterType::Paeth => paeth(a, b, c),
+        }
+    }
+}
+
+impl TryFrom<u8> for FilterType {
+    type Error = u8;
+
+    fn try_from(value: u8) -> std::result::Result<Self, u8> {
+        FilterType::ALL
+            .into_iter()
+            .find(|f| f.value() == value)
+            .ok_or(value)
+    }
+}
+
+impl Display for FilterType {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name: &str = match self {
+            FilterType::None => "None",
+            FilterType::Sub => "Sub",
+            FilterType::Up => "Up",
+            FilterType::Average => "Average",
+            FilterType::Paeth => "Paeth",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How the encoder picks the filter for each scanline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilterStrategy {
+    /// The same filter on every row.
+    Fixed(FilterType),
+    /// The filter leaving the smallest sum of absolute differences, read as
+    /// signed bytes. This is the heuristic libpng uses, and the PNG spec
+    /// recommends it for all but palette and low bit depth images.
+    #[default]
+    MinSumAbs,
+    /// The filter whose row deflates smallest on its own. Slow, but it can
+    /// beat the heuristic on images with noise or sharp edges.
+    BruteForce,
+}
+
+impl FilterStrategy {
+    /// What the PNG spec recommends for an image with these pixels: no
+    /// filtering for palette indices and bit depths below 8, adaptive
+    /// filtering for everything else.
+    pub fn recommended(header: &ImageHeader) -> FilterStrategy {
+        if header.color_type() == ColorType::Indexed || header.bit_depth() < 8 {
+            FilterStrategy::Fixed(FilterType::None)
+        } else {
+            FilterStrategy::MinSumAbs
+        }
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p: i16 = a as i16 + b as i16 - c as i16;
+    let pa: i16 = (p - a as i16).abs();
+    let pb: i16 = (p - b as i16).abs();
+    let pc: i16 = (p - c as i16).abs();
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Reverses the filtering of `data`, a run of scanlines of `row_bytes`
+/// bytes each preceded by its filter type byte.
+///
+/// Returns the unfiltered rows packed back to back, and the filter each
+/// row was stored with.
+pub fn unfilter(
+    data: &[u8],
+    row_bytes: usize,
+    bpp: usize,
+) -> Result<(Vec<u8>, Vec<FilterType>)> {
+    if !data.len().is_multiple_of(row_bytes + 1) {
+        return Err(Error::InvalidImageData {
+            reason: "data does not divide into whole scanlines",
+        });
+    }
+
+    let rows: usize = data.len() / (row_bytes + 1);
+    let mut pixels: Vec<u8> = Vec::with_capacity(rows * row_bytes);
+    let mut filters: Vec<FilterType> = Vec::with_capacity(rows);
+
+    for (row, line) in data.chunks_exact(row_bytes + 1).enumerate() {
+        let filter: FilterType = FilterType::try_from(line[0])
+            .map_err(|value| Error::InvalidFilterType { row, value })?;
+        let start: usize = pixels.len();
+        for (i, &byte) in line[1..].iter().enumerate() {
+            let (a, b, c) = neighbours(&pixels, start, row_bytes, bpp, i);
+            pixels.push(byte.wrapping_add(filter.predict(a, b, c)));
+        }
+        filters.push(filter);
+    }
+
+    Ok((pixels, filters))
+}
+
+/// Filters `pixels`, unfiltered rows of `row_bytes` bytes each, applying
+/// `filters[row]` to each row and prefixing its type byte.
+///
+/// # Panics
+///
+/// If `filters` does not have one entry per row.
+pub fn filter(
+    pixels: &[u8],
+    row_bytes: usize,
+    bpp: usize,
+    filters: &[FilterType],
+) -> Vec<u8> {
+    let rows: usize = pixels.len().checked_div(row_bytes).unwrap_or(0);
+    assert_eq!(filters.len(), rows, "one filter per scanline");
+
+    let mut data: Vec<u8> = Vec::with_capacity(rows * (row_bytes + 1));
+    for (row, &filter) in filters.iter().enumerate() {
+        filter_row(pixels, row * row_bytes, row_bytes, bpp, filter, &mut data);
+    }
+
+    data
+}
+
+/// Filters `pixels` like [`filter`], picking the filter of each row by
+/// `strategy`. Returns the filtered data and the filters picked.
+pub fn filter_with(
+    pixels: &[u8],
+    row_bytes: usize,
+    bpp: usize,
+    strategy: FilterStrategy,
+) -> (Vec<u8>, Vec<FilterType>) {
+    let rows: usize = pixels.len().checked_div(row_bytes).unwrap_or(0);
+    let mut data: Vec<u8> = Vec::with_capacity(rows * (row_bytes + 1));
+    let filters: Vec<FilterType> =
+        pick_filters(pixels, row_bytes, bpp, strategy, Some(&mut data));
+
+    (data, filters)
+}
+
+/// The filters [`filter_with`] would pick for each row of `pixels`,
+/// without keeping the filtered data.
+pub fn choose_filters(
+    pixels: &[u8],
+    row_bytes: usize,
+    bpp: usize,
+    strategy: FilterStrategy,
+) -> Vec<FilterType> {
+    pick_filters(pixels, row_bytes, bpp, strategy, None)
+}
+
+/// Picks the filter of each row by `strategy`, appending the filtered rows
+/// to `data` if given.
+fn pick_filters(
+    pixels: &[u8],
+    row_bytes: usize,
+    bpp: usize,
+    strategy: FilterStrategy,
+    mut data: Option<&mut Vec<u8>>,
+) -> Vec<FilterType> {
+    let rows: usize = pixels.len().checked_div(row_bytes).unwrap_or(0);
+    let mut filters: Vec<FilterType> = Vec::with_capacity(rows);
+    let mut candidate: Vec<u8> = Vec::with_capacity(row_bytes + 1);
+    let mut best: Vec<u8> = Vec::with_capacity(row_bytes + 1);
+
+    for row in 0..rows {
+        let start: usize = row * row_bytes;
+        let filter: FilterType = match strategy {
+            FilterStrategy::Fixed(filter) => {
+                if let Some(data) = data.as_deref_mut() {
+                    filter_row(pixels, start, row_bytes, bpp, filter, data);
+                }
+                filter
+            }
+            FilterStrategy::MinSumAbs | FilterStrategy::BruteForce => {
+                let mut chosen: (FilterType, usize) = (FilterType::None, 0);
+                for (i, filter) in FilterType::ALL.into_iter().enumerate() {
+                    candidate.clear();
+                    filter_row(
+                        pixels,
+                        start,
+                        row_bytes,
+                        bpp,
+                        filter,
+                        &mut candidate,
+                    );
+                    let cost: usize = match strategy {
+                        FilterStrategy::BruteForce => {
+                            zlib::deflate(&candidate).len()
+                        }
+                        _ => sum_abs(&candidate),
+                    };
+                    if i == 0 || cost < chosen.1 {
+                        chosen = (filter, cost);
+                        std::mem::swap(&mut best, &mut candidate);
+                    }
+                }
+                if let Some(data) = data.as_deref_mut() {
+                    data.extend_from_slice(&best);
+                }
+                chosen.0
+            }
+        };
+        filters.push(filter);
+    }
+
+    filters
+}
+
+/// Appends the row of `pixels` starting at `start` to `data`, filtered by
+/// `filter` and prefixed with its type byte.
+fn filter_row(
+    pixels: &[u8],
+    start: usize,
+    row_bytes: usize,
+    bpp: usize,
+    filter: FilterType,
+    data: &mut Vec<u8>,
+) {
+    data.push(filter.value());
+    for (i, &byte) in pixels[start..start + row_bytes].iter().enumerate() {
+        let (a, b, c) = neighbours(pixels, start, row_bytes, bpp, i);
+        data.push(byte.wrapping_sub(filter.predict(a, b, c)));
+    }
+}
+
+/// The sum of a filtered row's bytes read as signed values, so that small
+/// differences either way count as small.
+fn sum_abs(line: &[u8]) -> usize {
+    line[1..]
+        .iter()
+        .map(|&byte| (byte as i8).unsigned_abs() as usize)
+        .sum()
+}
+
+/// The left, above and above-left bytes of byte `i` in the row starting at
+/// `start`, with 0 standing in for anything outside the image.
+fn neighbours(
+    pixels: &[u8],
+    start: usize,
+    row_bytes: usize,
+    bpp: usize,
+    i: usize,
+) -> (u8, u8, u8) {
+    let a: u8 = if i >= bpp { pixels[start + i - bpp] } else { 0 };
+    if start == 0 {
+        return (a, 0, 0);
+    }
+    let above: usize = start - row_bytes;
+    let b: u8 = pixels[above + i];
+    let c: u8 = if i >= bpp { pixels[above + i - bpp] } else { 0 };
+    (a, b, c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROW_BYTES: usize = 12;
+    const BPP: usize = 3;
+
+    fn testing_pixels() -> Vec<u8> {
+        (0..ROW_BYTES * 4)
+            .map(|i| (i * 37 % 251) as u8 ^ (i / 7) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip_every_filter() {
+        let pixels = testing_pixels();
+        for filter_type in FilterType::ALL {
+            let filters = vec![filter_type; 4];
+            let data = filter(&pixels, ROW_BYTES, BPP, &filters);
+            assert_eq!(data.len(), 4 * (ROW_BYTES + 1));
+            assert_eq!(data[0], filter_type.value());
+
+            let (unfiltered, found) = unfilter(&data, ROW_BYTES, BPP).unwrap();
+            assert_eq!(unfiltered, pixels, "{}", filter_type);
+            assert_eq!(found, filters);
+        }
+    }
+
+    #[test]
+    fn test_mixed_filters() {
+        let pixels = testing_pixels();
+        let filters = [
+            FilterType::Paeth,
+            FilterType::Sub,
+            FilterType::Average,
+            FilterType::Up,
+        ];
+        let data = filter(&pixels, ROW_BYTES, BPP, &filters);
+
+        assert_eq!(
+            unfilter(&data, ROW_BYTES, BPP).unwrap(),
+            (pixels, filters.to_vec())
+        );
+    }
+
+    #[test]
+    fn test_every_bit_depth_and_color_type() {
+        use crate::header::Interlace;
+
+        for color_type in [
+            ColorType::Grayscale,
+            ColorType::Rgb,
+            ColorType::Indexed,
+            ColorType::GrayscaleAlpha,
+            ColorType::Rgba,
+        ] {
+            for &bit_depth in color_type.allowed_bit_depths() {
+                let header = ImageHeader::new(
+                    13,
+                    5,
+                    bit_depth,
+                    color_type,
+                    Interlace::None,
+                )
+                .unwrap();
+                let (row_bytes, bpp) =
+                    (header.row_bytes(), header.bytes_per_pixel());
+                let pixels: Vec<u8> = (0..row_bytes * 5)
+                    .map(|i| (i * 97 % 256) as u8 ^ (i / row_bytes) as u8)
+                    .collect();
+
+                for filter_type in FilterType::ALL {
+                    let filters = vec![filter_type; 5];
+                    let data = filter(&pixels, row_bytes, bpp, &filters);
+                    assert_eq!(
+                        unfilter(&data, row_bytes, bpp).unwrap(),
+                        (pixels.clone(), filters),
+                        "{:?} {} {}",
+                        color_type,
+                        bit_depth,
+                        filter_type
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_strategies_round_trip() {
+        let pixels = testing_pixels();
+        for strategy in [
+            FilterStrategy::Fixed(FilterType::Average),
+            FilterStrategy::MinSumAbs,
+            FilterStrategy::BruteForce,
+        ] {
+            let (data, filters) =
+                filter_with(&pixels, ROW_BYTES, BPP, strategy);
+            assert_eq!(data, filter(&pixels, ROW_BYTES, BPP, &filters));
+            assert_eq!(
+                choose_filters(&pixels, ROW_BYTES, BPP, strategy),
+                filters
+            );
+            assert_eq!(
+                unfilter(&data, ROW_BYTES, BPP).unwrap(),
+                (pixels.clone(), filters),
+                "{:?}",
+                strategy
+            );
+        }
+
+        let (_, filters) = filter_with(
+            &pixels,
+            ROW_BYTES,
+            BPP,
+            FilterStrategy::Fixed(FilterType::Average),
+        );
+        assert_eq!(filters, vec![FilterType::Average; 4]);
+    }
+
+    #[test]
+    fn test_min_sum_abs() {
+        // A horizontal ramp, then the same row again, then a constant row
+        // far from zero.
+        let ramp: Vec<u8> = (0..ROW_BYTES as u8).map(|i| i * 10).collect();
+        let pixels: Vec<u8> =
+            [ramp.clone(), ramp, vec![200; ROW_BYTES]].concat();
+        let (_, filters) =
+            filter_with(&pixels, ROW_BYTES, 1, FilterStrategy::MinSumAbs);
+
+        assert_eq!(filters, [FilterType::Sub, FilterType::Up, FilterType::Sub]);
+    }
+
+    #[test]
+    fn test_brute_force() {
+        let pixels = testing_pixels();
+        let (data, _) =
+            filter_with(&pixels, ROW_BYTES, BPP, FilterStrategy::BruteForce);
+
+        for (row, line) in data.chunks(ROW_BYTES + 1).enumerate() {
+            let smallest = FilterType::ALL
+                .into_iter()
+                .map(|filter_type| {
+                    let mut candidate = Vec::new();
+                    filter_row(
+                        &pixels,
+                        row * ROW_BYTES,
+                        ROW_BYTES,
+                        BPP,
+                        filter_type,
+                        &mut candidate,
+                    );
+                    zlib::deflate(&candidate).len()
+                })
+                .min()
+                .unwrap();
+            assert_eq!(zlib::deflate(line).len(), smallest);
+        }
+    }
+
+    #[test]
+    fn test_recommended() {
+        use crate::header::Interlace;
+
+        let header = |bit_depth, color_type| {
+            ImageHeader::new(1, 1, bit_depth, color_type, Interlace::None)
+                .unwrap()
+        };
+        assert_eq!(
+            FilterStrategy::recommended(&header(8, ColorType::Indexed)),
+            FilterStrategy::Fixed(FilterType::None)
+        );
+        assert_eq!(
+            FilterStrategy::recommended(&header(4, ColorType::Grayscale)),
+            FilterStrategy::Fixed(FilterType::None)
+        );
+        assert_eq!(
+            FilterStrategy::recommended(&header(16, ColorType::Rgba)),
+            FilterStrategy::MinSumAbs
+        );
+    }
+
+    #[test]
+    fn test_known_values() {
+        // A 2x2 single-byte image: Sub on the first row, Up on the second.
+        let data = [1, 10, 5, 2, 1, 1];
+        let (pixels, _) = unfilter(&data, 2, 1).unwrap();
+        assert_eq!(pixels, [10, 15, 11, 16]);
+
+        assert_eq!(paeth(10, 20, 15), 15);
+        assert_eq!(paeth(10, 20, 10), 20);
+        assert_eq!(paeth(20, 10, 10), 20);
+    }
+
+    #[test]
+    fn test_invalid_data() {
+        assert!(matches!(
+            unfilter(&[5, 0, 0], 2, 1),
+            Err(Error::InvalidFilterType { row: 0, value: 5 })
+        ));
+        assert!(matches!(
+            unfilter(&[0, 0, 0, 0], 2, 1),
+            Err(Error::InvalidImageData { .. })
+        ));
+    }
+}
@@ -0,0 +1,361 @@
+use crate::{
+    chunk::{read_u32, Chunk},
+    Error, Result,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+};
+
+/// Marks chunk data as one piece of a payload split across chunks.
+pub const MAGIC: [u8; 4] = *b"PGMS";
+pub const VERSION: u8 = 1;
+
+const DIGEST_SIZE: usize = 32;
+const HEADER_SIZE: usize = MAGIC.len() + 1 + DIGEST_SIZE + 4 + 4;
+
+/// One piece of a payload too big, or deliberately too big, for a single
+/// chunk.
+///
+/// Every fragment carries the SHA-256 digest of the whole payload, its own
+/// position and the total count, so the pieces can be put back together
+/// wherever they sit in the file, and fragments of different payloads
+/// sharing a chunk type are kept apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fragment {
+    digest: [u8; DIGEST_SIZE],
+    index: u32,
+    total: u32,
+    data: Vec<u8>,
+}
+
+impl Fragment {
+    /// The most payload bytes a single fragment chunk can hold.
+    pub const MAX_SIZE: usize = Chunk::MAX_LENGTH as usize - HEADER_SIZE;
+
+    /// Position of this fragment, starting from 0.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+    /// SHA-256 digest of the whole payload.
+    pub fn digest(&self) -> &[u8; DIGEST_SIZE] {
+        &self.digest
+    }
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> =
+            Vec::with_capacity(HEADER_SIZE + self.data.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.digest);
+        bytes.extend_from_slice(&self.index.to_be_bytes());
+        bytes.extend_from_slice(&self.total.to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for Fragment {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        if !is_fragment(value) || value.len() < HEADER_SIZE {
+            return Err(Error::InvalidFragment {
+                reason: "data is not a payload fragment",
+            });
+        }
+
+        if value[4] != VERSION {
+            return Err(Error::InvalidFragment {
+                reason: "unsupported fragment version",
+            });
+        }
+
+        let digest: [u8; DIGEST_SIZE] =
+            value[5..5 + DIGEST_SIZE].try_into().unwrap();
+        let index: u32 = read_u32(&value[37..41]);
+        let total: u32 = read_u32(&value[41..45]);
+
+        if index >= total {
+            return Err(Error::InvalidFragment {
+                reason: "fragment index is past the total count",
+            });
+        }
+
+        Ok(Fragment {
+            digest,
+            index,
+            total,
+            data: value[HEADER_SIZE..].to_vec(),
+        })
+    }
+}
+
+impl Display for Fragment {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "fragment {} of {} ({} bytes, payload {})",
+            self.index + 1,
+            self.total,
+            self.data.len(),
+            hex::encode(&self.digest[..4])
+        )
+    }
+}
+
+/// Returns `true` if `data` starts like a [`Fragment`].
+pub fn is_fragment(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Splits `payload` into fragments of at most `size` bytes each. An empty
+/// payload still gives one (empty) fragment.
+pub fn split(payload: &[u8], size: usize) -> Result<Vec<Fragment>> {
+    if size == 0 || size > Fragment::MAX_SIZE {
+        return Err(Error::InvalidFragment {
+            reason: "fragment size must be between 1 and 2^31-46 bytes",
+        });
+    }
+
+    let total: u32 = u32::try_from(payload.len().div_ceil(size).max(1))
+        .map_err(|_| Error::InvalidFragment {
+            reason: "payload needs too many fragments",
+        })?;
+    let digest: [u8; DIGEST_SIZE] = Sha256::digest(payload).into();
+    let pieces: Vec<&[u8]> = if payload.is_empty() {
+        vec![payload]
+    } else {
+        payload.chunks(size).collect()
+    };
+
+    Ok(pieces
+        .into_iter()
+        .enumerate()
+        .map(|(index, data)| Fragment {
+            digest,
+            index: index as u32,
+            total,
+            data: data.to_vec(),
+        })
+        .collect())
+}
+
+/// Collects fragments in any order and puts each payload back together.
+#[derive(Debug, Default, Clone)]
+pub struct Reassembler {
+    payloads: Vec<Partial>,
+}
+
+#[derive(Debug, Clone)]
+struct Partial {
+    digest: [u8; DIGEST_SIZE],
+    total: u32,
+    // Keyed by index rather than preallocated, since `total` comes from the
+    // file and may be absurd.
+    pieces: BTreeMap<u32, Vec<u8>>,
+}
+
+impl Reassembler {
+    pub fn new() -> Reassembler {
+        Reassembler::default()
+    }
+
+    /// Adds a fragment. A repeated fragment is ignored if it matches the
+    /// first copy, and refused otherwise.
+    pub fn push(&mut self, fragment: Fragment) -> Result<()> {
+        let position: usize = match self
+            .payloads
+            .iter()
+            .position(|partial| partial.digest == fragment.digest)
+        {
+            Some(position) => position,
+            None => {
+                self.payloads.push(Partial {
+                    digest: fragment.digest,
+                    total: fragment.total,
+                    pieces: BTreeMap::new(),
+                });
+                self.payloads.len() - 1
+            }
+        };
+        let partial: &mut Partial = &mut self.payloads[position];
+
+        if partial.total != fragment.total {
+            return Err(Error::InvalidFragment {
+                reason: "fragments disagree on the total count",
+            });
+        }
+
+        match partial.pieces.get(&fragment.index) {
+            Some(data) if *data != fragment.data => {
+                Err(Error::InvalidFragment {
+                    reason: "two copies of a fragment differ",
+                })
+            }
+            Some(_) => Ok(()),
+            None => {
+                partial.pieces.insert(fragment.index, fragment.data);
+                Ok(())
+            }
+        }
+    }
+
+    /// Returns `true` if no fragment has been pushed.
+    pub fn is_empty(&self) -> bool {
+        self.payloads.is_empty()
+    }
+
+    /// Returns every payload in the order its first fragment was pushed,
+    /// failing on the first one that is incomplete or does not match its
+    /// digest.
+    pub fn finish(self) -> Result<Vec<Vec<u8>>> {
+        self.payloads
+            .into_iter()
+            .map(|mut partial| {
+                let mut payload: Vec<u8> = Vec::new();
+                for index in 0..partial.total {
+                    match partial.pieces.remove(&index) {
+                        Some(data) => payload.extend_from_slice(&data),
+                        None => {
+                            return Err(Error::MissingFragment {
+                                index,
+                                total: partial.total,
+                            })
+                        }
+                    }
+                }
+
+                if <[u8; DIGEST_SIZE]>::from(Sha256::digest(&payload))
+                    != partial.digest
+                {
+                    return Err(Error::InvalidFragment {
+                        reason: "reassembled payload does not match its digest",
+                    });
+                }
+                Ok(payload)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_payload() -> Vec<u8> {
+        (0..=255u8).cycle().take(1000).collect()
+    }
+
+    fn round_trip(fragments: Vec<Fragment>) -> Vec<Fragment> {
+        fragments
+            .iter()
+            .map(|f| Fragment::try_from(f.as_bytes().as_slice()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let payload = testing_payload();
+        let fragments = round_trip(split(&payload, 300).unwrap());
+
+        assert_eq!(fragments.len(), 4);
+        assert!(fragments.iter().all(|f| f.total() == 4));
+        assert_eq!(fragments[3].data().len(), 100);
+
+        let mut reassembler = Reassembler::new();
+        for fragment in fragments.into_iter().rev() {
+            reassembler.push(fragment).unwrap();
+        }
+        assert_eq!(reassembler.finish().unwrap(), vec![payload]);
+    }
+
+    #[test]
+    fn test_separate_payloads() {
+        let mut reassembler = Reassembler::new();
+        let first = split(b"first payload", 5).unwrap();
+        let second = split(b"second payload", 4).unwrap();
+        for fragment in second.into_iter().chain(first) {
+            reassembler.push(fragment).unwrap();
+        }
+
+        assert_eq!(
+            reassembler.finish().unwrap(),
+            vec![b"second payload".to_vec(), b"first payload".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_missing_fragment() {
+        let mut reassembler = Reassembler::new();
+        for fragment in split(&testing_payload(), 300).unwrap() {
+            if fragment.index() != 2 {
+                reassembler.push(fragment).unwrap();
+            }
+        }
+
+        assert!(matches!(
+            reassembler.finish(),
+            Err(Error::MissingFragment { index: 2, total: 4 })
+        ));
+    }
+
+    #[test]
+    fn test_conflicting_fragments() {
+        let fragments = split(&testing_payload(), 300).unwrap();
+        let mut forged = fragments[1].clone();
+        forged.data[0] ^= 1;
+
+        let mut reassembler = Reassembler::new();
+        reassembler.push(fragments[1].clone()).unwrap();
+        reassembler.push(fragments[1].clone()).unwrap();
+        assert!(reassembler.push(forged.clone()).is_err());
+
+        let mut reassembler = Reassembler::new();
+        for fragment in fragments.into_iter().skip(2) {
+            reassembler.push(fragment).unwrap();
+        }
+        reassembler
+            .push(split(&testing_payload(), 300).unwrap().remove(0))
+            .unwrap();
+        reassembler.push(forged).unwrap();
+        assert!(matches!(
+            reassembler.finish(),
+            Err(Error::InvalidFragment { .. })
+        ));
+    }
+
+    #[test]
+    fn test_empty_payload() {
+        let fragments = split(b"", 10).unwrap();
+        assert_eq!(fragments.len(), 1);
+
+        let mut reassembler = Reassembler::new();
+        reassembler.push(fragments[0].clone()).unwrap();
+        assert_eq!(reassembler.finish().unwrap(), vec![Vec::<u8>::new()]);
+    }
+
+    #[test]
+    fn test_invalid_fragments() {
+        assert!(split(b"payload", 0).is_err());
+
+        let bytes = split(b"payload", 3).unwrap()[0].as_bytes();
+        assert!(Fragment::try_from(&bytes[..HEADER_SIZE - 1]).is_err());
+
+        let mut past_end = bytes.clone();
+        past_end[37..41].copy_from_slice(&3u32.to_be_bytes());
+        assert!(Fragment::try_from(past_end.as_slice()).is_err());
+
+        let mut future = bytes;
+        future[4] = VERSION + 1;
+        assert!(Fragment::try_from(future.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,390 @@
+use crate::{adam7, chunk::Chunk, chunk_type::ChunkType, Error};
+use std::{
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorType {
+    Grayscale,
+    Rgb,
+    Indexed,
+    GrayscaleAlpha,
+    Rgba,
+}
+
+impl ColorType {
+    pub fn value(&self) -> u8 {
+        match self {
+            ColorType::Grayscale => 0,
+            ColorType::Rgb => 2,
+            ColorType::Indexed => 3,
+            ColorType::GrayscaleAlpha => 4,
+            ColorType::Rgba => 6,
+        }
+    }
+    pub fn channels(&self) -> u8 {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+    pub fn has_alpha(&self) -> bool {
+        matches!(self, ColorType::GrayscaleAlpha | ColorType::Rgba)
+    }
+    /// Bit depths the spec allows for this color type.
+    pub fn allowed_bit_depths(&self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => {
+                &[8, 16]
+            }
+        }
+    }
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err(Error::InvalidColorType { value }),
+        }
+    }
+}
+
+impl Display for ColorType {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name: &str = match self {
+            ColorType::Grayscale => "grayscale",
+            ColorType::Rgb => "RGB",
+            ColorType::Indexed => "indexed",
+            ColorType::GrayscaleAlpha => "grayscale+alpha",
+            ColorType::Rgba => "RGBA",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interlace {
+    None,
+    Adam7,
+}
+
+impl Interlace {
+    pub fn value(&self) -> u8 {
+        match self {
+            Interlace::None => 0,
+            Interlace::Adam7 => 1,
+        }
+    }
+}
+
+impl Display for Interlace {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Interlace::None => write!(f, "non-interlaced"),
+            Interlace::Adam7 => write!(f, "Adam7-interlaced"),
+        }
+    }
+}
+
+/// The decoded contents of an IHDR chunk.
+///
+/// Only combinations the spec allows can be constructed, so code working
+/// with an `ImageHeader` does not need to re-check them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: ColorType,
+    interlace: Interlace,
+}
+
+impl ImageHeader {
+    pub const CHUNK_TYPE: [u8; 4] = *b"IHDR";
+    pub const LENGTH: usize = 13;
+    pub const MAX_DIMENSION: u32 = (1 << 31) - 1;
+
+    pub fn new(
+        width: u32,
+        height: u32,
+        bit_depth: u8,
+        color_type: ColorType,
+        interlace: Interlace,
+    ) -> Result<ImageHeader, Error> {
+        if width == 0
+            || height == 0
+            || width > ImageHeader::MAX_DIMENSION
+            || height > ImageHeader::MAX_DIMENSION
+        {
+            return Err(Error::InvalidDimensions { width, height });
+        }
+
+        if !color_type.allowed_bit_depths().contains(&bit_depth) {
+            return Err(Error::InvalidBitDepth {
+                color_type,
+                bit_depth,
+            });
+        }
+
+        Ok(ImageHeader {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlace,
+        })
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+    pub fn color_type(&self) -> ColorType {
+        self.color_type
+    }
+    /// Always 0 (deflate), the only method the spec defines.
+    pub fn compression_method(&self) -> u8 {
+        0
+    }
+    /// Always 0 (adaptive filtering), the only method the spec defines.
+    pub fn filter_method(&self) -> u8 {
+        0
+    }
+    pub fn interlace(&self) -> Interlace {
+        self.interlace
+    }
+    /// Bits in one pixel, across all its channels.
+    pub fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() as usize * self.bit_depth as usize
+    }
+    /// Bytes in one pixel, rounded up to at least 1. This is how far back
+    /// the Sub, Average and Paeth filters look.
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+    /// Bytes in one unfiltered scanline, without the filter type byte.
+    pub fn row_bytes(&self) -> usize {
+        (self.width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+    /// Size of the inflated image data: every scanline plus its filter
+    /// type byte, over all seven passes of an interlaced image. `None` if
+    /// it does not fit in memory.
+    pub fn raw_size(&self) -> Option<usize> {
+        match self.interlace {
+            Interlace::None => (self.width as usize)
+                .checked_mul(self.bits_per_pixel())?
+                .div_ceil(8)
+                .checked_add(1)?
+                .checked_mul(self.height as usize),
+            Interlace::Adam7 => {
+                adam7::passes(self).iter().try_fold(0usize, |size, pass| {
+                    size.checked_add(pass.raw_size()?)
+                })
+            }
+        }
+    }
+    pub fn as_bytes(&self) -> [u8; ImageHeader::LENGTH] {
+        let mut bytes: [u8; ImageHeader::LENGTH] = [0; ImageHeader::LENGTH];
+        bytes[0..4].copy_from_slice(&self.width.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.height.to_be_bytes());
+        bytes[8] = self.bit_depth;
+        bytes[9] = self.color_type.value();
+        bytes[10] = self.compression_method();
+        bytes[11] = self.filter_method();
+        bytes[12] = self.interlace.value();
+        bytes
+    }
+    /// The same header with another interlace method.
+    pub fn with_interlace(&self, interlace: Interlace) -> ImageHeader {
+        ImageHeader { interlace, ..*self }
+    }
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(
+            ChunkType::try_from(ImageHeader::CHUNK_TYPE).unwrap(),
+            self.as_bytes().to_vec(),
+        )
+    }
+}
+
+impl TryFrom<&[u8]> for ImageHeader {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        if value.len() != ImageHeader::LENGTH {
+            return Err(Error::ImageHeaderLength {
+                length: value.len(),
+            });
+        }
+
+        let width: u32 =
+            u32::from_be_bytes([value[0], value[1], value[2], value[3]]);
+        let height: u32 =
+            u32::from_be_bytes([value[4], value[5], value[6], value[7]]);
+        let bit_depth: u8 = value[8];
+        let color_type: ColorType = ColorType::try_from(value[9])?;
+
+        if value[10] != 0 {
+            return Err(Error::InvalidCompressionMethod { value: value[10] });
+        }
+
+        if value[11] != 0 {
+            return Err(Error::InvalidFilterMethod { value: value[11] });
+        }
+
+        let interlace: Interlace = match value[12] {
+            0 => Interlace::None,
+            1 => Interlace::Adam7,
+            other => {
+                return Err(Error::InvalidInterlaceMethod { value: other })
+            }
+        };
+
+        ImageHeader::new(width, height, bit_depth, color_type, interlace)
+    }
+}
+
+impl Display for ImageHeader {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}x{}, {}-bit {}, {}",
+            self.width,
+            self.height,
+            self.bit_depth,
+            self.color_type,
+            self.interlace
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_bytes() -> [u8; 13] {
+        [0, 0, 0, 50, 0, 0, 0, 40, 8, 6, 0, 0, 0]
+    }
+
+    #[test]
+    fn test_header_from_bytes() {
+        let header = ImageHeader::try_from(&testing_bytes()[..]).unwrap();
+
+        assert_eq!(header.width(), 50);
+        assert_eq!(header.height(), 40);
+        assert_eq!(header.bit_depth(), 8);
+        assert_eq!(header.color_type(), ColorType::Rgba);
+        assert_eq!(header.interlace(), Interlace::None);
+        assert_eq!(header.as_bytes(), testing_bytes());
+    }
+
+    #[test]
+    fn test_header_display() {
+        let header = ImageHeader::try_from(&testing_bytes()[..]).unwrap();
+        assert_eq!(header.to_string(), "50x40, 8-bit RGBA, non-interlaced");
+    }
+
+    #[test]
+    fn test_illegal_bit_depth() {
+        let mut bytes = testing_bytes();
+        bytes[8] = 4;
+
+        let header = ImageHeader::try_from(&bytes[..]);
+
+        assert!(matches!(
+            header,
+            Err(Error::InvalidBitDepth {
+                color_type: ColorType::Rgba,
+                bit_depth: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn test_legal_bit_depths() {
+        for value in [0, 2, 3, 4, 6] {
+            let color_type = ColorType::try_from(value).unwrap();
+            for bit_depth in [1, 2, 4, 8, 16] {
+                let header = ImageHeader::new(
+                    1,
+                    1,
+                    bit_depth,
+                    color_type,
+                    Interlace::None,
+                );
+                assert_eq!(
+                    header.is_ok(),
+                    color_type.allowed_bit_depths().contains(&bit_depth)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_scanline_sizes() {
+        let header = ImageHeader::try_from(&testing_bytes()[..]).unwrap();
+        assert_eq!(header.bytes_per_pixel(), 4);
+        assert_eq!(header.row_bytes(), 200);
+        assert_eq!(header.raw_size(), Some(201 * 40));
+
+        let header =
+            ImageHeader::new(10, 3, 1, ColorType::Grayscale, Interlace::None)
+                .unwrap();
+        assert_eq!(header.bytes_per_pixel(), 1);
+        assert_eq!(header.row_bytes(), 2);
+        assert_eq!(header.raw_size(), Some(9));
+
+        // Seven passes of 1x1, 1x1, 2x1, 2x2, 4x2, 4x4 and 8x4 pixels.
+        let header =
+            ImageHeader::new(8, 8, 8, ColorType::Grayscale, Interlace::Adam7)
+                .unwrap();
+        assert_eq!(
+            header.raw_size(),
+            Some(2 + 2 + 3 + 2 * 3 + 2 * 5 + 4 * 5 + 4 * 9)
+        );
+    }
+
+    #[test]
+    fn test_invalid_fields() {
+        let mut bytes = testing_bytes();
+        bytes[9] = 5;
+        assert!(matches!(
+            ImageHeader::try_from(&bytes[..]),
+            Err(Error::InvalidColorType { value: 5 })
+        ));
+
+        let mut bytes = testing_bytes();
+        bytes[0..4].copy_from_slice(&[0, 0, 0, 0]);
+        assert!(matches!(
+            ImageHeader::try_from(&bytes[..]),
+            Err(Error::InvalidDimensions { width: 0, .. })
+        ));
+
+        let mut bytes = testing_bytes();
+        bytes[12] = 2;
+        assert!(matches!(
+            ImageHeader::try_from(&bytes[..]),
+            Err(Error::InvalidInterlaceMethod { value: 2 })
+        ));
+
+        assert!(matches!(
+            ImageHeader::try_from(&bytes[..12]),
+            Err(Error::ImageHeaderLength { length: 12 })
+        ));
+    }
+}
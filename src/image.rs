@@ -0,0 +1,590 @@
+use crate::{
+    chunk::Chunk,
+    header::{ColorType, ImageHeader, Interlace},
+    image_data::{RawImage, IDAT_SIZE},
+    png::Png,
+    Error, Result,
+};
+
+/// Ancillary chunks whose contents are tied to the color type or bit depth,
+/// and no longer make sense once the pixels are stored another way.
+const FORMAT_CHUNKS: [[u8; 4]; 3] = [*b"tRNS", *b"bKGD", *b"sBIT"];
+/// The palette and the histogram of its colors, which the spec forbids in
+/// grayscale images.
+const PALETTE_CHUNKS: [[u8; 4]; 2] = [*b"PLTE", *b"hIST"];
+
+/// The samples of one pixel, at the bit depth of its image. Samples of
+/// images with fewer than 16 bits per sample fit in the low bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pixel {
+    Gray(u16),
+    GrayAlpha(u16, u16),
+    Rgb(u16, u16, u16),
+    Rgba(u16, u16, u16, u16),
+    /// An index into the palette.
+    Indexed(u8),
+}
+
+impl Pixel {
+    pub fn color_type(&self) -> ColorType {
+        match self {
+            Pixel::Gray(_) => ColorType::Grayscale,
+            Pixel::GrayAlpha(..) => ColorType::GrayscaleAlpha,
+            Pixel::Rgb(..) => ColorType::Rgb,
+            Pixel::Rgba(..) => ColorType::Rgba,
+            Pixel::Indexed(_) => ColorType::Indexed,
+        }
+    }
+
+    fn samples(&self) -> Vec<u16> {
+        match *self {
+            Pixel::Gray(v) => vec![v],
+            Pixel::GrayAlpha(v, a) => vec![v, a],
+            Pixel::Rgb(r, g, b) => vec![r, g, b],
+            Pixel::Rgba(r, g, b, a) => vec![r, g, b, a],
+            Pixel::Indexed(i) => vec![i as u16],
+        }
+    }
+
+    fn from_samples(color_type: ColorType, samples: &[u16]) -> Pixel {
+        match color_type {
+            ColorType::Grayscale => Pixel::Gray(samples[0]),
+            ColorType::GrayscaleAlpha => {
+                Pixel::GrayAlpha(samples[0], samples[1])
+            }
+            ColorType::Rgb => Pixel::Rgb(samples[0], samples[1], samples[2]),
+            ColorType::Rgba => {
+                Pixel::Rgba(samples[0], samples[1], samples[2], samples[3])
+            }
+            ColorType::Indexed => Pixel::Indexed(samples[0] as u8),
+        }
+    }
+}
+
+/// The colors of a palette image, from its PLTE chunk, with the alpha of
+/// each entry from its tRNS chunk. Entries tRNS leaves out are opaque.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+    alpha: Vec<u8>,
+}
+
+impl Palette {
+    pub const MAX_ENTRIES: usize = 256;
+
+    pub fn new(colors: Vec<[u8; 3]>, alpha: Vec<u8>) -> Result<Palette> {
+        if colors.is_empty() || colors.len() > Palette::MAX_ENTRIES {
+            return Err(Error::InvalidPalette {
+                reason: "a palette holds from 1 to 256 colors",
+            });
+        }
+        if alpha.len() > colors.len() {
+            return Err(Error::InvalidPalette {
+                reason: "tRNS has more entries than the palette",
+            });
+        }
+        Ok(Palette { colors, alpha })
+    }
+
+    /// Reads the palette of `png`, or `None` if it has no PLTE chunk.
+    pub fn from_png(png: &Png) -> Result<Option<Palette>> {
+        let plte: &Chunk = match png.chunk_by_type("PLTE").first() {
+            Some(chunk) => chunk,
+            None => return Ok(None),
+        };
+        if !plte.data().len().is_multiple_of(3) {
+            return Err(Error::InvalidPalette {
+                reason: "PLTE length is not a multiple of 3",
+            });
+        }
+        let colors: Vec<[u8; 3]> = plte
+            .data()
+            .chunks_exact(3)
+            .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+            .collect();
+        let alpha: Vec<u8> = match png.header_info()?.color_type() {
+            ColorType::Indexed => png
+                .chunk_by_type("tRNS")
+                .first()
+                .map(|chunk| chunk.data().to_vec())
+                .unwrap_or_default(),
+            // Other images use tRNS for a single transparent color.
+            _ => Vec::new(),
+        };
+
+        Palette::new(colors, alpha).map(Some)
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+    /// Whether any entry is less than fully opaque.
+    pub fn has_alpha(&self) -> bool {
+        self.alpha.iter().any(|&a| a != u8::MAX)
+    }
+    /// The color and alpha of entry `index`.
+    pub fn rgba(&self, index: u8) -> Option<[u8; 4]> {
+        let [r, g, b] = *self.colors.get(index as usize)?;
+        let a: u8 = self.alpha.get(index as usize).copied().unwrap_or(u8::MAX);
+        Some([r, g, b, a])
+    }
+}
+
+/// The decoded pixels of an image, at full resolution, in the color type
+/// and bit depth it is stored with.
+///
+/// It remembers how the image was stored, so that writing it back with
+/// [`Png::set_image`] keeps the scanline filters and interlacing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageBuffer {
+    raw: RawImage,
+}
+
+impl ImageBuffer {
+    /// A new image with every sample 0.
+    pub fn new(
+        width: u32,
+        height: u32,
+        bit_depth: u8,
+        color_type: ColorType,
+    ) -> Result<ImageBuffer> {
+        let header: ImageHeader = ImageHeader::new(
+            width,
+            height,
+            bit_depth,
+            color_type,
+            Interlace::None,
+        )?;
+        let size: usize = header
+            .row_bytes()
+            .checked_mul(height as usize)
+            .ok_or(Error::UnsupportedImage {
+                reason: "image is too large to decode",
+            })?;
+        Ok(ImageBuffer {
+            raw: RawImage::new(header, vec![0; size])?,
+        })
+    }
+
+    /// Inflates, unfilters and de-interlaces the pixels of `png`.
+    pub fn decode(png: &Png) -> Result<ImageBuffer> {
+        Ok(ImageBuffer {
+            raw: RawImage::decode(png)?,
+        })
+    }
+
+    pub fn header(&self) -> &ImageHeader {
+        self.raw.header()
+    }
+    pub fn width(&self) -> u32 {
+        self.header().width()
+    }
+    pub fn height(&self) -> u32 {
+        self.header().height()
+    }
+    pub fn color_type(&self) -> ColorType {
+        self.header().color_type()
+    }
+    pub fn bit_depth(&self) -> u8 {
+        self.header().bit_depth()
+    }
+    /// The packed rows of samples, as the PNG format lays them out.
+    pub fn as_raw(&self) -> &RawImage {
+        &self.raw
+    }
+
+    /// The pixel at `(x, y)`, or `None` outside the image.
+    pub fn get_pixel(&self, x: u32, y: u32) -> Option<Pixel> {
+        if x >= self.width() || y >= self.height() {
+            return None;
+        }
+        let samples: Vec<u16> = (0..self.channels())
+            .map(|channel| self.sample(x, y, channel))
+            .collect();
+        Some(Pixel::from_samples(self.color_type(), &samples))
+    }
+
+    /// Replaces the pixel at `(x, y)`. It must be of the image's color type,
+    /// with every sample within its bit depth.
+    pub fn set_pixel(&mut self, x: u32, y: u32, pixel: Pixel) -> Result<()> {
+        if x >= self.width() || y >= self.height() {
+            return Err(Error::PixelOutOfBounds { x, y });
+        }
+        if pixel.color_type() != self.color_type() {
+            return Err(Error::InvalidPixel {
+                reason: "pixel is of another color type than the image",
+            });
+        }
+        let max: u32 = (1 << self.bit_depth()) - 1;
+        let samples: Vec<u16> = pixel.samples();
+        if samples.iter().any(|&sample| sample as u32 > max) {
+            return Err(Error::InvalidPixel {
+                reason: "sample does not fit in the image's bit depth",
+            });
+        }
+
+        for (channel, sample) in samples.into_iter().enumerate() {
+            self.set_sample(x, y, channel, sample);
+        }
+        Ok(())
+    }
+
+    /// Looks the indices of a palette image up in `palette`, giving an 8-bit
+    /// RGB image, or RGBA if any entry is transparent.
+    pub fn expand_palette(&self, palette: &Palette) -> Result<ImageBuffer> {
+        if self.color_type() != ColorType::Indexed {
+            return Err(Error::UnsupportedImage {
+                reason: "only palette images can be expanded",
+            });
+        }
+        let color_type: ColorType = if palette.has_alpha() {
+            ColorType::Rgba
+        } else {
+            ColorType::Rgb
+        };
+        let channels: usize = color_type.channels() as usize;
+        let header: ImageHeader = ImageHeader::new(
+            self.width(),
+            self.height(),
+            8,
+            color_type,
+            self.header().interlace(),
+        )?;
+
+        let mut pixels: Vec<u8> =
+            Vec::with_capacity(header.row_bytes() * self.height() as usize);
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let index: u8 = self.sample(x, y, 0) as u8;
+                let rgba: [u8; 4] =
+                    palette.rgba(index).ok_or(Error::InvalidPalette {
+                        reason: "pixel refers to a color past the palette",
+                    })?;
+                pixels.extend_from_slice(&rgba[..channels]);
+            }
+        }
+
+        Ok(ImageBuffer {
+            raw: RawImage::new(header, pixels)?,
+        })
+    }
+
+    fn channels(&self) -> usize {
+        self.color_type().channels() as usize
+    }
+
+    /// Where sample `channel` of pixel `(x, y)` starts, in bits from the
+    /// start of the pixel data.
+    fn bit_offset(&self, x: u32, y: u32, channel: usize) -> usize {
+        let header: &ImageHeader = self.header();
+        y as usize * header.row_bytes() * 8
+            + (x as usize * self.channels() + channel)
+                * header.bit_depth() as usize
+    }
+
+    fn sample(&self, x: u32, y: u32, channel: usize) -> u16 {
+        let bits: usize = self.bit_depth() as usize;
+        let offset: usize = self.bit_offset(x, y, channel);
+        let pixels: &[u8] = self.raw.pixels();
+        match bits {
+            16 => {
+                u16::from_be_bytes([pixels[offset / 8], pixels[offset / 8 + 1]])
+            }
+            8 => pixels[offset / 8] as u16,
+            _ => {
+                let shift: usize = 8 - bits - offset % 8;
+                ((pixels[offset / 8] >> shift) & ((1 << bits) - 1)) as u16
+            }
+        }
+    }
+
+    fn set_sample(&mut self, x: u32, y: u32, channel: usize, sample: u16) {
+        let bits: usize = self.bit_depth() as usize;
+        let offset: usize = self.bit_offset(x, y, channel);
+        let pixels: &mut [u8] = self.raw.pixels_mut();
+        match bits {
+            16 => pixels[offset / 8..offset / 8 + 2]
+                .copy_from_slice(&sample.to_be_bytes()),
+            8 => pixels[offset / 8] = sample as u8,
+            _ => {
+                let shift: usize = 8 - bits - offset % 8;
+                let mask: u8 = ((1 << bits) - 1) << shift;
+                let byte: &mut u8 = &mut pixels[offset / 8];
+                *byte = (*byte & !mask) | ((sample as u8) << shift);
+            }
+        }
+    }
+}
+
+/// Stores `image` as the pixels of `png`, keeping every other chunk. If it
+/// has another size, color type or bit depth, IHDR is rewritten, and the
+/// chunks describing the old format (tRNS, bKGD and sBIT) are dropped,
+/// along with PLTE and hIST if the image becomes grayscale. Nothing is
+/// changed unless the image can be written.
+pub(crate) fn write_image(png: &mut Png, image: &ImageBuffer) -> Result<()> {
+    let old: ImageHeader = png.header_info()?;
+    let new: &ImageHeader = image.header();
+    if new.color_type() == ColorType::Indexed
+        && Palette::from_png(png)?.is_none()
+    {
+        return Err(Error::InvalidPalette {
+            reason: "palette image without a PLTE chunk",
+        });
+    }
+    if png.chunk_by_type("IDAT").is_empty() {
+        return Err(Error::ChunkNotFound {
+            chunk_type: "IDAT".to_string(),
+        });
+    }
+
+    if old.with_interlace(new.interlace()) != *new {
+        if old.color_type() != new.color_type()
+            || old.bit_depth() != new.bit_depth()
+        {
+            let gray: bool = matches!(
+                new.color_type(),
+                ColorType::Grayscale | ColorType::GrayscaleAlpha
+            );
+            let dropped = |bytes: &[u8; 4]| {
+                FORMAT_CHUNKS.contains(bytes)
+                    || (gray && PALETTE_CHUNKS.contains(bytes))
+            };
+            png.retain(|c| !dropped(&c.chunk_type().bytes()));
+        }
+        png.remove_chunk("IHDR")?;
+        png.insert_chunk_at(0, new.to_chunk())?;
+    }
+    image.raw.encode_into_sized(png, IDAT_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunk_type::ChunkType, image_data::testing_png};
+    use std::str::FromStr;
+
+    fn chunk(name: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(name).unwrap(), data.to_vec())
+    }
+
+    fn testing_palette_png() -> Png {
+        // 5x2 pixels of 2-bit indices: 0 1 2 3 0 / 3 2 1 0 3.
+        let header =
+            ImageHeader::new(5, 2, 2, ColorType::Indexed, Interlace::None)
+                .unwrap();
+        let mut png =
+            testing_png(&header, &[0b0001_1011, 0, 0b1110_0100, 0b1100_0000]);
+        png.insert_chunk_at(
+            1,
+            chunk("PLTE", &[255, 0, 0, 0, 255, 0, 0, 0, 255, 9, 9, 9]),
+        )
+        .unwrap();
+        png.insert_chunk_at(2, chunk("tRNS", &[255, 128])).unwrap();
+        png.insert_chunk_at(3, chunk("tEXt", b"Comment\0palette"))
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn test_get_pixel() {
+        let header =
+            ImageHeader::new(2, 2, 16, ColorType::Rgba, Interlace::None)
+                .unwrap();
+        let pixels: Vec<u8> = (0..32).collect();
+        let image =
+            ImageBuffer::decode(&testing_png(&header, &pixels)).unwrap();
+
+        assert_eq!(image.color_type(), ColorType::Rgba);
+        assert_eq!(image.bit_depth(), 16);
+        assert_eq!(
+            image.get_pixel(1, 0),
+            Some(Pixel::Rgba(0x0809, 0x0a0b, 0x0c0d, 0x0e0f))
+        );
+        assert_eq!(image.get_pixel(2, 0), None);
+        assert_eq!(image.get_pixel(0, 2), None);
+
+        let image = ImageBuffer::decode(&testing_palette_png()).unwrap();
+        let row: Vec<Option<Pixel>> =
+            (0..5).map(|x| image.get_pixel(x, 1)).collect();
+        assert_eq!(
+            row,
+            [3, 2, 1, 0, 3].map(|i| Some(Pixel::Indexed(i))).to_vec()
+        );
+    }
+
+    #[test]
+    fn test_set_pixel() {
+        for (color_type, bit_depth, pixel) in [
+            (ColorType::Grayscale, 1, Pixel::Gray(1)),
+            (ColorType::Grayscale, 4, Pixel::Gray(11)),
+            (ColorType::Grayscale, 16, Pixel::Gray(40_000)),
+            (ColorType::GrayscaleAlpha, 8, Pixel::GrayAlpha(7, 200)),
+            (ColorType::Rgb, 8, Pixel::Rgb(1, 2, 3)),
+            (ColorType::Rgba, 16, Pixel::Rgba(1, 300, 3, 65_535)),
+            (ColorType::Indexed, 2, Pixel::Indexed(2)),
+        ] {
+            let mut image =
+                ImageBuffer::new(7, 3, bit_depth, color_type).unwrap();
+            image.set_pixel(5, 1, pixel).unwrap();
+
+            assert_eq!(image.get_pixel(5, 1), Some(pixel));
+            let blank = Pixel::from_samples(color_type, &[0; 4]);
+            for (x, y) in [(4, 1), (6, 1), (5, 0), (5, 2)] {
+                assert_eq!(image.get_pixel(x, y), Some(blank));
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_pixels() {
+        let mut image =
+            ImageBuffer::new(4, 4, 4, ColorType::Grayscale).unwrap();
+
+        assert!(matches!(
+            image.set_pixel(4, 0, Pixel::Gray(1)),
+            Err(Error::PixelOutOfBounds { x: 4, y: 0 })
+        ));
+        assert!(matches!(
+            image.set_pixel(0, 0, Pixel::Gray(16)),
+            Err(Error::InvalidPixel { .. })
+        ));
+        assert!(matches!(
+            image.set_pixel(0, 0, Pixel::Rgb(1, 1, 1)),
+            Err(Error::InvalidPixel { .. })
+        ));
+    }
+
+    #[test]
+    fn test_palette() {
+        let png = testing_palette_png();
+        let palette = Palette::from_png(&png).unwrap().unwrap();
+
+        assert_eq!(palette.len(), 4);
+        assert!(palette.has_alpha());
+        assert_eq!(palette.rgba(0), Some([255, 0, 0, 255]));
+        assert_eq!(palette.rgba(1), Some([0, 255, 0, 128]));
+        assert_eq!(palette.rgba(3), Some([9, 9, 9, 255]));
+        assert_eq!(palette.rgba(4), None);
+
+        let mut png = png;
+        png.remove_chunk("PLTE").unwrap();
+        assert_eq!(Palette::from_png(&png).unwrap(), None);
+        png.insert_chunk_at(1, chunk("PLTE", &[1, 2])).unwrap();
+        assert!(matches!(
+            Palette::from_png(&png),
+            Err(Error::InvalidPalette { .. })
+        ));
+    }
+
+    #[test]
+    fn test_expand_palette() {
+        let png = testing_palette_png();
+        let palette = Palette::from_png(&png).unwrap().unwrap();
+        let image = png.decode_image().unwrap().expand_palette(&palette);
+        let image = image.unwrap();
+
+        assert_eq!(image.color_type(), ColorType::Rgba);
+        assert_eq!(image.bit_depth(), 8);
+        assert_eq!(image.get_pixel(1, 0), Some(Pixel::Rgba(0, 255, 0, 128)));
+        assert_eq!(image.get_pixel(0, 1), Some(Pixel::Rgba(9, 9, 9, 255)));
+
+        let opaque = Palette::new(vec![[1, 2, 3]; 4], vec![255; 4]).unwrap();
+        let image = png.decode_image().unwrap().expand_palette(&opaque);
+        assert_eq!(image.unwrap().color_type(), ColorType::Rgb);
+
+        let short = Palette::new(vec![[1, 2, 3]; 2], Vec::new()).unwrap();
+        assert!(matches!(
+            png.decode_image().unwrap().expand_palette(&short),
+            Err(Error::InvalidPalette { .. })
+        ));
+    }
+
+    #[test]
+    fn test_write_back() {
+        let mut png = testing_palette_png();
+        let mut image = png.decode_image().unwrap();
+        image.set_pixel(4, 1, Pixel::Indexed(1)).unwrap();
+        png.set_image(&image).unwrap();
+
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "PLTE", "tRNS", "tEXt", "IDAT", "IEND"]);
+        assert_eq!(png.decode_image().unwrap(), image);
+    }
+
+    #[test]
+    fn test_write_back_gray() {
+        let mut png = testing_palette_png();
+        png.insert_chunk_before(
+            "tEXt",
+            chunk("hIST", &[0, 1, 0, 2, 0, 3, 0, 4]),
+        )
+        .unwrap();
+        let mut image =
+            ImageBuffer::new(5, 2, 8, ColorType::Grayscale).unwrap();
+        image.set_pixel(1, 0, Pixel::Gray(200)).unwrap();
+        png.set_image(&image).unwrap();
+
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "tEXt", "IDAT", "IEND"]);
+        assert!(png.validate().is_ok());
+        assert_eq!(png.decode_image().unwrap(), image);
+    }
+
+    #[test]
+    fn test_write_back_checks_first() {
+        let image = testing_palette_png().decode_image().unwrap();
+        let header =
+            ImageHeader::new(1, 1, 8, ColorType::Rgb, Interlace::None).unwrap();
+        let mut png = testing_png(&header, &[1, 2, 3]);
+        png.insert_chunk_at(1, chunk("bKGD", &[0, 0, 0, 0, 0, 0]))
+            .unwrap();
+        let before = png.as_bytes().unwrap();
+
+        assert!(matches!(
+            png.set_image(&image),
+            Err(Error::InvalidPalette { .. })
+        ));
+        assert_eq!(png.as_bytes().unwrap(), before);
+
+        png.insert_chunk_at(1, chunk("PLTE", &[1, 2, 3])).unwrap();
+        png.remove_chunk("IDAT").unwrap();
+        let before = png.as_bytes().unwrap();
+        assert!(matches!(
+            png.set_image(&image),
+            Err(Error::ChunkNotFound { .. })
+        ));
+        assert_eq!(png.as_bytes().unwrap(), before);
+    }
+
+    #[test]
+    fn test_write_back_expanded() {
+        let mut png = testing_palette_png();
+        let palette = Palette::from_png(&png).unwrap().unwrap();
+        let image = png.decode_image().unwrap().expand_palette(&palette);
+        let image = image.unwrap();
+        png.set_image(&image).unwrap();
+
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "PLTE", "tEXt", "IDAT", "IEND"]);
+        assert_eq!(png.header_info().unwrap(), *image.header());
+        assert_eq!(
+            png.decode_image().unwrap().get_pixel(1, 0),
+            image.get_pixel(1, 0)
+        );
+    }
+}
@@ -0,0 +1,571 @@
+use crate::{
+    adam7,
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    filter::{self, FilterStrategy, FilterType},
+    header::{ImageHeader, Interlace},
+    png::Png,
+    zlib, Error, Result,
+};
+use std::str::FromStr;
+
+/// How much image data goes into each IDAT chunk written back, the same
+/// default libpng uses.
+pub const IDAT_SIZE: usize = 8192;
+
+/// The unfiltered pixel rows of an image at full resolution, along with
+/// the filter each stored scanline used so it can be written back the same
+/// way. The filters of an interlaced image run through the scanlines of
+/// all its passes in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawImage {
+    header: ImageHeader,
+    pixels: Vec<u8>,
+    filters: Vec<FilterType>,
+}
+
+impl RawImage {
+    /// Wraps full-resolution unfiltered `pixels`, with filters picked the
+    /// way the PNG spec recommends for this kind of image.
+    pub fn new(header: ImageHeader, pixels: Vec<u8>) -> Result<RawImage> {
+        if header.row_bytes().checked_mul(header.height() as usize)
+            != Some(pixels.len())
+        {
+            return Err(Error::InvalidImageData {
+                reason: "pixel buffer does not match the image size",
+            });
+        }
+        let mut image: RawImage = RawImage {
+            header,
+            pixels,
+            filters: Vec::new(),
+        };
+        image.refilter(FilterStrategy::recommended(&header));
+        Ok(image)
+    }
+
+    /// Inflates and unfilters the IDAT chunks of `png`, de-interlacing
+    /// them if the image is interlaced.
+    pub fn decode(png: &Png) -> Result<RawImage> {
+        let header: ImageHeader = png.header_info()?;
+        let data: Vec<u8> = read_scanlines(png)?;
+        let (pixels, filters) = match header.interlace() {
+            Interlace::None => filter::unfilter(
+                &data,
+                header.row_bytes(),
+                header.bytes_per_pixel(),
+            )?,
+            Interlace::Adam7 => deinterlace(&header, &data)?,
+        };
+
+        Ok(RawImage {
+            header,
+            pixels,
+            filters,
+        })
+    }
+
+    pub fn header(&self) -> &ImageHeader {
+        &self.header
+    }
+    /// Every scanline of the full image, without filter type bytes, packed
+    /// back to back.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+    pub fn pixels_mut(&mut self) -> &mut [u8] {
+        &mut self.pixels
+    }
+    pub fn filters(&self) -> &[FilterType] {
+        &self.filters
+    }
+
+    /// Picks new filters for the stored scanlines by `strategy`, for the
+    /// next time the image is encoded. Decoding keeps the filters the image
+    /// was stored with.
+    pub fn refilter(&mut self, strategy: FilterStrategy) {
+        let bpp: usize = self.header.bytes_per_pixel();
+        self.filters = match self.header.interlace() {
+            Interlace::None => {
+                let row_bytes: usize = self.header.row_bytes();
+                filter::choose_filters(&self.pixels, row_bytes, bpp, strategy)
+            }
+            Interlace::Adam7 => adam7::passes(&self.header)
+                .iter()
+                .flat_map(|pass| {
+                    let reduced: Vec<u8> =
+                        adam7::extract(&self.header, &self.pixels, pass);
+                    filter::choose_filters(
+                        &reduced,
+                        pass.row_bytes(),
+                        bpp,
+                        strategy,
+                    )
+                })
+                .collect(),
+        };
+    }
+
+    /// Switches the image to be stored interlaced or not the next time it
+    /// is encoded. The scanlines change, so new filters are picked the way
+    /// [`RawImage::new`] does.
+    pub fn set_interlace(&mut self, interlace: Interlace) {
+        if interlace != self.header.interlace() {
+            self.header = self.header.with_interlace(interlace);
+            self.refilter(FilterStrategy::recommended(&self.header));
+        }
+    }
+
+    /// Refilters and deflates the pixels, replacing the IDAT chunks of
+    /// `png` with new ones of [`IDAT_SIZE`] bytes in the place of the first.
+    pub fn encode_into(&self, png: &mut Png) -> Result<()> {
+        self.encode_into_sized(png, IDAT_SIZE)
+    }
+
+    /// Like [`RawImage::encode_into`], splitting the compressed data into
+    /// IDAT chunks of `idat_size` bytes. IHDR is rewritten if the image is
+    /// to be stored with another interlace method.
+    pub fn encode_into_sized(
+        &self,
+        png: &mut Png,
+        idat_size: usize,
+    ) -> Result<()> {
+        let header: ImageHeader = png.header_info()?;
+        if header.with_interlace(self.header.interlace()) != self.header {
+            return Err(Error::InvalidImageData {
+                reason: "pixels are for an image of another size or format",
+            });
+        }
+        if header != self.header {
+            png.remove_chunk("IHDR")?;
+            png.insert_chunk_at(0, self.header.to_chunk())?;
+        }
+        write_scanlines(png, &self.scanlines(), idat_size)
+    }
+
+    /// The filtered scanlines, pass after pass for an interlaced image.
+    fn scanlines(&self) -> Vec<u8> {
+        let bpp: usize = self.header.bytes_per_pixel();
+        match self.header.interlace() {
+            Interlace::None => filter::filter(
+                &self.pixels,
+                self.header.row_bytes(),
+                bpp,
+                &self.filters,
+            ),
+            Interlace::Adam7 => {
+                let mut data: Vec<u8> = Vec::new();
+                let mut filters: &[FilterType] = &self.filters;
+                for pass in adam7::passes(&self.header) {
+                    let reduced: Vec<u8> =
+                        adam7::extract(&self.header, &self.pixels, &pass);
+                    let (own, rest) = filters.split_at(pass.height() as usize);
+                    data.extend(filter::filter(
+                        &reduced,
+                        pass.row_bytes(),
+                        bpp,
+                        own,
+                    ));
+                    filters = rest;
+                }
+                data
+            }
+        }
+    }
+}
+
+/// Unfilters the scanlines of each Adam7 pass and scatters their pixels
+/// into a full-resolution image.
+fn deinterlace(
+    header: &ImageHeader,
+    data: &[u8],
+) -> Result<(Vec<u8>, Vec<FilterType>)> {
+    let mut pixels: Vec<u8> =
+        vec![0; header.row_bytes() * header.height() as usize];
+    let mut filters: Vec<FilterType> = Vec::new();
+    let mut offset: usize = 0;
+
+    for pass in adam7::passes(header) {
+        let size: usize = (pass.row_bytes() + 1) * pass.height() as usize;
+        let rows_before: usize = filters.len();
+        let (reduced, pass_filters) = filter::unfilter(
+            &data[offset..offset + size],
+            pass.row_bytes(),
+            header.bytes_per_pixel(),
+        )
+        .map_err(|e| match e {
+            Error::InvalidFilterType { row, value } => {
+                Error::InvalidFilterType {
+                    row: rows_before + row,
+                    value,
+                }
+            }
+            e => e,
+        })?;
+        adam7::insert(header, &mut pixels, &pass, &reduced);
+        filters.extend(pass_filters);
+        offset += size;
+    }
+
+    Ok((pixels, filters))
+}
+
+/// Concatenates the IDAT chunks of `png` and inflates them into the
+/// filtered scanlines, each still preceded by its filter type byte.
+///
+/// Refuses data that does not come out at exactly the size the image
+/// header implies.
+pub fn read_scanlines(png: &Png) -> Result<Vec<u8>> {
+    let header: ImageHeader = png.header_info()?;
+    let expected: usize = header.raw_size().ok_or(Error::UnsupportedImage {
+        reason: "image is too large to decode",
+    })?;
+
+    let compressed: Vec<u8> = png
+        .chunks()
+        .iter()
+        .filter(|c| c.chunk_type().bytes() == *b"IDAT")
+        .flat_map(|c| c.data().iter().copied())
+        .collect();
+    if compressed.is_empty() {
+        return Err(Error::InvalidImageData {
+            reason: "there are no IDAT chunks",
+        });
+    }
+
+    let data: Vec<u8> =
+        zlib::inflate(&compressed, expected).map_err(|e| match e {
+            Error::DecompressedTooLarge { .. } => Error::InvalidImageData {
+                reason: "size does not match the image header",
+            },
+            e => e,
+        })?;
+    if data.len() != expected {
+        return Err(Error::InvalidImageData {
+            reason: "size does not match the image header",
+        });
+    }
+
+    Ok(data)
+}
+
+/// Deflates filtered `scanlines` and replaces the IDAT chunks of `png`
+/// with chunks of at most `idat_size` bytes, in the place of the first.
+pub fn write_scanlines(
+    png: &mut Png,
+    scanlines: &[u8],
+    idat_size: usize,
+) -> Result<()> {
+    if idat_size == 0 || idat_size > Chunk::MAX_LENGTH as usize {
+        return Err(Error::InvalidIdatSize { size: idat_size });
+    }
+    let header: ImageHeader = png.header_info()?;
+    if header.raw_size() != Some(scanlines.len()) {
+        return Err(Error::InvalidImageData {
+            reason: "size does not match the image header",
+        });
+    }
+    let index: usize = png
+        .chunks()
+        .iter()
+        .position(|c| c.chunk_type().bytes() == *b"IDAT")
+        .ok_or_else(|| Error::ChunkNotFound {
+            chunk_type: "IDAT".to_string(),
+        })?;
+
+    let compressed: Vec<u8> = zlib::deflate(scanlines);
+    png.retain(|c| c.chunk_type().bytes() != *b"IDAT");
+    let chunk_type: ChunkType = ChunkType::from_str("IDAT")?;
+    for (offset, data) in compressed.chunks(idat_size).enumerate() {
+        png.insert_chunk_at(
+            index + offset,
+            Chunk::new(chunk_type, data.to_vec()),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Builds an image from full-resolution unfiltered `pixels`, each scanline
+/// stored with the None filter, for tests elsewhere in the crate.
+#[cfg(test)]
+pub(crate) fn testing_png(header: &ImageHeader, pixels: &[u8]) -> Png {
+    let mut image: RawImage = RawImage::new(*header, pixels.to_vec()).unwrap();
+    image.refilter(FilterStrategy::Fixed(FilterType::None));
+    let chunk = |name: &str, data: Vec<u8>| {
+        Chunk::new(ChunkType::from_str(name).unwrap(), data)
+    };
+
+    Png::from_chunks(vec![
+        header.to_chunk(),
+        chunk("IDAT", zlib::deflate(&image.scanlines())),
+        chunk("IEND", Vec::new()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::ColorType;
+
+    fn testing_header() -> ImageHeader {
+        ImageHeader::new(50, 40, 8, ColorType::Rgb, Interlace::None).unwrap()
+    }
+
+    fn testing_pixels() -> Vec<u8> {
+        (0..50 * 40 * 3).map(|i| (i % 256) as u8).collect()
+    }
+
+    #[test]
+    fn test_decode() {
+        let png = testing_png(&testing_header(), &testing_pixels());
+        let image = RawImage::decode(&png).unwrap();
+
+        assert_eq!(image.pixels(), testing_pixels().as_slice());
+        assert_eq!(image.filters(), vec![FilterType::None; 40].as_slice());
+    }
+
+    #[test]
+    fn test_encode_keeps_other_chunks() {
+        let mut png = testing_png(&testing_header(), &testing_pixels());
+        png.insert_chunk_before(
+            "IDAT",
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hi".to_vec()),
+        )
+        .unwrap();
+
+        let mut image = RawImage::decode(&png).unwrap();
+        image.pixels_mut()[0] = 255;
+        image.encode_into(&mut png).unwrap();
+
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "ruSt", "IDAT", "IEND"]);
+        assert_eq!(RawImage::decode(&png).unwrap().pixels()[0], 255);
+    }
+
+    #[test]
+    fn test_split_idat() {
+        let mut png = testing_png(&testing_header(), &testing_pixels());
+        let mut compressed: Vec<u8> = Vec::new();
+        for chunk in png.remove_all("IDAT").unwrap() {
+            compressed.extend_from_slice(chunk.data());
+        }
+        for piece in compressed.chunks(10) {
+            png.insert_chunk(Chunk::new(
+                ChunkType::from_str("IDAT").unwrap(),
+                piece.to_vec(),
+            ));
+        }
+
+        let image = RawImage::decode(&png).unwrap();
+        assert_eq!(image.pixels(), testing_pixels().as_slice());
+    }
+
+    #[test]
+    fn test_refilter() {
+        let mut png = testing_png(&testing_header(), &testing_pixels());
+        let before = png.image_data().unwrap();
+        let mut image = RawImage::decode(&png).unwrap();
+        image.refilter(FilterStrategy::MinSumAbs);
+        assert!(image.filters().iter().any(|&f| f != FilterType::None));
+
+        image.encode_into(&mut png).unwrap();
+        let decoded = RawImage::decode(&png).unwrap();
+        assert_eq!(decoded, image);
+        assert!(
+            zlib::deflate(&png.image_data().unwrap()).len()
+                < zlib::deflate(&before).len()
+        );
+    }
+
+    #[test]
+    fn test_idat_size() {
+        let mut png = testing_png(&testing_header(), &testing_pixels());
+        let image = RawImage::decode(&png).unwrap();
+        image.encode_into_sized(&mut png, 100).unwrap();
+
+        let sizes: Vec<u32> = png
+            .chunk_by_type("IDAT")
+            .iter()
+            .map(|c| c.length())
+            .collect();
+        assert!(sizes.len() > 1);
+        assert!(sizes[..sizes.len() - 1].iter().all(|&size| size == 100));
+        assert_eq!(RawImage::decode(&png).unwrap(), image);
+
+        for size in [0, Chunk::MAX_LENGTH as usize + 1] {
+            assert!(matches!(
+                image.encode_into_sized(&mut png, size),
+                Err(Error::InvalidIdatSize { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let mut png = testing_png(&testing_header(), &testing_pixels());
+        let mut idat = png.remove_chunk("IDAT").unwrap().data().to_vec();
+        let last = idat.len() - 1;
+        idat[last] ^= 0x40;
+        png.insert_chunk_before(
+            "IEND",
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), idat),
+        )
+        .unwrap();
+
+        assert!(matches!(
+            read_scanlines(&png),
+            Err(Error::InvalidZlibStream {
+                reason: "Adler-32 checksum does not match"
+            })
+        ));
+    }
+
+    #[test]
+    fn test_wrong_size() {
+        let header =
+            ImageHeader::new(50, 41, 8, ColorType::Rgb, Interlace::None)
+                .unwrap();
+        let mut png = testing_png(&testing_header(), &testing_pixels());
+        png.remove_chunk("IHDR").unwrap();
+        png.insert_chunk_at(0, header.to_chunk()).unwrap();
+
+        assert!(matches!(
+            RawImage::decode(&png),
+            Err(Error::InvalidImageData { .. })
+        ));
+    }
+
+    // A 9x7 8-bit RGB image written interlaced by another encoder, every
+    // scanline with the Sub filter. Pixel (x, y) is [20x, 30y, 7xy].
+    const INTERLACED_RGB: [u8; 154] = [
+        137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0,
+        9, 0, 0, 0, 7, 8, 2, 0, 0, 1, 34, 254, 192, 161, 0, 0, 0, 97, 73, 68,
+        65, 84, 120, 218, 109, 203, 173, 13, 128, 48, 16, 134, 225, 247, 83,
+        56, 212, 225, 8, 6, 211, 48, 65, 69, 135, 64, 160, 152, 2, 217, 73,
+        208, 12, 193, 128, 28, 129, 180, 166, 201, 115, 185, 124, 247, 35, 224,
+        2, 173, 94, 100, 86, 14, 167, 0, 239, 32, 228, 248, 38, 18, 129, 177,
+        16, 183, 231, 173, 144, 225, 249, 39, 75, 125, 189, 180, 236, 45, 126,
+        100, 247, 82, 127, 152, 48, 186, 38, 177, 251, 110, 104, 18, 167, 239,
+        230, 166, 7, 244, 185, 19, 193, 60, 15, 4, 206, 0, 0, 0, 0, 73, 69, 78,
+        68, 174, 66, 96, 130,
+    ];
+
+    // An 11x5 1-bit grayscale image written interlaced by another encoder.
+    // Pixel (x, y) is 1 where x + y is a multiple of 3, and the low bit of
+    // x(y + 1) elsewhere.
+    const INTERLACED_GRAY: [u8; 85] = [
+        137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0,
+        11, 0, 0, 0, 5, 1, 0, 0, 0, 1, 204, 218, 27, 109, 0, 0, 0, 28, 73, 68,
+        65, 84, 120, 218, 99, 104, 96, 96, 96, 80, 96, 112, 96, 104, 96, 80,
+        97, 248, 1, 134, 42, 13, 12, 147, 28, 0, 57, 204, 5, 227, 135, 161,
+        129, 202, 0, 0, 0, 0, 73, 69, 78, 68, 174, 66, 96, 130,
+    ];
+
+    fn read_fixture(bytes: &[u8]) -> Png {
+        Png::try_from(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_decode_interlaced() {
+        let image = RawImage::decode(&read_fixture(&INTERLACED_RGB)).unwrap();
+        let expected: Vec<u8> = (0..7u32)
+            .flat_map(|y| {
+                (0..9u32).flat_map(move |x| [x * 20, y * 30, x * y * 7])
+            })
+            .map(|sample| sample as u8)
+            .collect();
+        assert_eq!(image.pixels(), expected.as_slice());
+        // 1 + 1 + 1 + 2 + 2 + 4 + 3 scanlines over the seven passes.
+        assert_eq!(image.filters(), vec![FilterType::Sub; 14].as_slice());
+
+        let image = RawImage::decode(&read_fixture(&INTERLACED_GRAY)).unwrap();
+        for y in 0..5 {
+            for x in 0..11 {
+                let byte = image.pixels()[y * 2 + x / 8];
+                let bit = byte >> (7 - x % 8) & 1;
+                let expected =
+                    if (x + y) % 3 == 0 { 1 } else { x * (y + 1) % 2 };
+                assert_eq!(bit as usize, expected, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_interlaced_round_trip() {
+        for fixture in [&INTERLACED_RGB[..], &INTERLACED_GRAY[..]] {
+            let mut png = read_fixture(fixture);
+            let image = RawImage::decode(&png).unwrap();
+            image.encode_into(&mut png).unwrap();
+            assert_eq!(
+                png.image_data().unwrap().len(),
+                image.header().raw_size().unwrap()
+            );
+            assert_eq!(RawImage::decode(&png).unwrap(), image);
+        }
+    }
+
+    #[test]
+    fn test_set_interlace() {
+        let mut png = read_fixture(&INTERLACED_RGB);
+        let interlaced = RawImage::decode(&png).unwrap();
+
+        let mut image = interlaced.clone();
+        image.set_interlace(Interlace::None);
+        image.encode_into(&mut png).unwrap();
+        assert_eq!(png.header_info().unwrap().interlace(), Interlace::None);
+        let plain = RawImage::decode(&png).unwrap();
+        assert_eq!(plain.pixels(), interlaced.pixels());
+        assert_eq!(plain.filters().len(), 7);
+
+        let mut image = plain;
+        image.set_interlace(Interlace::Adam7);
+        image.encode_into(&mut png).unwrap();
+        assert_eq!(png.header_info().unwrap().interlace(), Interlace::Adam7);
+        assert_eq!(
+            RawImage::decode(&png).unwrap().pixels(),
+            interlaced.pixels()
+        );
+        assert_eq!(png.chunks()[0].chunk_type().to_string(), "IHDR");
+    }
+
+    #[test]
+    fn test_interlace_every_format() {
+        for (color_type, bit_depth) in [
+            (ColorType::Grayscale, 1),
+            (ColorType::Grayscale, 2),
+            (ColorType::Indexed, 4),
+            (ColorType::GrayscaleAlpha, 8),
+            (ColorType::Rgba, 16),
+        ] {
+            let header = ImageHeader::new(
+                19,
+                13,
+                bit_depth,
+                color_type,
+                Interlace::Adam7,
+            )
+            .unwrap();
+            let row_bytes = header.row_bytes();
+            // Padding bits at the end of a row are not pixels and come back
+            // as zeros, so leave them zero.
+            let padding = row_bytes * 8 - 19 * header.bits_per_pixel();
+            let mut pixels: Vec<u8> =
+                (0..row_bytes * 13).map(|i| (i * 31 % 256) as u8).collect();
+            for row in pixels.chunks_mut(row_bytes) {
+                row[row_bytes - 1] &= 0xff << padding;
+            }
+
+            let mut image = RawImage::new(header, pixels).unwrap();
+            image.refilter(FilterStrategy::MinSumAbs);
+            let mut png = testing_png(&header, image.pixels());
+            image.encode_into(&mut png).unwrap();
+
+            assert_eq!(RawImage::decode(&png).unwrap(), image);
+        }
+    }
+}
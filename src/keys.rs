@@ -0,0 +1,272 @@
+use crate::{Error, Result};
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use ed25519_dalek::Signer;
+use std::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+const KEY_SIZE: usize = 32;
+
+const RECIPIENT_LABEL: &str = "x25519-public:";
+const IDENTITY_LABEL: &str = "x25519-secret:";
+const VERIFYING_LABEL: &str = "ed25519-public:";
+const SIGNING_LABEL: &str = "ed25519-secret:";
+
+/// The public half of an X25519 key pair; messages sealed to it can only be
+/// opened with the matching [`Identity`].
+///
+/// Written as `x25519-public:` followed by the key in hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recipient(PublicKey);
+
+impl Recipient {
+    pub fn from_bytes(bytes: [u8; KEY_SIZE]) -> Recipient {
+        Recipient(PublicKey::from(bytes))
+    }
+    pub fn as_bytes(&self) -> &[u8; KEY_SIZE] {
+        self.0.as_bytes()
+    }
+    pub(crate) fn key(&self) -> &PublicKey {
+        &self.0
+    }
+}
+
+impl FromStr for Recipient {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(Recipient::from_bytes(parse_key(s, RECIPIENT_LABEL)?))
+    }
+}
+
+impl Display for Recipient {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}{}", RECIPIENT_LABEL, hex::encode(self.as_bytes()))
+    }
+}
+
+/// The secret half of an X25519 key pair.
+///
+/// Written as `x25519-secret:` followed by the key in hex. When parsing,
+/// blank lines and lines starting with `#` are skipped, so a key file can
+/// carry a comment naming its public key. The secret is wiped on drop.
+#[derive(Clone)]
+pub struct Identity(StaticSecret);
+
+impl Identity {
+    pub fn generate() -> Identity {
+        Identity(StaticSecret::random_from_rng(OsRng))
+    }
+    pub fn from_bytes(bytes: [u8; KEY_SIZE]) -> Identity {
+        Identity(StaticSecret::from(bytes))
+    }
+    pub fn recipient(&self) -> Recipient {
+        Recipient(PublicKey::from(&self.0))
+    }
+    pub(crate) fn secret(&self) -> &StaticSecret {
+        &self.0
+    }
+}
+
+impl FromStr for Identity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(Identity::from_bytes(parse_key(s, IDENTITY_LABEL)?))
+    }
+}
+
+impl Display for Identity {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}{}", IDENTITY_LABEL, hex::encode(self.0.as_bytes()))
+    }
+}
+
+/// Identities are never printed by `{:?}`, so a stray debug log cannot leak
+/// one.
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("Identity").field(&self.recipient()).finish()
+    }
+}
+
+/// The public half of an Ed25519 key pair, used to check signatures.
+///
+/// Written as `ed25519-public:` followed by the key in hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyingKey(ed25519_dalek::VerifyingKey);
+
+impl VerifyingKey {
+    pub fn from_bytes(bytes: [u8; KEY_SIZE]) -> Result<VerifyingKey> {
+        ed25519_dalek::VerifyingKey::from_bytes(&bytes)
+            .map(VerifyingKey)
+            .map_err(|_| Error::InvalidKey {
+                reason: "not a valid Ed25519 public key",
+            })
+    }
+    pub fn as_bytes(&self) -> &[u8; KEY_SIZE] {
+        self.0.as_bytes()
+    }
+    /// Checks `signature` over `message` without accepting malleable or
+    /// weak-key signatures.
+    pub(crate) fn verify(
+        &self,
+        message: &[u8],
+        signature: &ed25519_dalek::Signature,
+    ) -> bool {
+        self.0.verify_strict(message, signature).is_ok()
+    }
+}
+
+impl FromStr for VerifyingKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        VerifyingKey::from_bytes(parse_key(s, VERIFYING_LABEL)?)
+    }
+}
+
+impl Display for VerifyingKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}{}", VERIFYING_LABEL, hex::encode(self.as_bytes()))
+    }
+}
+
+/// The secret half of an Ed25519 key pair.
+///
+/// Written as `ed25519-secret:` followed by the seed in hex, and parsed the
+/// same way as an [`Identity`]. The secret is wiped on drop.
+#[derive(Clone)]
+pub struct SigningKey(ed25519_dalek::SigningKey);
+
+impl SigningKey {
+    pub fn generate() -> SigningKey {
+        let mut seed: Zeroizing<[u8; KEY_SIZE]> = Zeroizing::new([0; KEY_SIZE]);
+        OsRng.fill_bytes(seed.as_mut());
+        SigningKey::from_bytes(*seed)
+    }
+    pub fn from_bytes(bytes: [u8; KEY_SIZE]) -> SigningKey {
+        SigningKey(ed25519_dalek::SigningKey::from_bytes(&bytes))
+    }
+    pub fn verifying_key(&self) -> VerifyingKey {
+        VerifyingKey(self.0.verifying_key())
+    }
+    pub(crate) fn sign(&self, message: &[u8]) -> ed25519_dalek::Signature {
+        self.0.sign(message)
+    }
+}
+
+impl FromStr for SigningKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(SigningKey::from_bytes(parse_key(s, SIGNING_LABEL)?))
+    }
+}
+
+impl Display for SigningKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}{}", SIGNING_LABEL, hex::encode(self.0.as_bytes()))
+    }
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("SigningKey")
+            .field(&self.verifying_key())
+            .finish()
+    }
+}
+
+/// Parses the first line of `s` that is neither blank nor a `#` comment as
+/// `label` followed by a hex-encoded key.
+fn parse_key(s: &str, label: &'static str) -> Result<[u8; KEY_SIZE]> {
+    let line: &str = s
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .ok_or(Error::InvalidKey {
+            reason: "no key found",
+        })?;
+    let encoded: &str = line.strip_prefix(label).ok_or(Error::InvalidKey {
+        reason: "not the expected kind of key",
+    })?;
+
+    let mut bytes: [u8; KEY_SIZE] = [0; KEY_SIZE];
+    hex::decode_to_slice(encoded, &mut bytes).map_err(|_| {
+        Error::InvalidKey {
+            reason: "key is not 32 hex-encoded bytes",
+        }
+    })?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_round_trip() {
+        let identity = Identity::generate();
+        let recipient = identity.recipient();
+
+        let parsed = Identity::from_str(&identity.to_string()).unwrap();
+        assert_eq!(parsed.recipient(), recipient);
+        assert_eq!(
+            Recipient::from_str(&recipient.to_string()).unwrap(),
+            recipient
+        );
+    }
+
+    #[test]
+    fn test_key_file_with_comments() {
+        let identity = Identity::generate();
+        let file =
+            format!("# public key: {}\n\n{}\n", identity.recipient(), identity);
+
+        let parsed = Identity::from_str(&file).unwrap();
+        assert_eq!(parsed.recipient(), identity.recipient());
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        let identity = Identity::generate();
+
+        assert!(Recipient::from_str(&identity.to_string()).is_err());
+        assert!(Identity::from_str("").is_err());
+        assert!(Identity::from_str("x25519-secret:abcd").is_err());
+        assert!(Recipient::from_str("x25519-public:zz").is_err());
+    }
+
+    #[test]
+    fn test_debug_hides_secret() {
+        let identity = Identity::generate();
+        let secret = hex::encode(identity.secret().as_bytes());
+        assert!(!format!("{:?}", identity).contains(&secret));
+
+        let signing = SigningKey::generate();
+        let secret = hex::encode(signing.0.as_bytes());
+        assert!(!format!("{:?}", signing).contains(&secret));
+    }
+
+    #[test]
+    fn test_signing_key_round_trip() {
+        let signing = SigningKey::generate();
+        let verifying = signing.verifying_key();
+
+        let parsed = SigningKey::from_str(&signing.to_string()).unwrap();
+        assert_eq!(parsed.verifying_key(), verifying);
+        assert_eq!(
+            VerifyingKey::from_str(&verifying.to_string()).unwrap(),
+            verifying
+        );
+        assert!(VerifyingKey::from_str(&signing.to_string()).is_err());
+
+        let signature = signing.sign(b"message");
+        assert!(verifying.verify(b"message", &signature));
+        assert!(!verifying.verify(b"massage", &signature));
+    }
+}
@@ -0,0 +1,42 @@
+pub mod adam7;
+pub mod chunk;
+pub mod chunk_type;
+pub mod compression;
+pub mod envelope;
+pub mod error;
+pub mod filter;
+pub mod fragment;
+pub mod header;
+pub mod image;
+pub mod image_data;
+pub mod keys;
+pub mod lsb;
+pub mod payload;
+pub mod png;
+pub mod reader;
+pub mod scan;
+pub mod signature;
+pub mod structure;
+pub mod text;
+pub mod writer;
+pub mod zlib;
+
+pub use chunk::{Chunk, ChunkRef};
+pub use chunk_type::ChunkType;
+pub use compression::Compression;
+pub use error::{Error, Result};
+pub use filter::{FilterStrategy, FilterType};
+pub use fragment::{Fragment, Reassembler};
+pub use header::{ColorType, ImageHeader, Interlace};
+pub use image::{ImageBuffer, Palette, Pixel};
+pub use image_data::RawImage;
+pub use keys::{Identity, Recipient, SigningKey, VerifyingKey};
+pub use lsb::{Channels, LsbOptions};
+pub use payload::Payload;
+pub use png::{EmbedMode, Png, PngRef};
+pub use reader::{ChunkHeader, ChunkReader};
+pub use scan::{Finding, Report, Severity};
+pub use signature::{Signature, SignatureStatus};
+pub use structure::{StructureValidator, Violation};
+pub use text::TextChunk;
+pub use writer::ChunkWriter;
@@ -0,0 +1,661 @@
+use crate::{
+    chunk::read_u32,
+    envelope::KdfParams,
+    header::{ColorType, ImageHeader},
+    image_data::RawImage,
+    png::Png,
+    Error, Result,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Display, Formatter},
+    str::FromStr,
+};
+use zeroize::Zeroizing;
+
+/// Marks the start of a message hidden in pixel data.
+pub const MAGIC: [u8; 4] = *b"PGML";
+
+const HEADER_SIZE: usize = MAGIC.len() + 4;
+
+// Salt prefix for the seed of the keyed sample order. The IHDR data follows
+// it, so the same passphrase gives each image a different order.
+const ORDER_SALT: &[u8] = b"pngme lsb order v1";
+
+/// Which channels of each pixel carry hidden bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Channels {
+    /// The red, green and blue channels, or the gray channel.
+    #[default]
+    Color,
+    /// Only the alpha channel. Changes there are invisible wherever the
+    /// image is opaque, but the image must have an alpha channel.
+    Alpha,
+    /// Every channel, alpha included.
+    All,
+}
+
+impl FromStr for Channels {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "color" => Ok(Channels::Color),
+            "alpha" => Ok(Channels::Alpha),
+            "all" => Ok(Channels::All),
+            _ => Err(Error::UnknownChannels {
+                name: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl Display for Channels {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name: &str = match self {
+            Channels::Color => "color",
+            Channels::Alpha => "alpha",
+            Channels::All => "all",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Where and how densely a message is spread over the pixels. Extracting
+/// needs the same options that embedding used.
+#[derive(Clone, PartialEq, Eq)]
+pub struct LsbOptions {
+    bits: u8,
+    channels: Channels,
+    key: Option<Zeroizing<Vec<u8>>>,
+    kdf: KdfParams,
+}
+
+impl LsbOptions {
+    /// Uses the lowest `bits` bits, from 1 to 8, of each chosen channel.
+    /// For 16-bit samples only the low byte is touched.
+    pub fn new(bits: u8, channels: Channels) -> Result<LsbOptions> {
+        if !(1..=8).contains(&bits) {
+            return Err(Error::InvalidLsbOptions {
+                reason: "bits per channel must be between 1 and 8",
+            });
+        }
+        Ok(LsbOptions {
+            bits,
+            channels,
+            ..LsbOptions::default()
+        })
+    }
+
+    /// Scatters the message over the image in an order derived from
+    /// `passphrase` instead of filling samples from the top left, so it
+    /// cannot even be located without the passphrase.
+    pub fn with_key(self, passphrase: &[u8]) -> LsbOptions {
+        self.with_key_params(passphrase, KdfParams::default())
+    }
+
+    /// Like [`LsbOptions::with_key`], with explicit Argon2id costs for
+    /// turning the passphrase into a seed.
+    pub fn with_key_params(
+        self,
+        passphrase: &[u8],
+        params: KdfParams,
+    ) -> LsbOptions {
+        LsbOptions {
+            key: Some(Zeroizing::new(passphrase.to_vec())),
+            kdf: params,
+            ..self
+        }
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
+    pub fn channels(&self) -> Channels {
+        self.channels
+    }
+    /// Returns `true` if samples are chosen by a passphrase.
+    pub fn is_keyed(&self) -> bool {
+        self.key.is_some()
+    }
+
+    fn order(&self, header: &ImageHeader, len: usize) -> Result<Order> {
+        let passphrase: &[u8] = match &self.key {
+            Some(passphrase) => passphrase,
+            None => return Ok(Order::Sequential { next: 0, len }),
+        };
+
+        let mut salt: Vec<u8> = ORDER_SALT.to_vec();
+        salt.extend_from_slice(&header.as_bytes());
+        let seed: Zeroizing<[u8; 32]> =
+            self.kdf.derive_key(passphrase, &salt)?;
+        Ok(Order::Keyed(Shuffle {
+            stream: KeyStream::new(seed),
+            swapped: HashMap::new(),
+            next: 0,
+            len,
+        }))
+    }
+}
+
+impl Default for LsbOptions {
+    fn default() -> Self {
+        LsbOptions {
+            bits: 1,
+            channels: Channels::Color,
+            key: None,
+            kdf: KdfParams::default(),
+        }
+    }
+}
+
+impl Debug for LsbOptions {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("LsbOptions")
+            .field("bits", &self.bits)
+            .field("channels", &self.channels)
+            .field("keyed", &self.is_keyed())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Hands out the indices of the chosen samples in the order they are
+/// filled: from the top left, or following a keyed random permutation.
+enum Order {
+    Sequential { next: usize, len: usize },
+    Keyed(Shuffle),
+}
+
+impl Iterator for Order {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        match self {
+            Order::Sequential { next, len } if *next < *len => {
+                *next += 1;
+                Some(*next - 1)
+            }
+            Order::Sequential { .. } => None,
+            Order::Keyed(shuffle) => shuffle.next(),
+        }
+    }
+}
+
+/// A Fisher-Yates shuffle of `0..len` carried out one step per index
+/// drawn. Only the entries it has moved are stored, so a short message in
+/// a large image costs little memory.
+struct Shuffle {
+    stream: KeyStream,
+    swapped: HashMap<usize, usize>,
+    next: usize,
+    len: usize,
+}
+
+impl Shuffle {
+    fn next(&mut self) -> Option<usize> {
+        if self.next >= self.len {
+            return None;
+        }
+        let i: usize = self.next;
+        let j: usize = i + self.stream.below((self.len - i) as u64) as usize;
+        // Swap entries `i` and `j` and hand out the one now at `i`, which
+        // is never looked at again.
+        let at_i: usize = self.swapped.remove(&i).unwrap_or(i);
+        let at_j: usize = if j == i {
+            at_i
+        } else {
+            self.swapped.insert(j, at_i).unwrap_or(j)
+        };
+        self.next += 1;
+        Some(at_j)
+    }
+}
+
+/// Pseudo-random numbers from SHA-256 in counter mode over a secret seed.
+struct KeyStream {
+    seed: Zeroizing<[u8; 32]>,
+    counter: u64,
+    block: [u8; 32],
+    used: usize,
+}
+
+impl KeyStream {
+    fn new(seed: Zeroizing<[u8; 32]>) -> KeyStream {
+        KeyStream {
+            seed,
+            counter: 0,
+            block: [0; 32],
+            used: 32,
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        if self.used == self.block.len() {
+            let mut hasher: Sha256 = Sha256::new();
+            hasher.update(self.seed.as_ref());
+            hasher.update(self.counter.to_be_bytes());
+            self.block = hasher.finalize().into();
+            self.counter += 1;
+            self.used = 0;
+        }
+        let bytes: [u8; 8] =
+            self.block[self.used..self.used + 8].try_into().unwrap();
+        self.used += 8;
+        u64::from_be_bytes(bytes)
+    }
+
+    /// A uniform number below `bound`. Draws from the uneven tail of the
+    /// `u64` range are thrown away so no value is favoured.
+    fn below(&mut self, bound: u64) -> u64 {
+        let zone: u64 = u64::MAX - u64::MAX % bound;
+        loop {
+            let value: u64 = self.next_u64();
+            if value < zone {
+                return value % bound;
+            }
+        }
+    }
+}
+
+/// The samples a hidden stream occupies, drawn from an [`Order`] only as
+/// far as the stream reaches.
+struct Slots {
+    order: Order,
+    samples: Vec<usize>,
+}
+
+impl Slots {
+    fn new(order: Order) -> Slots {
+        Slots {
+            order,
+            samples: Vec::new(),
+        }
+    }
+
+    fn get(&mut self, n: usize) -> usize {
+        while self.samples.len() <= n {
+            // Callers check the stream fits before walking it.
+            let sample: usize = self.order.next().expect("stream fits");
+            self.samples.push(sample);
+        }
+        self.samples[n]
+    }
+}
+
+/// The positions in the unfiltered pixel bytes of every bit chosen to
+/// carry the hidden stream, in the order they are filled.
+#[derive(Debug, Clone)]
+struct Layout {
+    pixel_bytes: usize,
+    sample_bytes: usize,
+    selected: Vec<usize>,
+    bits: usize,
+    samples: usize,
+}
+
+impl Layout {
+    fn new(header: &ImageHeader, options: &LsbOptions) -> Result<Layout> {
+        if header.color_type() == ColorType::Indexed {
+            return Err(Error::UnsupportedImage {
+                reason: "palette images cannot hide data in their pixels",
+            });
+        }
+        if header.bit_depth() < 8 {
+            return Err(Error::UnsupportedImage {
+                reason: "pixel embedding needs 8- or 16-bit samples",
+            });
+        }
+
+        let count: usize = header.color_type().channels() as usize;
+        let has_alpha: bool = header.color_type().has_alpha();
+        let selected: Vec<usize> = match options.channels {
+            Channels::Color if has_alpha => (0..count - 1).collect(),
+            Channels::Alpha if has_alpha => vec![count - 1],
+            Channels::Alpha => {
+                return Err(Error::InvalidLsbOptions {
+                    reason: "the image has no alpha channel",
+                })
+            }
+            Channels::Color | Channels::All => (0..count).collect(),
+        };
+
+        let sample_bytes: usize = header.bit_depth() as usize / 8;
+        let pixels: usize = header.width() as usize * header.height() as usize;
+        Ok(Layout {
+            pixel_bytes: count * sample_bytes,
+            sample_bytes,
+            bits: options.bits as usize,
+            samples: pixels * selected.len(),
+            selected,
+        })
+    }
+
+    /// Whole bytes of hidden stream, header included, the pixels can hold.
+    fn stream_size(&self) -> usize {
+        self.samples * self.bits / 8
+    }
+
+    /// The pixel byte holding bit `position` of the hidden stream, and how
+    /// far that bit is shifted within it. Each sample takes its share of
+    /// bits most significant first, into the low byte for 16-bit samples.
+    fn locate(&self, slots: &mut Slots, position: usize) -> (usize, usize) {
+        let n: usize = slots.get(position / self.bits);
+        let pixel: usize = n / self.selected.len();
+        let channel: usize = self.selected[n % self.selected.len()];
+        let offset: usize =
+            pixel * self.pixel_bytes + (channel + 1) * self.sample_bytes - 1;
+        (offset, self.bits - 1 - position % self.bits)
+    }
+
+    fn write(&self, pixels: &mut [u8], slots: &mut Slots, stream: &[u8]) {
+        for (i, byte) in stream.iter().enumerate() {
+            for j in 0..8 {
+                let bit: u8 = (byte >> (7 - j)) & 1;
+                let (offset, shift) = self.locate(slots, i * 8 + j);
+                pixels[offset] =
+                    (pixels[offset] & !(1 << shift)) | (bit << shift);
+            }
+        }
+    }
+
+    fn read(
+        &self,
+        pixels: &[u8],
+        slots: &mut Slots,
+        start: usize,
+        length: usize,
+    ) -> Vec<u8> {
+        (start..start + length)
+            .map(|i| {
+                (0..8).fold(0u8, |byte, j| {
+                    let (offset, shift) = self.locate(slots, i * 8 + j);
+                    (byte << 1) | ((pixels[offset] >> shift) & 1)
+                })
+            })
+            .collect()
+    }
+}
+
+/// How many message bytes the pixels of an image described by `header`
+/// can hide with `options`.
+pub fn capacity(header: &ImageHeader, options: &LsbOptions) -> Result<usize> {
+    let layout: Layout = Layout::new(header, options)?;
+    Ok(layout.stream_size().saturating_sub(HEADER_SIZE))
+}
+
+/// Hides `message` in the low bits of the pixels of `png` and rewrites its
+/// image data.
+pub fn embed(
+    png: &mut Png,
+    message: &[u8],
+    options: &LsbOptions,
+) -> Result<()> {
+    let mut image: RawImage = RawImage::decode(png)?;
+    let layout: Layout = Layout::new(image.header(), options)?;
+
+    let available: usize = layout.stream_size().saturating_sub(HEADER_SIZE);
+    if message.len() > available {
+        return Err(Error::InsufficientCapacity {
+            needed: message.len(),
+            available,
+        });
+    }
+
+    let mut stream: Vec<u8> = Vec::with_capacity(HEADER_SIZE + message.len());
+    stream.extend_from_slice(&MAGIC);
+    stream.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    stream.extend_from_slice(message);
+    let mut slots: Slots =
+        Slots::new(options.order(image.header(), layout.samples)?);
+    layout.write(image.pixels_mut(), &mut slots, &stream);
+
+    image.encode_into(png)
+}
+
+/// Recovers a message hidden by [`embed`] with the same `options`,
+/// passphrase included.
+pub fn extract(png: &Png, options: &LsbOptions) -> Result<Vec<u8>> {
+    let image: RawImage = RawImage::decode(png)?;
+    let layout: Layout = Layout::new(image.header(), options)?;
+    if layout.stream_size() < HEADER_SIZE {
+        return Err(Error::NoHiddenData);
+    }
+
+    let mut slots: Slots =
+        Slots::new(options.order(image.header(), layout.samples)?);
+    let header: Vec<u8> =
+        layout.read(image.pixels(), &mut slots, 0, HEADER_SIZE);
+    let length: usize = read_u32(&header[MAGIC.len()..]) as usize;
+    if header[..MAGIC.len()] != MAGIC
+        || length > layout.stream_size() - HEADER_SIZE
+    {
+        return Err(Error::NoHiddenData);
+    }
+
+    Ok(layout.read(image.pixels(), &mut slots, HEADER_SIZE, length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{header::Interlace, image_data::testing_png};
+
+    fn testing_image(color_type: ColorType, bit_depth: u8) -> Png {
+        let header =
+            ImageHeader::new(40, 30, bit_depth, color_type, Interlace::None)
+                .unwrap();
+        let pixels: Vec<u8> = (0..header.row_bytes() * 30)
+            .map(|i| (i * 7 % 256) as u8)
+            .collect();
+        testing_png(&header, &pixels)
+    }
+
+    fn testing_message() -> Vec<u8> {
+        b"This is where your secret message will be!".to_vec()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for bits in [1, 2, 4, 8] {
+            for channels in [Channels::Color, Channels::Alpha, Channels::All] {
+                let options = LsbOptions::new(bits, channels).unwrap();
+                let mut png = testing_image(ColorType::Rgba, 8);
+                embed(&mut png, &testing_message(), &options).unwrap();
+
+                assert_eq!(
+                    extract(&png, &options).unwrap(),
+                    testing_message(),
+                    "{} bits in {} channels",
+                    bits,
+                    channels
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_interlaced() {
+        let header =
+            ImageHeader::new(40, 30, 8, ColorType::Rgb, Interlace::Adam7)
+                .unwrap();
+        let pixels: Vec<u8> = (0..header.row_bytes() * 30)
+            .map(|i| (i * 7 % 256) as u8)
+            .collect();
+        let mut png = testing_png(&header, &pixels);
+        let options = LsbOptions::new(2, Channels::Color).unwrap();
+        embed(&mut png, &testing_message(), &options).unwrap();
+
+        assert_eq!(png.header_info().unwrap(), header);
+        assert_eq!(extract(&png, &options).unwrap(), testing_message());
+    }
+
+    #[test]
+    fn test_only_low_bits_change() {
+        let original = testing_image(ColorType::Rgb, 8);
+        let before = RawImage::decode(&original).unwrap();
+        let mut png = testing_image(ColorType::Rgb, 8);
+        let options = LsbOptions::new(2, Channels::Color).unwrap();
+        embed(&mut png, &testing_message(), &options).unwrap();
+        let after = RawImage::decode(&png).unwrap();
+
+        assert_ne!(before.pixels(), after.pixels());
+        for (a, b) in before.pixels().iter().zip(after.pixels()) {
+            assert_eq!(a & !0b11, b & !0b11);
+        }
+    }
+
+    #[test]
+    fn test_sixteen_bit_and_gray() {
+        let options = LsbOptions::default();
+        for (color_type, bit_depth) in [
+            (ColorType::Rgb, 16),
+            (ColorType::Grayscale, 8),
+            (ColorType::GrayscaleAlpha, 16),
+        ] {
+            let mut png = testing_image(color_type, bit_depth);
+            let before = RawImage::decode(&png).unwrap();
+            embed(&mut png, &testing_message(), &options).unwrap();
+            assert_eq!(extract(&png, &options).unwrap(), testing_message());
+
+            if bit_depth == 16 {
+                let after = RawImage::decode(&png).unwrap();
+                for (a, b) in
+                    before.pixels().iter().zip(after.pixels()).step_by(2)
+                {
+                    assert_eq!(a, b, "high bytes must be left alone");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_capacity() {
+        let header =
+            ImageHeader::new(40, 30, 8, ColorType::Rgba, Interlace::None)
+                .unwrap();
+        let capacity = |bits, channels| {
+            capacity(&header, &LsbOptions::new(bits, channels).unwrap())
+                .unwrap()
+        };
+
+        assert_eq!(capacity(1, Channels::Color), 40 * 30 * 3 / 8 - 8);
+        assert_eq!(capacity(4, Channels::All), 40 * 30 * 4 / 2 - 8);
+        assert_eq!(capacity(1, Channels::Alpha), 40 * 30 / 8 - 8);
+
+        let mut png = testing_image(ColorType::Rgba, 8);
+        let options = LsbOptions::new(1, Channels::Alpha).unwrap();
+        let message = vec![0xA5; capacity(1, Channels::Alpha)];
+        embed(&mut png, &message, &options).unwrap();
+        assert_eq!(extract(&png, &options).unwrap(), message);
+        assert!(matches!(
+            embed(&mut png, &[message, vec![0]].concat(), &options),
+            Err(Error::InsufficientCapacity { .. })
+        ));
+    }
+
+    #[test]
+    fn test_no_hidden_data() {
+        let png = testing_image(ColorType::Rgb, 8);
+        assert!(matches!(
+            extract(&png, &LsbOptions::default()),
+            Err(Error::NoHiddenData)
+        ));
+
+        let mut png = testing_image(ColorType::Rgb, 8);
+        embed(&mut png, &testing_message(), &LsbOptions::default()).unwrap();
+        assert!(matches!(
+            extract(&png, &LsbOptions::new(2, Channels::Color).unwrap()),
+            Err(Error::NoHiddenData)
+        ));
+    }
+
+    // Cheap parameters so the tests do not spend their time in Argon2.
+    fn keyed(options: LsbOptions, passphrase: &[u8]) -> LsbOptions {
+        let params = KdfParams {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+        };
+        options.with_key_params(passphrase, params)
+    }
+
+    #[test]
+    fn test_keyed_round_trip() {
+        for bits in [1, 3, 8] {
+            let options = keyed(
+                LsbOptions::new(bits, Channels::All).unwrap(),
+                b"correct horse",
+            );
+            let mut png = testing_image(ColorType::Rgba, 8);
+            embed(&mut png, &testing_message(), &options).unwrap();
+            assert_eq!(extract(&png, &options).unwrap(), testing_message());
+
+            let wrong = keyed(
+                LsbOptions::new(bits, Channels::All).unwrap(),
+                b"wrong horse",
+            );
+            assert!(matches!(extract(&png, &wrong), Err(Error::NoHiddenData)));
+            assert!(matches!(
+                extract(&png, &LsbOptions::new(bits, Channels::All).unwrap()),
+                Err(Error::NoHiddenData)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_keyed_order_is_scattered() {
+        let before = RawImage::decode(&testing_image(ColorType::Rgb, 8))
+            .unwrap()
+            .pixels()
+            .to_vec();
+        let mut png = testing_image(ColorType::Rgb, 8);
+        let options = keyed(LsbOptions::default(), b"correct horse");
+        embed(&mut png, &testing_message(), &options).unwrap();
+        let after = RawImage::decode(&png).unwrap().pixels().to_vec();
+
+        // A sequential embedding of this message stays within the first
+        // 400 samples; a scattered one reaches well past them.
+        let changed: Vec<usize> = (0..before.len())
+            .filter(|&i| before[i] != after[i])
+            .collect();
+        assert!(changed.iter().any(|&i| i > 1800));
+    }
+
+    #[test]
+    fn test_shuffle_is_a_permutation() {
+        let seed = Zeroizing::new([7; 32]);
+        let order = Order::Keyed(Shuffle {
+            stream: KeyStream::new(seed),
+            swapped: HashMap::new(),
+            next: 0,
+            len: 1000,
+        });
+        let mut drawn: Vec<usize> = order.collect();
+        assert_eq!(drawn.len(), 1000);
+        assert_ne!(drawn, (0..1000).collect::<Vec<usize>>());
+        drawn.sort_unstable();
+        assert_eq!(drawn, (0..1000).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        assert!(LsbOptions::new(0, Channels::Color).is_err());
+        assert!(LsbOptions::new(9, Channels::Color).is_err());
+        assert!(Channels::from_str("green").is_err());
+        assert_eq!(Channels::from_str("Alpha").unwrap(), Channels::Alpha);
+
+        let mut png = testing_image(ColorType::Rgb, 8);
+        let options = LsbOptions::new(1, Channels::Alpha).unwrap();
+        assert!(matches!(
+            embed(&mut png, b"hi", &options),
+            Err(Error::InvalidLsbOptions { .. })
+        ));
+
+        let mut png = testing_image(ColorType::Indexed, 8);
+        assert!(matches!(
+            embed(&mut png, b"hi", &LsbOptions::default()),
+            Err(Error::UnsupportedImage { .. })
+        ));
+    }
+}
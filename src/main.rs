@@ -1,16 +1,14 @@
 use args::Cli;
+use std::process;
 use structopt::StructOpt;
 
 mod args;
-mod chunk;
-mod chunk_type;
 mod commands;
-mod png;
 
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
-
-fn main() -> Result<()> {
+fn main() {
     let cli: Cli = Cli::from_args();
-    commands::run(cli.subcmd)
+    if let Err(e) = commands::run(cli.subcmd) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
 }
@@ -0,0 +1,395 @@
+use crate::{compression::Compression, Error, Result};
+use std::{
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+    path::Path,
+};
+
+/// Marks chunk data (or the plaintext inside an envelope) as a file with a
+/// header rather than a bare text message.
+pub const MAGIC: [u8; 4] = *b"PGMF";
+pub const VERSION: u8 = 1;
+
+/// Media type recorded when nothing better is known.
+pub const DEFAULT_MEDIA_TYPE: &str = "application/octet-stream";
+/// Media type of a payload made by [`Payload::message`].
+pub const MESSAGE_MEDIA_TYPE: &str = "text/plain; charset=utf-8";
+
+const MEDIA_TYPES: [(&str, &str); 14] = [
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("html", "text/html"),
+    ("csv", "text/csv"),
+    ("json", "application/json"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("pem", "application/x-pem-file"),
+];
+
+/// Raw bytes plus what is needed to hand them back as a file: the original
+/// file name, the size and a media type.
+///
+/// Stored as the magic, a version byte, the [`Compression`] id, the name
+/// (u16 length, UTF-8), the media type (u8 length, UTF-8), the original
+/// size (u64) and then the possibly compressed bytes, with all integers
+/// big-endian. The size is checked against the bytes once decompressed, so
+/// a truncated payload is caught.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payload {
+    name: Option<String>,
+    media_type: String,
+    compression: Compression,
+    data: Vec<u8>,
+}
+
+impl Payload {
+    pub const MAX_NAME_LENGTH: usize = u16::MAX as usize;
+    pub const MAX_MEDIA_TYPE_LENGTH: usize = u8::MAX as usize;
+    /// The largest payload [`Payload::try_from`] will hand back.
+    pub const DEFAULT_MAX_SIZE: usize = 256 << 20;
+
+    /// A payload with no file name and the default media type.
+    pub fn new(data: Vec<u8>) -> Payload {
+        Payload {
+            name: None,
+            media_type: DEFAULT_MEDIA_TYPE.to_string(),
+            compression: Compression::None,
+            data,
+        }
+    }
+
+    /// A payload holding a text message rather than a file.
+    pub fn message(text: &str) -> Payload {
+        Payload {
+            media_type: MESSAGE_MEDIA_TYPE.to_string(),
+            ..Payload::new(text.as_bytes().to_vec())
+        }
+    }
+
+    /// A payload named after the last component of `path`, with the media
+    /// type guessed from its extension. A name too long to store is left
+    /// out.
+    pub fn from_path(path: &Path, data: Vec<u8>) -> Payload {
+        let name: Option<String> = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .filter(|name| name.len() <= Payload::MAX_NAME_LENGTH);
+        let media_type: &str = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(guess_media_type)
+            .unwrap_or(DEFAULT_MEDIA_TYPE);
+
+        Payload {
+            name,
+            media_type: media_type.to_string(),
+            compression: Compression::None,
+            data,
+        }
+    }
+
+    pub fn with_name(mut self, name: &str) -> Result<Payload> {
+        if name.len() > Payload::MAX_NAME_LENGTH {
+            return Err(Error::InvalidPayload {
+                reason: "file name is too long",
+            });
+        }
+        self.name = Some(name.to_string());
+        Ok(self)
+    }
+
+    pub fn with_media_type(mut self, media_type: &str) -> Result<Payload> {
+        if media_type.is_empty()
+            || media_type.len() > Payload::MAX_MEDIA_TYPE_LENGTH
+        {
+            return Err(Error::InvalidPayload {
+                reason: "media type must be 1 to 255 bytes long",
+            });
+        }
+        self.media_type = media_type.to_string();
+        Ok(self)
+    }
+
+    /// Sets how the data is compressed when the payload is stored.
+    pub fn with_compression(mut self, compression: Compression) -> Payload {
+        self.compression = compression;
+        self
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+    pub fn media_type(&self) -> &str {
+        &self.media_type
+    }
+    /// Returns `true` for an unnamed text payload, as made by
+    /// [`Payload::message`].
+    pub fn is_message(&self) -> bool {
+        self.name.is_none() && self.media_type == MESSAGE_MEDIA_TYPE
+    }
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+    /// The data as it was before compression.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Serializes the payload, compressing the data on the way.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        // Both lengths were checked when the fields were set.
+        let name: &[u8] = self.name.as_deref().unwrap_or_default().as_bytes();
+        let data: Vec<u8> = self.compression.compress(&self.data)?;
+
+        let mut bytes: Vec<u8> = Vec::with_capacity(
+            MAGIC.len() + 13 + name.len() + self.media_type.len() + data.len(),
+        );
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(VERSION);
+        bytes.push(self.compression.id());
+        bytes.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(name);
+        bytes.push(self.media_type.len() as u8);
+        bytes.extend_from_slice(self.media_type.as_bytes());
+        bytes.extend_from_slice(&(self.data.len() as u64).to_be_bytes());
+        bytes.extend_from_slice(&data);
+        Ok(bytes)
+    }
+
+    /// Parses a stored payload, refusing one that would be larger than
+    /// `limit` bytes once decompressed.
+    pub fn parse(value: &[u8], limit: usize) -> Result<Payload> {
+        let mut rest: &[u8] =
+            value.strip_prefix(&MAGIC).ok_or(Error::InvalidPayload {
+                reason: "data is not a file payload",
+            })?;
+
+        if take(&mut rest, 1)?[0] != VERSION {
+            return Err(Error::InvalidPayload {
+                reason: "unsupported payload version",
+            });
+        }
+        let compression: Compression = Compression::from_id(
+            take(&mut rest, 1)?[0],
+        )
+        .ok_or(Error::InvalidPayload {
+            reason: "unsupported compression",
+        })?;
+
+        let name_length: usize =
+            u16::from_be_bytes(take(&mut rest, 2)?.try_into().unwrap())
+                as usize;
+        let name: &[u8] = take(&mut rest, name_length)?;
+        let media_type_length: usize = take(&mut rest, 1)?[0] as usize;
+        let media_type: &[u8] = take(&mut rest, media_type_length)?;
+        let size: u64 =
+            u64::from_be_bytes(take(&mut rest, 8)?.try_into().unwrap());
+
+        if size > limit as u64 {
+            return Err(Error::DecompressedTooLarge { limit });
+        }
+
+        let data: Vec<u8> = compression.decompress(rest, limit)?;
+
+        if size != data.len() as u64 {
+            return Err(Error::InvalidPayload {
+                reason: "size does not match the stored data",
+            });
+        }
+
+        let text = |bytes: &[u8]| {
+            String::from_utf8(bytes.to_vec()).map_err(|_| {
+                Error::InvalidPayload {
+                    reason: "file name or media type is not UTF-8",
+                }
+            })
+        };
+
+        Ok(Payload {
+            name: if name.is_empty() {
+                None
+            } else {
+                Some(text(name)?)
+            },
+            media_type: text(media_type)?,
+            compression,
+            data,
+        })
+    }
+}
+
+/// Returns `true` if `data` starts like a [`Payload`].
+pub fn is_payload(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Looks up the media type for a file extension, ignoring case.
+pub fn guess_media_type(extension: &str) -> Option<&'static str> {
+    MEDIA_TYPES
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(extension))
+        .map(|(_, media_type)| *media_type)
+}
+
+impl TryFrom<&[u8]> for Payload {
+    type Error = Error;
+
+    /// Parses a stored payload with [`Payload::DEFAULT_MAX_SIZE`] as the
+    /// limit.
+    fn try_from(value: &[u8]) -> Result<Self> {
+        Payload::parse(value, Payload::DEFAULT_MAX_SIZE)
+    }
+}
+
+impl Display for Payload {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "file \"{}\"", name)?,
+            None => write!(f, "file")?,
+        }
+        write!(f, " ({} bytes, {}", self.data.len(), self.media_type)?;
+        if self.compression != Compression::None {
+            write!(f, ", {}-compressed", self.compression)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Splits `length` bytes off the front of `rest`.
+fn take<'a>(rest: &mut &'a [u8], length: usize) -> Result<&'a [u8]> {
+    if rest.len() < length {
+        return Err(Error::InvalidPayload {
+            reason: "payload is truncated",
+        });
+    }
+    let (head, tail): (&[u8], &[u8]) = rest.split_at(length);
+    *rest = tail;
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_payload() -> Payload {
+        Payload::from_path(
+            Path::new("docs/report.PDF"),
+            vec![0x25, 0x50, 0x44, 0x46, 0xff, 0x00, 0xfe],
+        )
+    }
+
+    #[test]
+    fn test_payload_round_trip() {
+        let payload = testing_payload();
+        let bytes = payload.to_bytes().unwrap();
+        let parsed = Payload::try_from(bytes.as_slice()).unwrap();
+
+        assert_eq!(parsed, payload);
+        assert_eq!(parsed.name(), Some("report.PDF"));
+        assert_eq!(parsed.media_type(), "application/pdf");
+        assert_eq!(parsed.data(), &[0x25, 0x50, 0x44, 0x46, 0xff, 0x00, 0xfe]);
+    }
+
+    #[test]
+    fn test_unnamed_payload() {
+        let payload = Payload::new(b"from stdin".to_vec());
+        let bytes = payload.to_bytes().unwrap();
+        let parsed = Payload::try_from(bytes.as_slice()).unwrap();
+
+        assert_eq!(parsed.name(), None);
+        assert_eq!(parsed.media_type(), DEFAULT_MEDIA_TYPE);
+        assert_eq!(
+            parsed.to_string(),
+            "file (10 bytes, application/octet-stream)"
+        );
+    }
+
+    #[test]
+    fn test_payload_display() {
+        assert_eq!(
+            testing_payload().to_string(),
+            "file \"report.PDF\" (7 bytes, application/pdf)"
+        );
+    }
+
+    #[test]
+    fn test_compressed_payload() {
+        let text = b"All work and no play makes Jack a dull boy. ".repeat(50);
+        for compression in
+            [Compression::Zlib, Compression::Zstd, Compression::Brotli]
+        {
+            let payload =
+                Payload::new(text.clone()).with_compression(compression);
+            let bytes = payload.to_bytes().unwrap();
+            let parsed = Payload::try_from(bytes.as_slice()).unwrap();
+
+            assert!(bytes.len() < text.len() / 10);
+            assert_eq!(parsed, payload);
+            assert_eq!(parsed.data(), text.as_slice());
+        }
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let payload =
+            Payload::new(vec![0; 100_000]).with_compression(Compression::Zstd);
+        let mut bytes = payload.to_bytes().unwrap();
+
+        assert!(Payload::parse(&bytes, 100_000).is_ok());
+        assert!(matches!(
+            Payload::parse(&bytes, 99_999),
+            Err(Error::DecompressedTooLarge { limit: 99_999 })
+        ));
+
+        // A header that understates the size is caught while inflating.
+        let size_at = MAGIC.len() + 4 + 1 + DEFAULT_MEDIA_TYPE.len();
+        bytes[size_at..size_at + 8].copy_from_slice(&10u64.to_be_bytes());
+        assert!(matches!(
+            Payload::parse(&bytes, 1000),
+            Err(Error::DecompressedTooLarge { limit: 1000 })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_fields() {
+        let payload = Payload::new(Vec::new());
+
+        assert!(payload.clone().with_media_type("").is_err());
+        assert!(payload.clone().with_media_type(&"a".repeat(256)).is_err());
+        assert!(payload.with_name(&"a".repeat(1 << 16)).is_err());
+    }
+
+    #[test]
+    fn test_malformed_payloads() {
+        let bytes = testing_payload().to_bytes().unwrap();
+
+        for end in 0..bytes.len() {
+            assert!(matches!(
+                Payload::try_from(&bytes[..end]),
+                Err(Error::InvalidPayload { .. })
+            ));
+        }
+
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert!(Payload::try_from(longer.as_slice()).is_err());
+
+        let mut future = bytes;
+        future[4] = VERSION + 1;
+        assert!(matches!(
+            Payload::try_from(future.as_slice()),
+            Err(Error::InvalidPayload {
+                reason: "unsupported payload version"
+            })
+        ));
+    }
+}
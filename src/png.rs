@@ -1,58 +1,295 @@
-use crate::{chunk::Chunk, Error};
+use crate::{
+    chunk::{self, Chunk, ChunkRef},
+    header::ImageHeader,
+    image::{self, ImageBuffer},
+    image_data,
+    lsb::{self, LsbOptions},
+    reader::ChunkReader,
+    structure,
+    text::TextChunk,
+    writer::ChunkWriter,
+    Error,
+};
 use std::{
     convert::TryFrom,
-    error,
     fmt::{self, Display, Formatter},
+    io::{Read, Write},
 };
 
 pub struct Png {
     chunks: Vec<Chunk>,
 }
 
+/// A way of hiding a message in an image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmbedMode {
+    /// In the data of a single ancillary chunk.
+    Chunk,
+    /// In the low bits of the pixels.
+    Lsb(LsbOptions),
+}
+
+impl Display for EmbedMode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            EmbedMode::Chunk => write!(f, "chunk"),
+            EmbedMode::Lsb(options) => write!(
+                f,
+                "LSB {} bit{} per {} channel",
+                options.bits(),
+                if options.bits() == 1 { "" } else { "s" },
+                options.channels()
+            ),
+        }
+    }
+}
+
 impl Png {
     pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
     pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
         Self { chunks }
     }
+    pub fn from_reader<R: Read>(reader: R) -> Result<Png, Error> {
+        let chunks: Vec<Chunk> =
+            ChunkReader::new(reader)?.collect::<Result<_, Error>>()?;
+
+        Ok(Self { chunks })
+    }
+    /// Like [`Png::from_reader`], but also refuses a chunk order that breaks
+    /// the rules [`Png::validate`] checks.
+    pub fn from_reader_validated<R: Read>(reader: R) -> Result<Png, Error> {
+        let chunks: Vec<Chunk> = ChunkReader::new_validated(reader)?
+            .collect::<Result<_, Error>>()?;
+
+        Ok(Self { chunks })
+    }
     pub fn append_chunk(&mut self, chunk: Chunk) {
         self.chunks.push(chunk);
     }
+    /// Inserts `chunk` right before IEND, the position where a new
+    /// ancillary chunk is always legal. Appends it if there is no IEND.
+    pub fn insert_chunk(&mut self, chunk: Chunk) {
+        let index: usize = self
+            .chunks
+            .iter()
+            .rposition(|c| c.chunk_type().bytes() == *b"IEND")
+            .unwrap_or(self.chunks.len());
+
+        self.chunks.insert(index, chunk);
+    }
+    /// Inserts `chunk` so that it ends up at position `index`.
+    pub fn insert_chunk_at(
+        &mut self,
+        index: usize,
+        chunk: Chunk,
+    ) -> Result<(), Error> {
+        if index > self.chunks.len() {
+            return Err(Error::IndexOutOfRange {
+                index,
+                length: self.chunks.len(),
+            });
+        }
+
+        self.chunks.insert(index, chunk);
+        Ok(())
+    }
+    /// Inserts `chunk` right before the first chunk of type `chunk_type`.
+    pub fn insert_chunk_before(
+        &mut self,
+        chunk_type: &str,
+        chunk: Chunk,
+    ) -> Result<(), Error> {
+        let index: usize = self
+            .chunks
+            .iter()
+            .position(|c| c.chunk_type().to_string() == chunk_type)
+            .ok_or_else(|| Error::ChunkNotFound {
+                chunk_type: chunk_type.to_string(),
+            })?;
+
+        self.insert_chunk_at(index, chunk)
+    }
+    /// Inserts `chunk` right after the last chunk of type `chunk_type`, so
+    /// a run of IDAT chunks is never split.
+    pub fn insert_chunk_after(
+        &mut self,
+        chunk_type: &str,
+        chunk: Chunk,
+    ) -> Result<(), Error> {
+        let index: usize = self
+            .chunks
+            .iter()
+            .rposition(|c| c.chunk_type().to_string() == chunk_type)
+            .ok_or_else(|| Error::ChunkNotFound {
+                chunk_type: chunk_type.to_string(),
+            })?;
+
+        self.insert_chunk_at(index + 1, chunk)
+    }
+    /// Checks the chunk ordering rules of the PNG specification.
+    pub fn validate(&self) -> Result<(), Error> {
+        structure::validate(self.chunks.iter().map(Chunk::chunk_type))
+    }
     pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk, Error> {
         let index = self
             .chunks
             .iter()
             .position(|c| c.chunk_type().to_string() == chunk_type)
-            .unwrap();
+            .ok_or_else(|| Error::ChunkNotFound {
+                chunk_type: chunk_type.to_string(),
+            })?;
+
+        Ok(self.chunks.remove(index))
+    }
+    /// Removes every chunk of type `chunk_type`, failing if there is none.
+    pub fn remove_all(
+        &mut self,
+        chunk_type: &str,
+    ) -> Result<Vec<Chunk>, Error> {
+        let (removed, kept): (Vec<Chunk>, Vec<Chunk>) = self
+            .chunks
+            .drain(..)
+            .partition(|c| c.chunk_type().to_string() == chunk_type);
+        self.chunks = kept;
+
+        if removed.is_empty() {
+            return Err(Error::ChunkNotFound {
+                chunk_type: chunk_type.to_string(),
+            });
+        }
+
+        Ok(removed)
+    }
+    pub fn remove_at(&mut self, index: usize) -> Result<Chunk, Error> {
+        if index >= self.chunks.len() {
+            return Err(Error::IndexOutOfRange {
+                index,
+                length: self.chunks.len(),
+            });
+        }
 
         Ok(self.chunks.remove(index))
     }
+    /// Keeps only the chunks for which `predicate` returns `true`.
+    pub fn retain(&mut self, predicate: impl FnMut(&Chunk) -> bool) {
+        self.chunks.retain(predicate);
+    }
     pub fn header(&self) -> &[u8; 8] {
         &Png::STANDARD_HEADER
     }
+    /// Decodes the IHDR chunk.
+    pub fn header_info(&self) -> Result<ImageHeader, Error> {
+        let chunk: &Chunk = self
+            .chunks
+            .iter()
+            .find(|c| c.chunk_type().bytes() == ImageHeader::CHUNK_TYPE)
+            .ok_or(Error::MissingImageHeader)?;
+
+        ImageHeader::try_from(chunk.data())
+    }
     pub fn chunks(&self) -> &[Chunk] {
         &self.chunks
     }
-    pub fn chunk_by_type(&self, chunk_type: &str) -> Vec<&Chunk> {
+    /// The most message bytes `mode` can hide in this image, before any
+    /// encryption or file payload overhead. Chunk mode is only limited by
+    /// the maximum chunk length, and fragments lift even that.
+    pub fn capacity(&self, mode: &EmbedMode) -> Result<usize, Error> {
+        match mode {
+            EmbedMode::Chunk => Ok(Chunk::MAX_LENGTH as usize),
+            EmbedMode::Lsb(options) => {
+                lsb::capacity(&self.header_info()?, options)
+            }
+        }
+    }
+    /// The inflated contents of the IDAT chunks: every scanline, still
+    /// filtered and preceded by its filter type byte.
+    pub fn image_data(&self) -> Result<Vec<u8>, Error> {
+        image_data::read_scanlines(self)
+    }
+    /// Deflates filtered `scanlines` into new IDAT chunks of at most
+    /// `idat_size` bytes each, replacing the old ones.
+    pub fn set_image_data(
+        &mut self,
+        scanlines: &[u8],
+        idat_size: usize,
+    ) -> Result<(), Error> {
+        image_data::write_scanlines(self, scanlines, idat_size)
+    }
+    /// Decodes the pixels, in the color type and bit depth they are stored
+    /// with. [`ImageBuffer::expand_palette`] turns palette indices into
+    /// colors.
+    pub fn decode_image(&self) -> Result<ImageBuffer, Error> {
+        ImageBuffer::decode(self)
+    }
+    /// Replaces the pixels with `image`, keeping the other chunks. Chunks
+    /// that describe the old pixel format are dropped if `image` is stored
+    /// another way.
+    pub fn set_image(&mut self, image: &ImageBuffer) -> Result<(), Error> {
+        image::write_image(self, image)
+    }
+    /// Decodes every tEXt, zTXt and iTXt chunk, in file order.
+    pub fn text_chunks(&self) -> Result<Vec<TextChunk>, Error> {
         self.chunks
             .iter()
-            .filter(|c| c.chunk_type().to_string() == chunk_type)
+            .filter(|c| TextChunk::is_text_chunk(c.chunk_type()))
+            .map(TextChunk::try_from)
             .collect()
     }
-    pub fn as_bytes(&self) -> Vec<u8> {
-        let mut res: Vec<u8> = Png::STANDARD_HEADER.into();
+    /// Stores `text` in place of the first text chunk with the same
+    /// keyword and drops any others, or inserts it before IEND if there is
+    /// none.
+    pub fn set_text(&mut self, text: &TextChunk) -> Result<(), Error> {
+        let mut chunk: Option<Chunk> = Some(text.to_chunk()?);
+        let mut chunks: Vec<Chunk> = Vec::with_capacity(self.chunks.len());
+        for c in self.chunks.drain(..) {
+            if TextChunk::has_keyword(&c, text.keyword()) {
+                chunks.extend(chunk.take());
+            } else {
+                chunks.push(c);
+            }
+        }
+        self.chunks = chunks;
 
-        for chunk in self.chunks.iter() {
-            let mut one_chunk: Vec<u8> =
-                u32::to_be_bytes(chunk.length()).into_iter().collect();
-            one_chunk.extend(chunk.chunk_type().bytes());
-            one_chunk.extend(chunk.data());
-            one_chunk.extend(u32::to_be_bytes(chunk.crc()));
+        if let Some(chunk) = chunk {
+            self.insert_chunk(chunk);
+        }
+        Ok(())
+    }
+    /// Removes every text chunk with this keyword, failing if there is
+    /// none.
+    pub fn remove_text(&mut self, keyword: &str) -> Result<Vec<Chunk>, Error> {
+        let (removed, kept): (Vec<Chunk>, Vec<Chunk>) = self
+            .chunks
+            .drain(..)
+            .partition(|c| TextChunk::has_keyword(c, keyword));
+        self.chunks = kept;
 
-            res.extend(one_chunk);
+        if removed.is_empty() {
+            return Err(Error::KeywordNotFound {
+                keyword: keyword.to_string(),
+            });
         }
 
-        res
+        Ok(removed)
+    }
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Vec<&Chunk> {
+        self.chunks
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == chunk_type)
+            .collect()
+    }
+    /// Serializes the image. Fails if a chunk holds more than
+    /// [`Chunk::MAX_LENGTH`] bytes, which `Chunk::new` does not prevent.
+    pub fn as_bytes(&self) -> Result<Vec<u8>, Error> {
+        self.write_to(Vec::new())
+    }
+    pub fn write_to<W: Write>(&self, writer: W) -> Result<W, Error> {
+        let mut writer: ChunkWriter<W> = ChunkWriter::new(writer)?;
+        for chunk in self.chunks.iter() {
+            writer.write_chunk(chunk)?;
+        }
+        writer.finish()
     }
 }
 
@@ -60,27 +297,106 @@ impl TryFrom<&[u8]> for Png {
     type Error = Error;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Ok(PngRef::try_from(value)?.into())
+    }
+}
+
+impl Display for Png {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        // Chunks are rendered one by one so that an oversized chunk cannot
+        // make formatting fail.
+        let bytes: Vec<u8> = Png::STANDARD_HEADER
+            .iter()
+            .copied()
+            .chain(self.chunks.iter().flat_map(Chunk::as_bytes))
+            .collect();
+        write!(f, "{:?}", bytes)
+    }
+}
+
+/// An index over the chunks of a PNG buffer that borrows their data
+/// instead of copying it.
+pub struct PngRef<'a> {
+    chunks: Vec<ChunkRef<'a>>,
+}
+
+impl<'a> PngRef<'a> {
+    /// Decodes the IHDR chunk.
+    pub fn header_info(&self) -> Result<ImageHeader, Error> {
+        let chunk: &ChunkRef<'a> = self
+            .chunks
+            .iter()
+            .find(|c| c.chunk_type().bytes() == ImageHeader::CHUNK_TYPE)
+            .ok_or(Error::MissingImageHeader)?;
+
+        ImageHeader::try_from(chunk.data())
+    }
+    pub fn chunks(&self) -> &[ChunkRef<'a>] {
+        &self.chunks
+    }
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Vec<&ChunkRef<'a>> {
+        self.chunks
+            .iter()
+            .filter(|c| c.chunk_type().bytes() == chunk_type.as_bytes())
+            .collect()
+    }
+    pub fn to_png(&self) -> Png {
+        Png::from_chunks(self.chunks.iter().map(|&c| c.into()).collect())
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for PngRef<'a> {
+    type Error = Error;
+
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
         if value.len() < Png::STANDARD_HEADER.len() {
-            return Err(Box::new(PngError::TooSmall));
+            return Err(Error::FileTooSmall {
+                length: value.len(),
+            });
         }
 
         if value[0..Png::STANDARD_HEADER.len()] != Png::STANDARD_HEADER {
-            return Err(Box::new(PngError::InvalidHeader));
+            let mut found: [u8; 8] = [0; 8];
+            found.copy_from_slice(&value[0..Png::STANDARD_HEADER.len()]);
+            return Err(Error::InvalidSignature { found });
         }
 
-        let mut chunks: Vec<Chunk> = Vec::new();
+        let mut chunks: Vec<ChunkRef<'a>> = Vec::new();
         let mut index: usize = Png::STANDARD_HEADER.len();
 
         while index < value.len() {
-            let len = u32::from_be_bytes(
-                value[index..index + Chunk::LENGTH_SIZE].try_into()?,
-            ) as usize;
+            let rest: &[u8] = &value[index..];
+
+            if rest.len() < Chunk::LENGTH_SIZE {
+                return Err(Error::TruncatedLength {
+                    offset: index,
+                    available: rest.len(),
+                });
+            }
+
+            let length: u32 = chunk::read_u32(rest);
+
+            if length > Chunk::MAX_LENGTH {
+                return Err(Error::ChunkTooLong {
+                    offset: index,
+                    length,
+                });
+            }
+
+            let size: usize = Chunk::METADATA_SIZE + length as usize;
 
-            let chunk: Chunk = Chunk::try_from(
-                &value[index..index + Chunk::METADATA_SIZE + len],
-            )?;
+            if rest.len() < size {
+                return Err(Error::TruncatedData {
+                    offset: index,
+                    needed: size,
+                    available: rest.len(),
+                });
+            }
+
+            let chunk: ChunkRef<'a> = ChunkRef::try_from(&rest[..size])
+                .map_err(|e| e.at_offset(index))?;
 
-            index += chunk.length() as usize + Chunk::METADATA_SIZE;
+            index += size;
 
             chunks.push(chunk);
         }
@@ -89,30 +405,9 @@ impl TryFrom<&[u8]> for Png {
     }
 }
 
-impl Display for Png {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "{:?}", self.as_bytes())
-    }
-}
-
-#[derive(Debug)]
-enum PngError {
-    TooSmall,
-    InvalidHeader,
-}
-
-impl error::Error for PngError {}
-
-impl Display for PngError {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        match self {
-            PngError::TooSmall => {
-                write!(f, "Invalid Png size!")
-            }
-            PngError::InvalidHeader => {
-                write!(f, "Invalid header!")
-            }
-        }
+impl From<PngRef<'_>> for Png {
+    fn from(value: PngRef<'_>) -> Self {
+        value.to_png()
     }
 }
 
@@ -223,7 +518,7 @@ mod tests {
     #[test]
     fn test_chunk_by_type() {
         let png = testing_png();
-        let chunk = png.chunk_by_type("FrSt").unwrap();
+        let chunk = png.chunk_by_type("FrSt")[0];
         assert_eq!(&chunk.chunk_type().to_string(), "FrSt");
         assert_eq!(&chunk.data_as_string().unwrap(), "I am the first chunk");
     }
@@ -232,7 +527,7 @@ mod tests {
     fn test_append_chunk() {
         let mut png = testing_png();
         png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
-        let chunk = png.chunk_by_type("TeSt").unwrap();
+        let chunk = png.chunk_by_type("TeSt")[0];
         assert_eq!(&chunk.chunk_type().to_string(), "TeSt");
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
@@ -243,7 +538,236 @@ mod tests {
         png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
         png.remove_chunk("TeSt").unwrap();
         let chunk = png.chunk_by_type("TeSt");
-        assert!(chunk.is_none());
+        assert!(chunk.is_empty());
+    }
+
+    #[test]
+    fn test_remove_missing_chunk() {
+        let mut png = testing_png();
+        let err = png.remove_chunk("TeSt").err().unwrap();
+        assert!(matches!(err, Error::ChunkNotFound { .. }));
+    }
+
+    #[test]
+    fn test_remove_all() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "One").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "Two").unwrap());
+
+        let removed = png.remove_all("TeSt").unwrap();
+
+        assert_eq!(removed.len(), 2);
+        assert_eq!(&removed[1].data_as_string().unwrap(), "Two");
+        assert_eq!(png.chunks().len(), 3);
+        assert!(matches!(
+            png.remove_all("TeSt"),
+            Err(Error::ChunkNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_remove_at() {
+        let mut png = testing_png();
+
+        let removed = png.remove_at(1).unwrap();
+
+        assert_eq!(&removed.chunk_type().to_string(), "miDl");
+        assert_eq!(png.chunks().len(), 2);
+        assert!(matches!(
+            png.remove_at(2),
+            Err(Error::IndexOutOfRange {
+                index: 2,
+                length: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut png = testing_png();
+        png.retain(|c| c.chunk_type().is_critical());
+        assert_eq!(chunk_types(&png), ["FrSt", "LASt"]);
+    }
+
+    #[test]
+    fn test_invalid_crc_offset() {
+        let mut bytes: Vec<u8> = PNG_FILE.to_vec();
+        // Last byte of the IHDR CRC.
+        bytes[32] ^= 1;
+
+        let err = Png::try_from(bytes.as_ref()).err().unwrap();
+
+        assert!(matches!(err, Error::InvalidCrc { offset: 8, .. }));
+        assert_eq!(err.chunk_type().unwrap().to_string(), "IHDR");
+    }
+
+    #[test]
+    fn test_header_info() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let header = png.header_info().unwrap();
+
+        assert_eq!(header.width(), 50);
+        assert_eq!(header.height(), 50);
+        assert_eq!(header.bit_depth(), 8);
+        assert_eq!(header.color_type(), crate::header::ColorType::Rgba);
+        assert!(matches!(
+            testing_png().header_info(),
+            Err(Error::MissingImageHeader)
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert!(png.validate().is_ok());
+        assert!(testing_png().validate().is_err());
+
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        assert!(matches!(
+            png.validate(),
+            Err(Error::InvalidStructure {
+                violation: structure::Violation::ChunkAfterEnd,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_from_reader_validated() {
+        assert!(Png::from_reader_validated(&PNG_FILE[..]).is_ok());
+
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        let bytes = png.as_bytes().unwrap();
+        assert!(Png::from_reader(bytes.as_slice()).is_ok());
+        assert!(matches!(
+            Png::from_reader_validated(bytes.as_slice()),
+            Err(Error::InvalidStructure { .. })
+        ));
+    }
+
+    #[test]
+    fn test_insert_chunk_before_end() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+
+        assert_eq!(types[types.len() - 2..], ["TeSt", "IEND"]);
+        assert!(png.validate().is_ok());
+    }
+
+    fn chunk_types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_insert_chunk_at() {
+        let mut png = testing_png();
+        png.insert_chunk_at(1, chunk_from_strings("TeSt", "Message").unwrap())
+            .unwrap();
+        assert_eq!(chunk_types(&png), ["FrSt", "TeSt", "miDl", "LASt"]);
+
+        let err = png
+            .insert_chunk_at(5, chunk_from_strings("TeSt", "Message").unwrap())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err,
+            Error::IndexOutOfRange {
+                index: 5,
+                length: 4
+            }
+        ));
+    }
+
+    #[test]
+    fn test_insert_chunk_before_and_after() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk_before(
+            "IDAT",
+            chunk_from_strings("BeFo", "").unwrap(),
+        )
+        .unwrap();
+        png.insert_chunk_after("IHDR", chunk_from_strings("AfTe", "").unwrap())
+            .unwrap();
+
+        assert_eq!(
+            chunk_types(&png),
+            [
+                "IHDR", "AfTe", "sRGB", "gAMA", "pHYs", "BeFo", "IDAT", "RuSt",
+                "IEND"
+            ]
+        );
+        assert!(png
+            .insert_chunk_after("PLTE", chunk_from_strings("AfTe", "").unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn test_png_ref_from_image_file() {
+        let png = PngRef::try_from(&PNG_FILE[..]).unwrap();
+        let chunk = png.chunk_by_type("RuSt")[0];
+
+        assert_eq!(png.chunks().len(), 7);
+        assert_eq!(chunk.data_as_str().unwrap(), "hey");
+        assert_eq!(png.to_png().as_bytes().unwrap(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_truncated_file_never_panics() {
+        for end in 0..PNG_FILE.len() {
+            if let Err(err) = Png::try_from(&PNG_FILE[..end]) {
+                assert!(matches!(
+                    err,
+                    Error::FileTooSmall { .. }
+                        | Error::TruncatedLength { .. }
+                        | Error::TruncatedData { .. }
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncated_length() {
+        let png = Png::try_from(&PNG_FILE[..10]);
+        assert!(matches!(
+            png,
+            Err(Error::TruncatedLength {
+                offset: 8,
+                available: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_truncated_data() {
+        let png = Png::try_from(&PNG_FILE[..20]);
+        assert!(matches!(
+            png,
+            Err(Error::TruncatedData {
+                offset: 8,
+                needed: 25,
+                available: 12
+            })
+        ));
+    }
+
+    #[test]
+    fn test_huge_length() {
+        let mut bytes: Vec<u8> = PNG_FILE.to_vec();
+        bytes[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+
+        let png = Png::try_from(bytes.as_ref());
+
+        assert!(matches!(png, Err(Error::ChunkTooLong { offset: 8, .. })));
     }
 
     #[test]
@@ -255,11 +779,116 @@ mod tests {
     #[test]
     fn test_as_bytes() {
         let png = Png::try_from(&PNG_FILE[..]).unwrap();
-        let actual = png.as_bytes();
+        let actual = png.as_bytes().unwrap();
         let expected: Vec<u8> = PNG_FILE.to_vec();
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_image_data() {
+        use crate::{
+            header::{ColorType, Interlace},
+            image_data::testing_png,
+        };
+
+        let header =
+            ImageHeader::new(16, 4, 8, ColorType::Grayscale, Interlace::None)
+                .unwrap();
+        let mut png = testing_png(&header, &[7; 64]);
+        let mut scanlines = png.image_data().unwrap();
+        assert_eq!(scanlines.len(), 4 * 17);
+        assert!(scanlines.chunks(17).all(|row| row[0] == 0 && row[1] == 7));
+
+        scanlines[1] = 9;
+        png.set_image_data(&scanlines, 10).unwrap();
+        let idat = png.chunk_by_type("IDAT");
+        assert!(idat.len() > 1);
+        assert!(idat.iter().all(|c| c.length() <= 10));
+        assert_eq!(png.image_data().unwrap(), scanlines);
+    }
+
+    #[test]
+    fn test_text() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let count = png.chunks().len();
+        assert!(png.text_chunks().unwrap().is_empty());
+
+        png.set_text(&TextChunk::new("Title", "First", false))
+            .unwrap();
+        png.set_text(&TextChunk::new("Author", "Ferris", true))
+            .unwrap();
+        png.append_chunk(
+            TextChunk::new("Title", "Again", false).to_chunk().unwrap(),
+        );
+        assert_eq!(png.chunks().len(), count + 3);
+        assert_eq!(png.chunks()[count - 1].chunk_type().to_string(), "tEXt");
+
+        // The first Title is replaced where it is and the second dropped.
+        png.set_text(&TextChunk::new("Title", "Última", true))
+            .unwrap();
+        let texts: Vec<String> = png
+            .text_chunks()
+            .unwrap()
+            .iter()
+            .map(TextChunk::to_string)
+            .collect();
+        assert_eq!(texts, ["Title: Última", "Author: Ferris"]);
+        assert_eq!(png.chunks()[count - 1].chunk_type().to_string(), "zTXt");
+
+        assert_eq!(png.remove_text("Author").unwrap().len(), 1);
+        assert!(matches!(
+            png.remove_text("Author"),
+            Err(Error::KeywordNotFound { .. })
+        ));
+        assert_eq!(png.text_chunks().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_capacity() {
+        use crate::{
+            header::{ColorType, Interlace},
+            image_data::testing_png,
+            lsb::Channels,
+        };
+
+        let header =
+            ImageHeader::new(64, 48, 8, ColorType::Rgba, Interlace::None)
+                .unwrap();
+        let png = testing_png(&header, &vec![0; header.row_bytes() * 48]);
+        let lsb = |bits, channels| {
+            EmbedMode::Lsb(LsbOptions::new(bits, channels).unwrap())
+        };
+
+        assert_eq!(
+            png.capacity(&EmbedMode::Chunk).unwrap(),
+            Chunk::MAX_LENGTH as usize
+        );
+        assert_eq!(
+            png.capacity(&lsb(1, Channels::Color)).unwrap(),
+            64 * 48 * 3 / 8 - 8
+        );
+        assert_eq!(
+            png.capacity(&lsb(2, Channels::Color)).unwrap(),
+            64 * 48 * 3 / 4 - 8
+        );
+        assert_eq!(
+            png.capacity(&lsb(4, Channels::Color)).unwrap(),
+            64 * 48 * 3 / 2 - 8
+        );
+        assert_eq!(
+            png.capacity(&lsb(1, Channels::Alpha)).unwrap(),
+            64 * 48 / 8 - 8
+        );
+        assert_eq!(
+            lsb(2, Channels::Alpha).to_string(),
+            "LSB 2 bits per alpha channel"
+        );
+
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert!(png.capacity(&EmbedMode::Chunk).is_ok());
+        assert!(png.capacity(&lsb(1, Channels::Color)).is_ok());
+    }
+
     #[test]
     fn test_png_trait_impls() {
         let chunk_bytes: Vec<u8> = testing_chunks()
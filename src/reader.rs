@@ -0,0 +1,439 @@
+use crate::{
+    chunk::{self, Chunk, CRC_PNG},
+    chunk_type::ChunkType,
+    png::Png,
+    structure::StructureValidator,
+    Error, Result,
+};
+use crc::Digest;
+use std::io::{self, Read};
+
+const SKIP_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Length and type of a chunk whose data has not been read yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkHeader {
+    pub offset: usize,
+    pub length: u32,
+    pub chunk_type: ChunkType,
+}
+
+struct Pending {
+    header: ChunkHeader,
+    remaining: u32,
+    digest: Digest<'static, u32>,
+}
+
+/// Reads a PNG stream one chunk at a time.
+///
+/// The signature is checked when the reader is created. Chunk data can be
+/// read as a whole with [`ChunkReader::read_chunk`], or lazily by calling
+/// [`ChunkReader::next_header`] and then either streaming it with
+/// [`ChunkReader::read_data`] or leaving it to be skipped. The CRC of every
+/// chunk is verified as its last byte goes past, skipped or not, so memory
+/// use does not depend on the size of the file.
+pub struct ChunkReader<R> {
+    reader: R,
+    offset: usize,
+    pending: Option<Pending>,
+    validator: Option<StructureValidator>,
+    failed: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    pub fn new(reader: R) -> Result<ChunkReader<R>> {
+        let mut new: ChunkReader<R> = ChunkReader {
+            reader,
+            offset: 0,
+            pending: None,
+            validator: None,
+            failed: false,
+        };
+
+        let mut signature: [u8; 8] = [0; 8];
+        let read: usize = new.read_full(&mut signature)?;
+
+        if read < signature.len() {
+            return Err(Error::FileTooSmall { length: read });
+        }
+
+        if signature != Png::STANDARD_HEADER {
+            return Err(Error::InvalidSignature { found: signature });
+        }
+
+        Ok(new)
+    }
+
+    /// Like [`ChunkReader::new`], but also fails as soon as the chunk
+    /// sequence breaks an ordering rule or ends without IEND.
+    pub fn new_validated(reader: R) -> Result<ChunkReader<R>> {
+        let mut new: ChunkReader<R> = ChunkReader::new(reader)?;
+        new.validator = Some(StructureValidator::new());
+        Ok(new)
+    }
+
+    /// Byte offset of the next unread byte in the stream.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Reads the length and type of the next chunk, skipping whatever is
+    /// left of the current one. Returns `None` at the end of the stream.
+    pub fn next_header(&mut self) -> Result<Option<ChunkHeader>> {
+        self.guard(|reader| {
+            reader.finish_chunk()?;
+            reader.read_header()
+        })
+    }
+
+    /// Streams data of the current chunk into `buf`, returning 0 once all of
+    /// it has been read. The CRC is checked when the data runs out.
+    pub fn read_data(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.guard(|reader| reader.read_pending(buf))
+    }
+
+    /// Reads the rest of the current chunk's data into memory.
+    pub fn read_chunk_data(&mut self) -> Result<Vec<u8>> {
+        let mut data: Vec<u8> = Vec::new();
+        self.read_chunk_data_into(&mut data)?;
+        Ok(data)
+    }
+
+    /// Like [`ChunkReader::read_chunk_data`], but reuses `data` so a loop
+    /// over many chunks does not allocate once the buffer is large enough.
+    pub fn read_chunk_data_into(&mut self, data: &mut Vec<u8>) -> Result<()> {
+        data.clear();
+
+        // Grow in steps rather than trusting the length field up front, so
+        // a truncated stream claiming a huge chunk cannot force a huge
+        // allocation.
+        loop {
+            let remaining: usize = match &self.pending {
+                Some(pending) => pending.remaining as usize,
+                None => 0,
+            };
+            let start: usize = data.len();
+            data.resize(start + remaining.min(SKIP_BUFFER_SIZE), 0);
+            let read: usize = self.read_data(&mut data[start..])?;
+            data.truncate(start + read);
+            if read == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reads the next complete chunk. Returns `None` at the end of the
+    /// stream.
+    pub fn read_chunk(&mut self) -> Result<Option<Chunk>> {
+        let header: ChunkHeader = match self.next_header()? {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        let data: Vec<u8> = self.read_chunk_data()?;
+
+        Ok(Some(Chunk::new(header.chunk_type, data)))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn guard<T>(
+        &mut self,
+        f: impl FnOnce(&mut ChunkReader<R>) -> Result<T>,
+    ) -> Result<T> {
+        let result: Result<T> = f(self);
+        if result.is_err() {
+            self.failed = true;
+            self.pending = None;
+        }
+        result
+    }
+
+    fn finish_chunk(&mut self) -> Result<()> {
+        let mut buf: [u8; SKIP_BUFFER_SIZE] = [0; SKIP_BUFFER_SIZE];
+        while self.pending.is_some() {
+            self.read_pending(&mut buf)?;
+        }
+        Ok(())
+    }
+
+    fn read_header(&mut self) -> Result<Option<ChunkHeader>> {
+        if self.failed {
+            return Ok(None);
+        }
+
+        let offset: usize = self.offset;
+        let mut fields: [u8; Chunk::LENGTH_SIZE + Chunk::TYPE_SIZE] =
+            [0; Chunk::LENGTH_SIZE + Chunk::TYPE_SIZE];
+        let read: usize = self.read_full(&mut fields)?;
+
+        if read == 0 {
+            if let Some(validator) = &self.validator {
+                validator.finish()?;
+            }
+            return Ok(None);
+        }
+
+        if read < Chunk::LENGTH_SIZE {
+            return Err(Error::TruncatedLength {
+                offset,
+                available: read,
+            });
+        }
+
+        let length: u32 = chunk::read_u32(&fields);
+
+        if length > Chunk::MAX_LENGTH {
+            return Err(Error::ChunkTooLong { offset, length });
+        }
+
+        if read < fields.len() {
+            return Err(Error::TruncatedData {
+                offset,
+                needed: Chunk::METADATA_SIZE + length as usize,
+                available: read,
+            });
+        }
+
+        let mut type_bytes: [u8; 4] = [0; 4];
+        type_bytes.copy_from_slice(&fields[Chunk::LENGTH_SIZE..]);
+        let chunk_type: ChunkType =
+            ChunkType::try_from(type_bytes).map_err(|e| {
+                Error::InvalidChunkType {
+                    offset: offset + Chunk::LENGTH_SIZE,
+                    source: Box::new(e),
+                }
+            })?;
+
+        if let Some(validator) = &mut self.validator {
+            validator.push(&chunk_type)?;
+        }
+
+        let header: ChunkHeader = ChunkHeader {
+            offset,
+            length,
+            chunk_type,
+        };
+        let mut digest: Digest<'static, u32> = CRC_PNG.digest();
+        digest.update(&type_bytes);
+
+        self.pending = Some(Pending {
+            header,
+            remaining: length,
+            digest,
+        });
+
+        Ok(Some(header))
+    }
+
+    fn read_pending(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let remaining: usize = match &self.pending {
+            Some(pending) => pending.remaining as usize,
+            None => return Ok(0),
+        };
+
+        if remaining == 0 {
+            self.verify_crc()?;
+            return Ok(0);
+        }
+
+        let want: usize = remaining.min(buf.len());
+        let read: usize = self.read_full(&mut buf[..want])?;
+
+        if let Some(pending) = &mut self.pending {
+            if read < want {
+                let header: ChunkHeader = pending.header;
+                let consumed: usize = self.offset - header.offset;
+                return Err(Error::TruncatedData {
+                    offset: header.offset,
+                    needed: Chunk::METADATA_SIZE + header.length as usize,
+                    available: consumed,
+                });
+            }
+            pending.digest.update(&buf[..read]);
+            pending.remaining -= read as u32;
+        }
+
+        Ok(read)
+    }
+
+    fn verify_crc(&mut self) -> Result<()> {
+        let pending: Pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        let header: ChunkHeader = pending.header;
+        let mut crc_bytes: [u8; Chunk::CRC_SIZE] = [0; Chunk::CRC_SIZE];
+        let read: usize = self.read_full(&mut crc_bytes)?;
+
+        if read < crc_bytes.len() {
+            return Err(Error::TruncatedData {
+                offset: header.offset,
+                needed: Chunk::METADATA_SIZE + header.length as usize,
+                available: self.offset - header.offset,
+            });
+        }
+
+        let stored: u32 = u32::from_be_bytes(crc_bytes);
+        let computed: u32 = pending.digest.finalize();
+
+        if stored != computed {
+            return Err(Error::InvalidCrc {
+                offset: header.offset,
+                chunk_type: header.chunk_type,
+                stored,
+                computed,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn read_full(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut filled: usize = 0;
+
+        while filled < buf.len() {
+            match self.reader.read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.offset += filled;
+
+        Ok(filled)
+    }
+}
+
+impl<R: Read> Iterator for ChunkReader<R> {
+    type Item = Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_chunk().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{convert::TryFrom, str::FromStr};
+
+    fn testing_bytes() -> Vec<u8> {
+        let chunks: Vec<Chunk> = vec![
+            Chunk::new(
+                ChunkType::from_str("FrSt").unwrap(),
+                b"I am the first chunk".to_vec(),
+            ),
+            Chunk::new(
+                ChunkType::from_str("miDl").unwrap(),
+                b"I am another chunk".to_vec(),
+            ),
+            Chunk::new(
+                ChunkType::from_str("LASt").unwrap(),
+                b"I am the last chunk".to_vec(),
+            ),
+        ];
+
+        Png::from_chunks(chunks).as_bytes().unwrap()
+    }
+
+    #[test]
+    fn test_read_all_chunks() {
+        let bytes: Vec<u8> = testing_bytes();
+        let reader = ChunkReader::new(bytes.as_slice()).unwrap();
+        let chunks: Vec<Chunk> = reader.collect::<Result<_>>().unwrap();
+        let expected: Png = Png::try_from(bytes.as_slice()).unwrap();
+
+        assert_eq!(chunks.len(), 3);
+        for (actual, expected) in chunks.iter().zip(expected.chunks()) {
+            assert_eq!(actual.as_bytes(), expected.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_skip_chunks_by_header() {
+        let bytes: Vec<u8> = testing_bytes();
+        let mut reader = ChunkReader::new(bytes.as_slice()).unwrap();
+        let mut types: Vec<String> = Vec::new();
+
+        while let Some(header) = reader.next_header().unwrap() {
+            types.push(header.chunk_type.to_string());
+        }
+
+        assert_eq!(types, ["FrSt", "miDl", "LASt"]);
+        assert_eq!(reader.offset(), bytes.len());
+    }
+
+    #[test]
+    fn test_lazy_data() {
+        let bytes: Vec<u8> = testing_bytes();
+        let mut reader = ChunkReader::new(bytes.as_slice()).unwrap();
+
+        reader.next_header().unwrap();
+        let header = reader.next_header().unwrap().unwrap();
+        let data = reader.read_chunk_data().unwrap();
+
+        assert_eq!(header.offset, 8 + 12 + 20);
+        assert_eq!(header.chunk_type.to_string(), "miDl");
+        assert_eq!(data, b"I am another chunk");
+    }
+
+    #[test]
+    fn test_invalid_signature() {
+        let mut bytes: Vec<u8> = testing_bytes();
+        bytes[0] = 13;
+
+        let reader = ChunkReader::new(bytes.as_slice());
+
+        assert!(matches!(reader, Err(Error::InvalidSignature { .. })));
+    }
+
+    #[test]
+    fn test_skipped_chunk_crc_is_checked() {
+        let mut bytes: Vec<u8> = testing_bytes();
+        // Last byte of the first chunk's CRC.
+        bytes[8 + 12 + 20 - 1] ^= 1;
+
+        let mut reader = ChunkReader::new(bytes.as_slice()).unwrap();
+        reader.next_header().unwrap();
+        let err = reader.next_header().err().unwrap();
+
+        assert!(matches!(err, Error::InvalidCrc { offset: 8, .. }));
+        assert!(reader.next_header().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_validated_reader() {
+        let bytes: Vec<u8> = testing_bytes();
+
+        let mut reader = ChunkReader::new_validated(bytes.as_slice()).unwrap();
+
+        assert!(matches!(
+            reader.next_header(),
+            Err(Error::InvalidStructure { index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_truncated_stream() {
+        let bytes: Vec<u8> = testing_bytes();
+
+        for end in 8..bytes.len() {
+            let reader = ChunkReader::new(&bytes[..end]).unwrap();
+            let result: Result<Vec<Chunk>> = reader.collect();
+            let expected: Result<Png> = Png::try_from(&bytes[..end]);
+
+            match (result, expected) {
+                (Ok(chunks), Ok(png)) => {
+                    assert_eq!(chunks.len(), png.chunks().len())
+                }
+                (Err(err), Err(expected)) => {
+                    assert_eq!(err.to_string(), expected.to_string())
+                }
+                _ => panic!("reader and parser disagree at {}", end),
+            }
+        }
+    }
+}
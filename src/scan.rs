@@ -0,0 +1,831 @@
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    header::{ColorType, ImageHeader},
+    image_data::RawImage,
+    png::Png,
+    reader::ChunkReader,
+    structure::{self, Violation},
+    Error, Result,
+};
+use std::fmt::{self, Display, Formatter};
+
+/// Ancillary chunks bigger than this are reported as oversized. Color
+/// profiles and metadata rarely come close.
+pub const OVERSIZED_CHUNK: usize = 64 * 1024;
+
+/// Chunk types defined by the PNG specification and its common extensions.
+const KNOWN_CHUNKS: [[u8; 4]; 26] = [
+    *b"IHDR", *b"PLTE", *b"IDAT", *b"IEND", *b"tRNS", *b"cHRM", *b"gAMA",
+    *b"iCCP", *b"sBIT", *b"sRGB", *b"cICP", *b"mDCV", *b"cLLI", *b"tEXt",
+    *b"zTXt", *b"iTXt", *b"bKGD", *b"hIST", *b"pHYs", *b"sPLT", *b"eXIf",
+    *b"tIME", *b"acTL", *b"fcTL", *b"fdAT", *b"oFFs",
+];
+
+// Windows smaller than this give the chi-square test too little to go on.
+const MIN_CHI_SQUARE_SAMPLES: usize = 2048;
+// Value pairs seen fewer times than this are left out of the chi-square sum.
+const MIN_PAIR_COUNT: u64 = 10;
+// Number of samples in each group of the RS analysis.
+const RS_GROUP: usize = 4;
+
+/// How strongly a finding points at hidden data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Low,
+    Medium,
+    High,
+}
+
+impl Severity {
+    /// Points this severity adds to the score of a [`Report`].
+    pub fn weight(&self) -> u32 {
+        match self {
+            Severity::Info => 0,
+            Severity::Low => 5,
+            Severity::Medium => 20,
+            Severity::High => 40,
+        }
+    }
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let name: &str = match self {
+            Severity::Info => "info",
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Something about an image that hints at hidden data.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Finding {
+    /// A private chunk, whose type only its author knows.
+    PrivateChunk {
+        index: usize,
+        chunk_type: ChunkType,
+        length: usize,
+    },
+    /// A public chunk type the specification does not define.
+    UnknownChunk { index: usize, chunk_type: ChunkType },
+    /// An ancillary chunk larger than [`OVERSIZED_CHUNK`].
+    OversizedChunk {
+        index: usize,
+        chunk_type: ChunkType,
+        length: usize,
+    },
+    /// Bytes after the end of IEND, which every decoder ignores.
+    TrailingData { offset: usize, length: usize },
+    /// The file could not be read past `offset`, because of a bad CRC or a
+    /// chunk that is cut short. Only the chunks before it were scanned.
+    Damaged { offset: usize, reason: String },
+    /// The chunk sequence breaks an ordering rule.
+    Ordering {
+        index: usize,
+        chunk_type: Option<ChunkType>,
+        violation: Violation,
+    },
+    /// The chi-square attack finds the pairs of values differing only in
+    /// their low bit evened out, as embedding in LSBs does. `probability`
+    /// is the highest seen over the first `fraction` of the samples.
+    LsbChiSquare { probability: f64, fraction: f64 },
+    /// RS analysis estimates this fraction of the samples carries a
+    /// message in its low bit.
+    LsbRsAnalysis { rate: f64 },
+    /// The pixels were not analyzed.
+    PixelsSkipped { reason: String },
+}
+
+impl Finding {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Finding::PrivateChunk { .. } => Severity::Medium,
+            Finding::UnknownChunk { .. } => Severity::Low,
+            Finding::OversizedChunk { .. } => Severity::Medium,
+            Finding::TrailingData { .. } => Severity::High,
+            Finding::Damaged { .. } => Severity::Medium,
+            Finding::Ordering { .. } => Severity::Low,
+            Finding::LsbChiSquare { probability, .. }
+                if *probability > 0.95 =>
+            {
+                Severity::High
+            }
+            Finding::LsbChiSquare { .. } => Severity::Medium,
+            Finding::LsbRsAnalysis { rate } if *rate > 0.25 => Severity::High,
+            Finding::LsbRsAnalysis { .. } => Severity::Medium,
+            Finding::PixelsSkipped { .. } => Severity::Info,
+        }
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Finding::PrivateChunk {
+                index,
+                chunk_type,
+                length,
+            } => write!(
+                f,
+                "private chunk {} at position {} ({} bytes)",
+                chunk_type, index, length
+            ),
+            Finding::UnknownChunk { index, chunk_type } => write!(
+                f,
+                "unknown public chunk {} at position {}",
+                chunk_type, index
+            ),
+            Finding::OversizedChunk {
+                index,
+                chunk_type,
+                length,
+            } => write!(
+                f,
+                "oversized {} chunk at position {} ({} bytes)",
+                chunk_type, index, length
+            ),
+            Finding::TrailingData { offset, length } => {
+                write!(f, "{} bytes after IEND at offset {}", length, offset)
+            }
+            Finding::Damaged { offset, reason } => {
+                write!(f, "unreadable from offset {}: {}", offset, reason)
+            }
+            Finding::Ordering {
+                index,
+                chunk_type: Some(chunk_type),
+                violation,
+            } => write!(
+                f,
+                "{} chunk at position {}: {}",
+                chunk_type, index, violation
+            ),
+            Finding::Ordering { violation, .. } => {
+                write!(f, "chunk structure: {}", violation)
+            }
+            Finding::LsbChiSquare {
+                probability,
+                fraction,
+            } => write!(
+                f,
+                "chi-square attack: {:.1}% embedding probability in the \
+                 first {:.0}% of the pixels",
+                probability * 100.0,
+                fraction * 100.0
+            ),
+            Finding::LsbRsAnalysis { rate } => write!(
+                f,
+                "RS analysis: about {:.1}% of the samples carry hidden bits",
+                rate * 100.0
+            ),
+            Finding::PixelsSkipped { reason } => {
+                write!(f, "pixels not analyzed: {}", reason)
+            }
+        }
+    }
+}
+
+/// The statistics of the low bits of an image's color samples, whether
+/// they crossed a threshold or not.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LsbStatistics {
+    /// Highest chi-square embedding probability over the prefixes tested.
+    pub chi_square: f64,
+    /// Share of the samples tested that the highest probability covers.
+    pub chi_square_fraction: f64,
+    /// Embedding rate estimated by RS analysis, from 0 to 1.
+    pub rs_rate: f64,
+}
+
+/// What [`scan`] found, with an overall score from 0 to 100.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    findings: Vec<Finding>,
+    lsb: Option<LsbStatistics>,
+}
+
+impl Report {
+    /// Findings, most severe first.
+    pub fn findings(&self) -> &[Finding] {
+        &self.findings
+    }
+    pub fn lsb_statistics(&self) -> Option<&LsbStatistics> {
+        self.lsb.as_ref()
+    }
+    /// The severity weights of all findings added up, capped at 100.
+    pub fn score(&self) -> u32 {
+        self.findings
+            .iter()
+            .map(|finding| finding.severity().weight())
+            .sum::<u32>()
+            .min(100)
+    }
+    /// A one-word reading of the score.
+    pub fn verdict(&self) -> &'static str {
+        match self.score() {
+            0 => "clean",
+            1..=19 => "unusual",
+            20..=49 => "suspicious",
+            _ => "likely hiding data",
+        }
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "Score: {}/100 ({})", self.score(), self.verdict())?;
+        for finding in &self.findings {
+            writeln!(f, "[{}] {}", finding.severity(), finding)?;
+        }
+        if let Some(lsb) = &self.lsb {
+            write!(
+                f,
+                "LSB statistics: chi-square {:.3} (first {:.0}%), \
+                 RS rate {:.3}",
+                lsb.chi_square,
+                lsb.chi_square_fraction * 100.0,
+                lsb.rs_rate
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Looks through a whole PNG file for signs of hidden data: in its chunks,
+/// after its end, and in the low bits of its pixels. A damaged file is
+/// scanned up to the damage.
+pub fn scan(bytes: &[u8]) -> Result<Report> {
+    let mut reader: ChunkReader<&[u8]> = ChunkReader::new(bytes)?;
+    let mut chunks: Vec<Chunk> = Vec::new();
+    let mut findings: Vec<Finding> = Vec::new();
+    let mut damaged: bool = false;
+    loop {
+        match reader.read_chunk() {
+            Ok(Some(chunk)) => {
+                let is_end: bool = chunk.chunk_type().bytes() == *b"IEND";
+                chunks.push(chunk);
+                if is_end {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                findings.push(Finding::Damaged {
+                    offset: e.offset().unwrap_or(reader.offset()),
+                    reason: e.to_string(),
+                });
+                damaged = true;
+                break;
+            }
+        }
+    }
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        let chunk_type: ChunkType = *chunk.chunk_type();
+        let length: usize = chunk.data().len();
+        if !chunk_type.is_public() {
+            findings.push(Finding::PrivateChunk {
+                index,
+                chunk_type,
+                length,
+            });
+        } else if !KNOWN_CHUNKS.contains(&chunk_type.bytes()) {
+            findings.push(Finding::UnknownChunk { index, chunk_type });
+        }
+        if !chunk_type.is_critical() && length > OVERSIZED_CHUNK {
+            findings.push(Finding::OversizedChunk {
+                index,
+                chunk_type,
+                length,
+            });
+        }
+    }
+
+    if !damaged && reader.offset() < bytes.len() {
+        findings.push(Finding::TrailingData {
+            offset: reader.offset(),
+            length: bytes.len() - reader.offset(),
+        });
+    }
+
+    // The damage already explains a missing end, or a missing start.
+    match structure::validate(chunks.iter().map(Chunk::chunk_type)) {
+        Err(Error::InvalidStructure {
+            violation: Violation::MissingData | Violation::MissingEnd,
+            ..
+        })
+        | Err(Error::MissingImageHeader)
+            if damaged => {}
+        Err(Error::InvalidStructure {
+            index,
+            chunk_type,
+            violation,
+        }) => findings.push(Finding::Ordering {
+            index,
+            chunk_type,
+            violation,
+        }),
+        Err(e) => return Err(e),
+        Ok(()) => {}
+    }
+
+    let png: Png = Png::from_chunks(chunks);
+    let lsb: Option<LsbStatistics> = match analyze_pixels(&png) {
+        Ok(statistics) => {
+            if statistics.chi_square > 0.5 {
+                findings.push(Finding::LsbChiSquare {
+                    probability: statistics.chi_square,
+                    fraction: statistics.chi_square_fraction,
+                });
+            }
+            if statistics.rs_rate > 0.1 {
+                findings.push(Finding::LsbRsAnalysis {
+                    rate: statistics.rs_rate,
+                });
+            }
+            Some(statistics)
+        }
+        Err(e) => {
+            findings.push(Finding::PixelsSkipped {
+                reason: e.to_string(),
+            });
+            None
+        }
+    };
+
+    findings.sort_by_key(|finding| std::cmp::Reverse(finding.severity()));
+    Ok(Report { findings, lsb })
+}
+
+/// Runs the chi-square attack and RS analysis over the color samples.
+pub fn analyze_pixels(png: &Png) -> Result<LsbStatistics> {
+    let header: ImageHeader = png.header_info()?;
+    if header.color_type() == ColorType::Indexed || header.bit_depth() < 8 {
+        return Err(Error::UnsupportedImage {
+            reason: "pixel analysis needs 8- or 16-bit direct color",
+        });
+    }
+    let image: RawImage = RawImage::decode(png)?;
+    let planes: Vec<Vec<u8>> = color_planes(&image);
+
+    // Sequential embedding only touches the start of the image, so the
+    // test also runs over growing prefixes of the samples in fill order.
+    let interleaved: Vec<u8> = (0..planes[0].len())
+        .flat_map(|i| planes.iter().map(move |plane| plane[i]))
+        .collect();
+    let (chi_square, chi_square_fraction) = [0.125, 0.25, 0.5, 1.0]
+        .into_iter()
+        .filter_map(|fraction| {
+            let end: usize = (interleaved.len() as f64 * fraction) as usize;
+            (end >= MIN_CHI_SQUARE_SAMPLES).then(|| {
+                (chi_square_probability(&interleaved[..end]), fraction)
+            })
+        })
+        .fold(
+            (0.0, 1.0),
+            |best, current| {
+                if current.0 > best.0 {
+                    current
+                } else {
+                    best
+                }
+            },
+        );
+
+    let width: usize = header.width() as usize;
+    let rs_rate: f64 = rs_rate(&planes, width);
+
+    Ok(LsbStatistics {
+        chi_square,
+        chi_square_fraction,
+        rs_rate,
+    })
+}
+
+/// The low bytes of each color channel, one plane per channel in row-major
+/// order.
+fn color_planes(image: &RawImage) -> Vec<Vec<u8>> {
+    let color_type: ColorType = image.header().color_type();
+    let channels: usize = color_type.channels() as usize;
+    let colors: usize = channels - color_type.has_alpha() as usize;
+    let sample_bytes: usize = image.header().bit_depth() as usize / 8;
+    let pixel_bytes: usize = channels * sample_bytes;
+
+    (0..colors)
+        .map(|channel| {
+            image
+                .pixels()
+                .chunks_exact(pixel_bytes)
+                .map(|pixel| pixel[(channel + 1) * sample_bytes - 1])
+                .collect()
+        })
+        .collect()
+}
+
+/// Westfeld and Pfitzmann's chi-square attack: the probability that the
+/// counts of each value pair 2k, 2k+1 are as even as LSB embedding makes
+/// them.
+fn chi_square_probability(samples: &[u8]) -> f64 {
+    let mut histogram: [u64; 256] = [0; 256];
+    for &sample in samples {
+        histogram[sample as usize] += 1;
+    }
+
+    let mut statistic: f64 = 0.0;
+    let mut pairs: u32 = 0;
+    for pair in histogram.chunks_exact(2) {
+        if pair[0] + pair[1] < MIN_PAIR_COUNT {
+            continue;
+        }
+        let expected: f64 = (pair[0] + pair[1]) as f64 / 2.0;
+        statistic += (pair[0] as f64 - expected).powi(2) / expected;
+        pairs += 1;
+    }
+    if pairs < 2 {
+        return 0.0;
+    }
+
+    1.0 - gamma_p((pairs - 1) as f64 / 2.0, statistic / 2.0)
+}
+
+/// Fridrich's RS analysis: estimates the share of samples whose low bit
+/// was replaced, from how flipping low bits changes the noise of small
+/// groups of neighbouring samples.
+fn rs_rate(planes: &[Vec<u8>], width: usize) -> f64 {
+    let counts = |flip: bool| -> [f64; 4] {
+        let mut counts: [u64; 4] = [0; 4];
+        let mut groups: u64 = 0;
+        for plane in planes {
+            for row in plane.chunks_exact(width) {
+                for group in row.chunks_exact(RS_GROUP) {
+                    let values: Vec<i32> = group
+                        .iter()
+                        .map(|&v| if flip { v ^ 1 } else { v } as i32)
+                        .collect();
+                    let [r, s, nr, ns] = classify(&values);
+                    counts[0] += r as u64;
+                    counts[1] += s as u64;
+                    counts[2] += nr as u64;
+                    counts[3] += ns as u64;
+                    groups += 1;
+                }
+            }
+        }
+        counts.map(|count| count as f64 / groups.max(1) as f64)
+    };
+
+    let [r, s, nr, ns] = counts(false);
+    let [r1, s1, nr1, ns1] = counts(true);
+    let d0: f64 = r - s;
+    let d1: f64 = r1 - s1;
+    let nd0: f64 = nr - ns;
+    let nd1: f64 = nr1 - ns1;
+
+    // 2(d1 + d0)z^2 + (nd0 - nd1 - d1 - 3d0)z + d0 - nd0 = 0
+    let a: f64 = 2.0 * (d1 + d0);
+    let b: f64 = nd0 - nd1 - d1 - 3.0 * d0;
+    let c: f64 = d0 - nd0;
+    let z: f64 = if a.abs() < 1e-12 {
+        if b.abs() < 1e-12 {
+            return 0.0;
+        }
+        -c / b
+    } else {
+        let discriminant: f64 = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            // Near full embedding the two curves meet and noise can push
+            // the roots off the real line. Regular groups no longer
+            // outnumbering singular ones gives it away.
+            return if d0 <= 0.0 { 1.0 } else { 0.0 };
+        }
+        let root: f64 = discriminant.sqrt();
+        let (z1, z2) = ((-b + root) / (2.0 * a), (-b - root) / (2.0 * a));
+        if z1.abs() < z2.abs() {
+            z1
+        } else {
+            z2
+        }
+    };
+
+    (z / (z - 0.5)).clamp(0.0, 1.0)
+}
+
+/// Whether flipping the low bits of a group with the mask [0, 1, 1, 0]
+/// makes it noisier (regular) or smoother (singular), first with the
+/// positive flip 0<->1, 2<->3, ... and then with the negative flip
+/// -1<->0, 1<->2, ....
+fn classify(values: &[i32]) -> [bool; 4] {
+    let noise = |values: &[i32]| -> i32 {
+        values.windows(2).map(|w| (w[1] - w[0]).abs()).sum()
+    };
+    let flip = |negative: bool| -> Vec<i32> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| match (i % 4, negative) {
+                (1 | 2, false) => v ^ 1,
+                (1 | 2, true) => ((v + 1) ^ 1) - 1,
+                _ => v,
+            })
+            .collect()
+    };
+
+    let original: i32 = noise(values);
+    let positive: i32 = noise(&flip(false));
+    let negative: i32 = noise(&flip(true));
+    [
+        positive > original,
+        positive < original,
+        negative > original,
+        negative < original,
+    ]
+}
+
+/// The regularized lower incomplete gamma function P(a, x), the CDF of the
+/// chi-square distribution with 2a degrees of freedom at 2x.
+fn gamma_p(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let prefix: f64 = (-x + a * x.ln() - ln_gamma(a)).exp();
+
+    if x < a + 1.0 {
+        let mut term: f64 = 1.0 / a;
+        let mut sum: f64 = term;
+        let mut n: f64 = a;
+        for _ in 0..1000 {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+            if term.abs() < sum.abs() * 1e-14 {
+                break;
+            }
+        }
+        (sum * prefix).min(1.0)
+    } else {
+        // Lentz's continued fraction for the upper function Q(a, x).
+        const TINY: f64 = 1e-300;
+        let mut b: f64 = x + 1.0 - a;
+        let mut c: f64 = 1.0 / TINY;
+        let mut d: f64 = 1.0 / b;
+        let mut h: f64 = d;
+        for i in 1..1000 {
+            let an: f64 = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = b + an / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            let delta: f64 = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-14 {
+                break;
+            }
+        }
+        (1.0 - prefix * h).max(0.0)
+    }
+}
+
+/// ln Γ(x) for x > 0, by the Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    let x: f64 = x - 1.0;
+    let series: f64 = COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |sum, (i, c)| {
+            sum + c / (x + i as f64 + 1.0)
+        });
+    let t: f64 = x + 7.5;
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t
+        + series.ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        header::Interlace,
+        image_data::testing_png,
+        lsb::{self, Channels, LsbOptions},
+    };
+    use std::str::FromStr;
+
+    const WIDTH: u32 = 128;
+    const HEIGHT: u32 = 96;
+
+    /// A smooth RGB image with a little deterministic noise and a contrast
+    /// stretch, which leaves gaps in its histogram as editing photographs
+    /// does.
+    fn cover_image() -> Png {
+        let header =
+            ImageHeader::new(WIDTH, HEIGHT, 8, ColorType::Rgb, Interlace::None)
+                .unwrap();
+        let mut seed: u32 = 12345;
+        let mut noise = move || {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) as f64 / 8192.0 - 4.0
+        };
+        let mut pixels: Vec<u8> = Vec::new();
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                for channel in 0..3 {
+                    let wave: f64 = ((x as f64 + 10.0 * channel as f64) / 9.0)
+                        .sin()
+                        * (y as f64 / 13.0).cos();
+                    let value: f64 = (77.0 + 49.0 * wave + noise()).round();
+                    pixels.push((value * 1.4).round().clamp(0.0, 255.0) as u8);
+                }
+            }
+        }
+        testing_png(&header, &pixels)
+    }
+
+    /// Bytes that look random, like an encrypted message.
+    fn random_message(length: usize) -> Vec<u8> {
+        let mut seed: u64 = 0x9E37_79B9_7F4A_7C15;
+        (0..length)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect()
+    }
+
+    fn full_embedding() -> Png {
+        let mut png = cover_image();
+        let options = LsbOptions::new(1, Channels::Color).unwrap();
+        let header = png.header_info().unwrap();
+        let capacity = lsb::capacity(&header, &options).unwrap();
+        lsb::embed(&mut png, &random_message(capacity), &options).unwrap();
+        png
+    }
+
+    fn chunk(name: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(name).unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_clean_image() {
+        let report = scan(&cover_image().as_bytes().unwrap()).unwrap();
+        let lsb = report.lsb_statistics().unwrap();
+
+        assert_eq!(report.findings(), &[]);
+        assert_eq!(report.score(), 0);
+        assert_eq!(report.verdict(), "clean");
+        assert!(lsb.chi_square < 0.5, "{:?}", lsb);
+        assert!(lsb.rs_rate < 0.1, "{:?}", lsb);
+    }
+
+    #[test]
+    fn test_lsb_embedding() {
+        let report = scan(&full_embedding().as_bytes().unwrap()).unwrap();
+        let lsb = report.lsb_statistics().unwrap();
+
+        assert!(lsb.chi_square > 0.95, "{:?}", lsb);
+        assert!(lsb.rs_rate > 0.5, "{:?}", lsb);
+        assert_eq!(report.score(), 80);
+        assert!(report
+            .findings()
+            .iter()
+            .all(|finding| finding.severity() == Severity::High));
+    }
+
+    #[test]
+    fn test_sequential_embedding_in_a_prefix() {
+        let mut png = cover_image();
+        let message = random_message(WIDTH as usize * HEIGHT as usize / 16);
+        lsb::embed(&mut png, &message, &LsbOptions::default()).unwrap();
+        let lsb = analyze_pixels(&png).unwrap();
+
+        assert!(lsb.chi_square > 0.95, "{:?}", lsb);
+        assert_eq!(lsb.chi_square_fraction, 0.125);
+    }
+
+    #[test]
+    fn test_suspicious_chunks() {
+        let mut png = cover_image();
+        png.insert_chunk(chunk("ruSt", b"hidden"));
+        png.insert_chunk(chunk("ZZZZ", b""));
+        png.insert_chunk(chunk("tEXt", &vec![b'a'; OVERSIZED_CHUNK + 1]));
+        png.insert_chunk_at(1, chunk("gAMA", &[0, 0, 177, 143]))
+            .unwrap();
+        png.insert_chunk(chunk("gAMA", &[0, 0, 177, 143]));
+        let mut bytes = png.as_bytes().unwrap();
+        let end = bytes.len();
+        bytes.extend_from_slice(b"trailing secret");
+
+        let report = scan(&bytes).unwrap();
+        let findings = report.findings();
+        assert_eq!(findings.len(), 5, "{}", report);
+        assert_eq!(
+            findings[0],
+            Finding::TrailingData {
+                offset: end,
+                length: 15
+            }
+        );
+        assert!(findings.contains(&Finding::PrivateChunk {
+            index: 3,
+            chunk_type: ChunkType::from_str("ruSt").unwrap(),
+            length: 6
+        }));
+        assert!(findings.iter().any(|finding| matches!(
+            finding,
+            Finding::UnknownChunk { index: 4, .. }
+        )));
+        assert!(findings.iter().any(|finding| matches!(
+            finding,
+            Finding::OversizedChunk { index: 5, .. }
+        )));
+        assert!(findings.iter().any(|finding| matches!(
+            finding,
+            Finding::Ordering {
+                violation: Violation::DuplicateChunk,
+                ..
+            }
+        )));
+        assert_eq!(report.score(), 40 + 20 + 5 + 20 + 5);
+        assert_eq!(report.verdict(), "likely hiding data");
+    }
+
+    #[test]
+    fn test_damaged_files() {
+        let mut png = cover_image();
+        png.insert_chunk_at(1, chunk("ruSt", b"hidden")).unwrap();
+        let bytes = png.as_bytes().unwrap();
+        // The chunk after IHDR starts at offset 33.
+        let crc_at = 33 + Chunk::METADATA_SIZE + 6 - 1;
+
+        let mut bad_crc = bytes.clone();
+        bad_crc[crc_at] ^= 1;
+        let report = scan(&bad_crc).unwrap();
+        assert!(matches!(
+            report.findings()[0],
+            Finding::Damaged { offset: 33, .. }
+        ));
+        assert!(report.findings().iter().all(|finding| matches!(
+            finding,
+            Finding::Damaged { .. } | Finding::PixelsSkipped { .. }
+        )));
+
+        let truncated = &bytes[..bytes.len() - 20];
+        let report = scan(truncated).unwrap();
+        assert!(report
+            .findings()
+            .iter()
+            .any(|finding| matches!(finding, Finding::Damaged { .. })));
+        assert!(report.findings().contains(&Finding::PrivateChunk {
+            index: 1,
+            chunk_type: ChunkType::from_str("ruSt").unwrap(),
+            length: 6
+        }));
+    }
+
+    #[test]
+    fn test_unsupported_pixels() {
+        let header =
+            ImageHeader::new(8, 8, 4, ColorType::Grayscale, Interlace::None)
+                .unwrap();
+        let png = testing_png(&header, &[0; 32]);
+        let report = scan(&png.as_bytes().unwrap()).unwrap();
+
+        assert!(report.lsb_statistics().is_none());
+        assert!(matches!(report.findings(), [Finding::PixelsSkipped { .. }]));
+        assert_eq!(report.score(), 0);
+    }
+
+    #[test]
+    fn test_gamma_p() {
+        // The chi-square CDF with 2 degrees of freedom is 1 - e^(-x/2).
+        for x in [0.5, 2.0, 7.0] {
+            let expected = 1.0 - (-x / 2.0f64).exp();
+            assert!((gamma_p(1.0, x / 2.0) - expected).abs() < 1e-10);
+        }
+        // Median of the chi-square distribution with 10 degrees of freedom.
+        assert!((gamma_p(5.0, 9.341_818 / 2.0) - 0.5).abs() < 1e-5);
+        assert!((ln_gamma(5.0) - 24f64.ln()).abs() < 1e-10);
+    }
+}
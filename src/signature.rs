@@ -0,0 +1,441 @@
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    keys::{SigningKey, VerifyingKey},
+    png::Png,
+    Error, Result,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+};
+
+const VERSION: u8 = 1;
+const DIGEST_SIZE: usize = 32;
+const SIGNATURE_SIZE: usize = 64;
+const FLAG_IMAGE: u8 = 1;
+const CONTEXT: &[u8] = b"pngme signature v1";
+
+/// A digest of everything a [`Signature`] can cover about an image: the
+/// concatenated data of its IDAT chunks.
+pub type ImageDigest = [u8; DIGEST_SIZE];
+
+/// An Ed25519 signature over a payload chunk, stored in a companion chunk.
+///
+/// The signed message is the chunk type and a SHA-256 digest of the chunk
+/// data, plus optionally an [`ImageDigest`] so the signature also breaks if
+/// the picture itself is swapped out. The payload digest is stored too, so
+/// each signature can be matched to the chunk it covers wherever the two
+/// end up in the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    chunk_type: ChunkType,
+    payload_digest: [u8; DIGEST_SIZE],
+    image_digest: Option<ImageDigest>,
+    signature: ed25519_dalek::Signature,
+}
+
+impl Signature {
+    /// The type of the companion chunk: ancillary, private and not safe to
+    /// copy, since it may be tied to the image data.
+    pub const CHUNK_TYPE: [u8; 4] = *b"siGN";
+
+    pub fn sign(
+        key: &SigningKey,
+        chunk: &Chunk,
+        image_digest: Option<ImageDigest>,
+    ) -> Signature {
+        let mut unsigned: Signature = Signature {
+            chunk_type: *chunk.chunk_type(),
+            payload_digest: Sha256::digest(chunk.data()).into(),
+            image_digest,
+            signature: ed25519_dalek::Signature::from_bytes(
+                &[0; SIGNATURE_SIZE],
+            ),
+        };
+        unsigned.signature = key.sign(&unsigned.message());
+        unsigned
+    }
+
+    /// The type of the chunk this signature covers.
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+
+    /// Returns `true` if the signature also covers the image data.
+    pub fn covers_image(&self) -> bool {
+        self.image_digest.is_some()
+    }
+
+    /// Returns `true` if this signature claims to cover `chunk`. Whether
+    /// the claim holds is up to [`Signature::verify`].
+    pub fn matches(&self, chunk: &Chunk) -> bool {
+        self.chunk_type == *chunk.chunk_type()
+            && self.payload_digest
+                == <[u8; DIGEST_SIZE]>::from(Sha256::digest(chunk.data()))
+    }
+
+    /// Checks the signature against `key`, and against `image_digest` if
+    /// it covers the image data.
+    pub fn verify(
+        &self,
+        key: &VerifyingKey,
+        image_digest: &ImageDigest,
+    ) -> Result<()> {
+        if self
+            .image_digest
+            .is_some_and(|digest| digest != *image_digest)
+        {
+            return Err(Error::SignatureMismatch);
+        }
+
+        if !key.verify(&self.message(), &self.signature) {
+            return Err(Error::SignatureMismatch);
+        }
+
+        Ok(())
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data: Vec<u8> = self.message()[CONTEXT.len()..].to_vec();
+        data.extend_from_slice(&self.signature.to_bytes());
+
+        Chunk::new(ChunkType::try_from(Signature::CHUNK_TYPE).unwrap(), data)
+    }
+
+    /// The bytes that get signed: a fixed context string followed by the
+    /// chunk data up to the signature itself.
+    fn message(&self) -> Vec<u8> {
+        let flags: u8 = if self.image_digest.is_some() {
+            FLAG_IMAGE
+        } else {
+            0
+        };
+
+        let mut message: Vec<u8> = CONTEXT.to_vec();
+        message.push(VERSION);
+        message.push(flags);
+        message.extend_from_slice(&self.chunk_type.bytes());
+        message.extend_from_slice(&self.payload_digest);
+        if let Some(digest) = &self.image_digest {
+            message.extend_from_slice(digest);
+        }
+        message
+    }
+}
+
+impl TryFrom<&[u8]> for Signature {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        let (version, flags): (u8, u8) = match value {
+            [version, flags, ..] => (*version, *flags),
+            _ => {
+                return Err(Error::InvalidSignatureChunk {
+                    reason: "chunk is truncated",
+                })
+            }
+        };
+
+        if version != VERSION {
+            return Err(Error::InvalidSignatureChunk {
+                reason: "unsupported signature version",
+            });
+        }
+
+        if flags & !FLAG_IMAGE != 0 {
+            return Err(Error::InvalidSignatureChunk {
+                reason: "unknown flags",
+            });
+        }
+
+        let digests: usize = if flags & FLAG_IMAGE != 0 { 2 } else { 1 };
+        let length: usize = 2 + 4 + digests * DIGEST_SIZE + SIGNATURE_SIZE;
+
+        if value.len() != length {
+            return Err(Error::InvalidSignatureChunk {
+                reason: "chunk has the wrong length",
+            });
+        }
+
+        let chunk_type: ChunkType =
+            ChunkType::try_from(<[u8; 4]>::try_from(&value[2..6]).unwrap())?;
+        let payload_digest: [u8; DIGEST_SIZE] =
+            value[6..6 + DIGEST_SIZE].try_into().unwrap();
+        let image_digest: Option<ImageDigest> = if digests == 2 {
+            Some(
+                value[6 + DIGEST_SIZE..6 + 2 * DIGEST_SIZE]
+                    .try_into()
+                    .unwrap(),
+            )
+        } else {
+            None
+        };
+        let signature: ed25519_dalek::Signature =
+            ed25519_dalek::Signature::from_bytes(
+                value[length - SIGNATURE_SIZE..].try_into().unwrap(),
+            );
+
+        Ok(Signature {
+            chunk_type,
+            payload_digest,
+            image_digest,
+            signature,
+        })
+    }
+}
+
+/// Incrementally digests IDAT data, so a streaming reader can feed it in
+/// pieces.
+#[derive(Debug, Clone, Default)]
+pub struct ImageHasher(Sha256);
+
+impl ImageHasher {
+    pub fn new() -> ImageHasher {
+        ImageHasher::default()
+    }
+    pub fn update(&mut self, idat_data: &[u8]) {
+        self.0.update(idat_data);
+    }
+    pub fn finish(self) -> ImageDigest {
+        self.0.finalize().into()
+    }
+}
+
+/// Digests the data of every IDAT chunk in `png`, in order.
+pub fn image_digest(png: &Png) -> ImageDigest {
+    let mut hasher: ImageHasher = ImageHasher::new();
+    for chunk in png.chunk_by_type("IDAT") {
+        hasher.update(chunk.data());
+    }
+    hasher.finish()
+}
+
+/// What [`verify`] found for one payload chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureStatus {
+    /// A signature by the key covers the chunk (and the image data, if
+    /// `image` is set).
+    Valid { image: bool },
+    /// A signature claims to cover the chunk but does not check out.
+    Invalid,
+    /// No signature claims to cover the chunk.
+    Missing,
+}
+
+impl Display for SignatureStatus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            SignatureStatus::Valid { image: true } => {
+                write!(f, "valid (image data included)")
+            }
+            SignatureStatus::Valid { image: false } => write!(f, "valid"),
+            SignatureStatus::Invalid => write!(f, "invalid"),
+            SignatureStatus::Missing => write!(f, "missing"),
+        }
+    }
+}
+
+/// Checks the signature of every chunk of type `chunk_type` in `png`,
+/// returning one status per chunk in file order.
+///
+/// A chunk nobody signed is [`SignatureStatus::Missing`], unless it is
+/// followed by a signature for its type that matches no chunk at all:
+/// encode writes each signature right after its chunk, so that is what an
+/// edited payload looks like, and it counts as [`SignatureStatus::Invalid`].
+/// Malformed signature chunks are ignored.
+pub fn verify(
+    png: &Png,
+    chunk_type: &str,
+    key: &VerifyingKey,
+) -> Vec<SignatureStatus> {
+    let payloads: Vec<&Chunk> = png.chunk_by_type(chunk_type);
+    let mut signatures: Vec<Signature> = Vec::new();
+    let mut tampered: Vec<bool> = vec![false; payloads.len()];
+    let mut last_payload: Option<usize> = None;
+    for chunk in png.chunks() {
+        if chunk.chunk_type().to_string() == chunk_type {
+            last_payload = Some(last_payload.map_or(0, |i| i + 1));
+            continue;
+        }
+        if chunk.chunk_type().bytes() != Signature::CHUNK_TYPE {
+            continue;
+        }
+        let signature: Signature = match Signature::try_from(chunk.data()) {
+            Ok(signature) if signature.chunk_type.to_string() == chunk_type => {
+                signature
+            }
+            _ => continue,
+        };
+        let orphaned: bool = !payloads.iter().any(|p| signature.matches(p));
+        if let (true, Some(index)) = (orphaned, last_payload) {
+            tampered[index] = true;
+        }
+        signatures.push(signature);
+    }
+    let digest: ImageDigest = image_digest(png);
+
+    payloads
+        .into_iter()
+        .zip(tampered)
+        .map(|(chunk, tampered)| {
+            let claims: Vec<&Signature> =
+                signatures.iter().filter(|s| s.matches(chunk)).collect();
+            let valid: Option<&&Signature> =
+                claims.iter().find(|s| s.verify(key, &digest).is_ok());
+            match valid {
+                Some(signature) => SignatureStatus::Valid {
+                    image: signature.covers_image(),
+                },
+                None if !claims.is_empty() || tampered => {
+                    SignatureStatus::Invalid
+                }
+                None => SignatureStatus::Missing,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        let chunk = |name: &str, data: &[u8]| {
+            Chunk::new(ChunkType::from_str(name).unwrap(), data.to_vec())
+        };
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk("IDAT", b"pixels"),
+            chunk("ruSt", b"first message"),
+            chunk("ruSt", b"second message"),
+            chunk("IEND", b""),
+        ])
+    }
+
+    fn sign_at(png: &mut Png, index: usize, key: &SigningKey, image: bool) {
+        let digest: Option<ImageDigest> = image.then(|| image_digest(png));
+        let signature = Signature::sign(key, &png.chunks()[index], digest);
+        png.insert_chunk_at(index + 1, signature.to_chunk())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_signature_chunk_round_trip() {
+        let key = SigningKey::generate();
+        let png = testing_png();
+        let signature =
+            Signature::sign(&key, &png.chunks()[2], Some(image_digest(&png)));
+
+        let chunk = signature.to_chunk();
+        let parsed = Signature::try_from(chunk.data()).unwrap();
+
+        assert_eq!(chunk.chunk_type().to_string(), "siGN");
+        assert_eq!(parsed, signature);
+        assert!(parsed.matches(&png.chunks()[2]));
+        assert!(!parsed.matches(&png.chunks()[3]));
+    }
+
+    #[test]
+    fn test_verify_statuses() {
+        let key = SigningKey::generate();
+        let mut png = testing_png();
+        sign_at(&mut png, 2, &key, true);
+
+        let statuses = verify(&png, "ruSt", &key.verifying_key());
+        assert_eq!(
+            statuses,
+            vec![
+                SignatureStatus::Valid { image: true },
+                SignatureStatus::Missing
+            ]
+        );
+
+        let other = SigningKey::generate();
+        let statuses = verify(&png, "ruSt", &other.verifying_key());
+        assert_eq!(statuses[0], SignatureStatus::Invalid);
+    }
+
+    #[test]
+    fn test_changed_image_breaks_signature() {
+        let key = SigningKey::generate();
+        let mut png = testing_png();
+        sign_at(&mut png, 3, &key, false);
+        sign_at(&mut png, 2, &key, true);
+
+        let idat = png.remove_at(1).unwrap();
+        png.insert_chunk_at(
+            1,
+            Chunk::new(*idat.chunk_type(), b"other pixels".to_vec()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            verify(&png, "ruSt", &key.verifying_key()),
+            vec![
+                SignatureStatus::Invalid,
+                SignatureStatus::Valid { image: false }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tampered_payload() {
+        let key = SigningKey::generate();
+        let mut png = testing_png();
+        sign_at(&mut png, 2, &key, false);
+
+        let payload = png.remove_at(2).unwrap();
+        png.insert_chunk_at(
+            2,
+            Chunk::new(*payload.chunk_type(), b"forged message".to_vec()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            verify(&png, "ruSt", &key.verifying_key()),
+            vec![SignatureStatus::Invalid, SignatureStatus::Missing]
+        );
+
+        // Only the chunk the orphaned signature follows is suspect.
+        let mut png = testing_png();
+        sign_at(&mut png, 3, &key, false);
+        let payload = png.remove_at(3).unwrap();
+        png.insert_chunk_at(
+            3,
+            Chunk::new(*payload.chunk_type(), b"forged message".to_vec()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            verify(&png, "ruSt", &key.verifying_key()),
+            vec![SignatureStatus::Missing, SignatureStatus::Invalid]
+        );
+    }
+
+    #[test]
+    fn test_malformed_signature_chunks() {
+        let key = SigningKey::generate();
+        let png = testing_png();
+        let data = Signature::sign(&key, &png.chunks()[2], None)
+            .to_chunk()
+            .data()
+            .to_vec();
+
+        assert!(Signature::try_from(&data[..data.len() - 1]).is_err());
+        assert!(Signature::try_from(&data[..1]).is_err());
+
+        let mut flagged = data.clone();
+        flagged[1] = FLAG_IMAGE;
+        assert!(Signature::try_from(flagged.as_slice()).is_err());
+
+        let mut future = data;
+        future[0] = VERSION + 1;
+        assert!(matches!(
+            Signature::try_from(future.as_slice()),
+            Err(Error::InvalidSignatureChunk { .. })
+        ));
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{chunk_type::ChunkType, Error, Result};
+use std::fmt::{self, Display, Formatter};
+
+/// The ordering rule a chunk sequence broke.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Violation {
+    /// The first chunk is not IHDR, or IHDR shows up again later.
+    HeaderNotFirst,
+    /// A chunk follows IEND.
+    ChunkAfterEnd,
+    /// A chunk that may appear only once appears again.
+    DuplicateChunk,
+    /// PLTE comes after the first IDAT.
+    PaletteAfterData,
+    /// IDAT chunks are separated by other chunks.
+    DataNotContiguous,
+    /// A chunk that must come before PLTE comes after it.
+    MustPrecedePalette,
+    /// A chunk that must come before the first IDAT comes after it.
+    MustPrecedeData,
+    /// The image has no IDAT chunk.
+    MissingData,
+    /// The image does not end with IEND.
+    MissingEnd,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let text: &str = match self {
+            Violation::HeaderNotFirst => "IHDR must be the first chunk",
+            Violation::ChunkAfterEnd => "no chunk may follow IEND",
+            Violation::DuplicateChunk => "chunk may appear only once",
+            Violation::PaletteAfterData => "PLTE must come before IDAT",
+            Violation::DataNotContiguous => "IDAT chunks must be consecutive",
+            Violation::MustPrecedePalette => "chunk must come before PLTE",
+            Violation::MustPrecedeData => "chunk must come before IDAT",
+            Violation::MissingData => "at least one IDAT is required",
+            Violation::MissingEnd => "IEND must be the last chunk",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+const IHDR: [u8; 4] = *b"IHDR";
+const PLTE: [u8; 4] = *b"PLTE";
+const IDAT: [u8; 4] = *b"IDAT";
+const IEND: [u8; 4] = *b"IEND";
+
+const SINGLETONS: [[u8; 4]; 15] = [
+    IHDR, PLTE, IEND, *b"cHRM", *b"cICP", *b"gAMA", *b"iCCP", *b"sBIT",
+    *b"sRGB", *b"bKGD", *b"hIST", *b"tRNS", *b"pHYs", *b"tIME", *b"eXIf",
+];
+const BEFORE_PALETTE: [[u8; 4]; 6] =
+    [*b"cHRM", *b"cICP", *b"gAMA", *b"iCCP", *b"sBIT", *b"sRGB"];
+const BEFORE_DATA: [[u8; 4]; 6] =
+    [*b"bKGD", *b"hIST", *b"tRNS", *b"pHYs", *b"sPLT", *b"eXIf"];
+
+/// Checks chunk ordering one chunk at a time, so it can sit behind a
+/// streaming reader or writer as well as run over a whole [`crate::Png`].
+#[derive(Debug, Default, Clone)]
+pub struct StructureValidator {
+    index: usize,
+    seen: Vec<[u8; 4]>,
+    in_data: bool,
+    data_done: bool,
+    ended: bool,
+}
+
+impl StructureValidator {
+    pub fn new() -> StructureValidator {
+        StructureValidator::default()
+    }
+
+    /// Records the next chunk, failing if it cannot legally go here.
+    pub fn push(&mut self, chunk_type: &ChunkType) -> Result<()> {
+        let bytes: [u8; 4] = chunk_type.bytes();
+        let violation: Option<Violation> = self.check(bytes);
+
+        if let Some(violation) = violation {
+            return Err(Error::InvalidStructure {
+                index: self.index,
+                chunk_type: Some(*chunk_type),
+                violation,
+            });
+        }
+
+        if bytes == IDAT {
+            self.in_data = true;
+        } else if self.in_data {
+            self.in_data = false;
+            self.data_done = true;
+        }
+        self.ended |= bytes == IEND;
+        if !self.seen.contains(&bytes) {
+            self.seen.push(bytes);
+        }
+        self.index += 1;
+
+        Ok(())
+    }
+
+    /// Fails if the chunks pushed so far do not form a complete image.
+    pub fn finish(&self) -> Result<()> {
+        if self.index == 0 {
+            return Err(Error::MissingImageHeader);
+        }
+
+        let violation: Violation = if !self.seen.contains(&IDAT) {
+            Violation::MissingData
+        } else if !self.ended {
+            Violation::MissingEnd
+        } else {
+            return Ok(());
+        };
+
+        Err(Error::InvalidStructure {
+            index: self.index,
+            chunk_type: None,
+            violation,
+        })
+    }
+
+    fn check(&self, bytes: [u8; 4]) -> Option<Violation> {
+        let seen_palette: bool = self.seen.contains(&PLTE);
+        let seen_data: bool = self.in_data || self.data_done;
+
+        if self.ended {
+            Some(Violation::ChunkAfterEnd)
+        } else if (self.index == 0) != (bytes == IHDR) {
+            Some(Violation::HeaderNotFirst)
+        } else if SINGLETONS.contains(&bytes) && self.seen.contains(&bytes) {
+            Some(Violation::DuplicateChunk)
+        } else if bytes == PLTE && seen_data {
+            Some(Violation::PaletteAfterData)
+        } else if bytes == IDAT && self.data_done {
+            Some(Violation::DataNotContiguous)
+        } else if BEFORE_PALETTE.contains(&bytes) && seen_palette {
+            Some(Violation::MustPrecedePalette)
+        } else if (BEFORE_PALETTE.contains(&bytes)
+            || BEFORE_DATA.contains(&bytes))
+            && seen_data
+        {
+            Some(Violation::MustPrecedeData)
+        } else if bytes == IEND && !seen_data {
+            Some(Violation::MissingData)
+        } else {
+            None
+        }
+    }
+}
+
+/// Checks that `chunk_types` form a structurally valid image.
+pub fn validate<'a>(
+    chunk_types: impl IntoIterator<Item = &'a ChunkType>,
+) -> Result<()> {
+    let mut validator: StructureValidator = StructureValidator::new();
+    for chunk_type in chunk_types {
+        validator.push(chunk_type)?;
+    }
+    validator.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn types(names: &[&str]) -> Vec<ChunkType> {
+        names
+            .iter()
+            .map(|name| ChunkType::from_str(name).unwrap())
+            .collect()
+    }
+
+    fn violation(names: &[&str]) -> Option<Violation> {
+        match validate(&types(names)) {
+            Err(Error::InvalidStructure { violation, .. }) => Some(violation),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_valid_structure() {
+        let chunks = types(&[
+            "IHDR", "gAMA", "PLTE", "tRNS", "IDAT", "IDAT", "tEXt", "IEND",
+        ]);
+        assert!(validate(&chunks).is_ok());
+    }
+
+    #[test]
+    fn test_header_not_first() {
+        assert_eq!(
+            violation(&["gAMA", "IHDR", "IDAT", "IEND"]),
+            Some(Violation::HeaderNotFirst)
+        );
+        assert_eq!(
+            violation(&["IHDR", "IHDR", "IDAT", "IEND"]),
+            Some(Violation::HeaderNotFirst)
+        );
+    }
+
+    #[test]
+    fn test_chunk_after_end() {
+        let result = validate(&types(&["IHDR", "IDAT", "IEND", "ruSt"]));
+        assert!(matches!(
+            result,
+            Err(Error::InvalidStructure {
+                index: 3,
+                violation: Violation::ChunkAfterEnd,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_ordering_rules() {
+        assert_eq!(
+            violation(&["IHDR", "IDAT", "PLTE", "IEND"]),
+            Some(Violation::PaletteAfterData)
+        );
+        assert_eq!(
+            violation(&["IHDR", "IDAT", "tEXt", "IDAT", "IEND"]),
+            Some(Violation::DataNotContiguous)
+        );
+        assert_eq!(
+            violation(&["IHDR", "PLTE", "gAMA", "IDAT", "IEND"]),
+            Some(Violation::MustPrecedePalette)
+        );
+        assert_eq!(
+            violation(&["IHDR", "IDAT", "pHYs", "IEND"]),
+            Some(Violation::MustPrecedeData)
+        );
+        assert_eq!(
+            violation(&["IHDR", "gAMA", "gAMA", "IDAT", "IEND"]),
+            Some(Violation::DuplicateChunk)
+        );
+    }
+
+    #[test]
+    fn test_incomplete_image() {
+        assert_eq!(violation(&["IHDR", "IDAT"]), Some(Violation::MissingEnd));
+        assert_eq!(violation(&["IHDR", "IEND"]), Some(Violation::MissingData));
+        assert!(matches!(validate(&[]), Err(Error::MissingImageHeader)));
+    }
+}
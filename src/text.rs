@@ -0,0 +1,491 @@
+use crate::{chunk::Chunk, chunk_type::ChunkType, zlib, Error, Result};
+use std::{
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+};
+
+/// The types of the three standard text chunks.
+pub const TEXT_CHUNK_TYPES: [[u8; 4]; 3] = [*b"tEXt", *b"zTXt", *b"iTXt"];
+
+/// Compressed text inflating to more than this is refused.
+pub const MAX_TEXT_SIZE: usize = 16 * 1024 * 1024;
+
+const MAX_KEYWORD_LENGTH: usize = 79;
+// Marks the text of a chunk written by `TextChunk::hide`, so that a comment
+// that only happens to be hex is not taken for a message.
+const HIDDEN_PREFIX: &str = "pngme:";
+// Compression method 0, zlib, is the only one the spec defines.
+const ZLIB: u8 = 0;
+
+/// A keyword and its text, as stored in one of the standard text chunks.
+///
+/// tEXt and zTXt hold Latin-1 text, zTXt compressed. iTXt holds UTF-8 text,
+/// compressed or not, along with a language tag and the keyword translated
+/// into that language.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextChunk {
+    Text {
+        keyword: String,
+        text: String,
+    },
+    Compressed {
+        keyword: String,
+        text: String,
+    },
+    International {
+        keyword: String,
+        compressed: bool,
+        language: String,
+        translated_keyword: String,
+        text: String,
+    },
+}
+
+impl TextChunk {
+    /// The plainest chunk that can hold `text`: tEXt, or zTXt if
+    /// `compressed`, and iTXt if the text is not Latin-1.
+    pub fn new(keyword: &str, text: &str, compressed: bool) -> TextChunk {
+        let (keyword, text) = (keyword.to_string(), text.to_string());
+        match (is_latin1(&text), compressed) {
+            (true, false) => TextChunk::Text { keyword, text },
+            (true, true) => TextChunk::Compressed { keyword, text },
+            (false, compressed) => TextChunk::International {
+                keyword,
+                compressed,
+                language: String::new(),
+                translated_keyword: String::new(),
+                text,
+            },
+        }
+    }
+
+    /// Hides `data` in a compressed iTXt chunk, as hex so that the chunk
+    /// holds valid text like any other, behind a marker that tells it
+    /// apart from a real comment.
+    pub fn hide(keyword: &str, data: &[u8]) -> TextChunk {
+        TextChunk::International {
+            keyword: keyword.to_string(),
+            compressed: true,
+            language: String::new(),
+            translated_keyword: String::new(),
+            text: format!("{}{}", HIDDEN_PREFIX, hex::encode(data)),
+        }
+    }
+
+    /// The bytes [`TextChunk::hide`] stored, or `None` if the chunk holds
+    /// ordinary text.
+    pub fn reveal(&self) -> Option<Vec<u8>> {
+        match self {
+            TextChunk::International { text, .. } => {
+                hex::decode(text.strip_prefix(HIDDEN_PREFIX)?).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether chunks of this type hold text.
+    pub fn is_text_chunk(chunk_type: &ChunkType) -> bool {
+        TEXT_CHUNK_TYPES.contains(&chunk_type.bytes())
+    }
+
+    /// Whether `chunk` is a text chunk whose keyword is `keyword`. Only the
+    /// keyword is looked at, so a chunk with broken text still matches.
+    pub fn has_keyword(chunk: &Chunk, keyword: &str) -> bool {
+        TextChunk::is_text_chunk(chunk.chunk_type())
+            && chunk.data().contains(&0)
+            && chunk
+                .data()
+                .iter()
+                .take_while(|&&b| b != 0)
+                .map(|&b| b as char)
+                .eq(keyword.chars())
+    }
+
+    pub fn keyword(&self) -> &str {
+        match self {
+            TextChunk::Text { keyword, .. }
+            | TextChunk::Compressed { keyword, .. }
+            | TextChunk::International { keyword, .. } => keyword,
+        }
+    }
+    pub fn text(&self) -> &str {
+        match self {
+            TextChunk::Text { text, .. }
+            | TextChunk::Compressed { text, .. }
+            | TextChunk::International { text, .. } => text,
+        }
+    }
+    pub fn chunk_type(&self) -> ChunkType {
+        let bytes: [u8; 4] = match self {
+            TextChunk::Text { .. } => TEXT_CHUNK_TYPES[0],
+            TextChunk::Compressed { .. } => TEXT_CHUNK_TYPES[1],
+            TextChunk::International { .. } => TEXT_CHUNK_TYPES[2],
+        };
+        ChunkType::try_from(bytes).unwrap()
+    }
+
+    /// Encodes the chunk, checking the keyword against the spec's rules and
+    /// that tEXt and zTXt text is Latin-1.
+    pub fn to_chunk(&self) -> Result<Chunk> {
+        let mut data: Vec<u8> = encode_keyword(self.keyword())?;
+        data.push(0);
+        match self {
+            TextChunk::Text { text, .. } => data.extend(encode_latin1(text)?),
+            TextChunk::Compressed { text, .. } => {
+                data.push(ZLIB);
+                data.extend(zlib::deflate(&encode_latin1(text)?));
+            }
+            TextChunk::International {
+                compressed,
+                language,
+                translated_keyword,
+                text,
+                ..
+            } => {
+                if !language
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+                {
+                    return Err(Error::InvalidTextChunk {
+                        reason: "language tag must be ASCII letters, digits \
+                                 and hyphens",
+                    });
+                }
+                if translated_keyword.contains('\0') {
+                    return Err(Error::InvalidTextChunk {
+                        reason: "translated keyword contains a null byte",
+                    });
+                }
+                data.extend([*compressed as u8, ZLIB]);
+                data.extend(language.bytes());
+                data.push(0);
+                data.extend(translated_keyword.bytes());
+                data.push(0);
+                if *compressed {
+                    data.extend(zlib::deflate(text.as_bytes()));
+                } else {
+                    data.extend(text.bytes());
+                }
+            }
+        }
+        Ok(Chunk::new(self.chunk_type(), data))
+    }
+}
+
+impl TryFrom<&Chunk> for TextChunk {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<TextChunk> {
+        let (keyword, rest) =
+            split_at_null(chunk.data()).ok_or(Error::InvalidTextChunk {
+                reason: "keyword is not null-terminated",
+            })?;
+        let keyword: String = decode_latin1(keyword);
+        check_keyword(&keyword)?;
+
+        match &chunk.chunk_type().bytes() {
+            b"tEXt" => Ok(TextChunk::Text {
+                keyword,
+                text: decode_latin1(rest),
+            }),
+            b"zTXt" => {
+                let (&method, compressed) =
+                    rest.split_first().ok_or(Error::InvalidTextChunk {
+                        reason: "compression method is missing",
+                    })?;
+                check_method(method)?;
+                Ok(TextChunk::Compressed {
+                    keyword,
+                    text: decode_latin1(&zlib::inflate(
+                        compressed,
+                        MAX_TEXT_SIZE,
+                    )?),
+                })
+            }
+            b"iTXt" => {
+                let [flag, method, rest @ ..] = rest else {
+                    return Err(Error::InvalidTextChunk {
+                        reason: "compression fields are missing",
+                    });
+                };
+                let compressed: bool = match flag {
+                    0 => false,
+                    1 => true,
+                    _ => {
+                        return Err(Error::InvalidTextChunk {
+                            reason: "compression flag must be 0 or 1",
+                        })
+                    }
+                };
+                if compressed {
+                    check_method(*method)?;
+                }
+                let (language, rest) =
+                    split_at_null(rest).ok_or(Error::InvalidTextChunk {
+                        reason: "language tag is not null-terminated",
+                    })?;
+                let (translated, text) =
+                    split_at_null(rest).ok_or(Error::InvalidTextChunk {
+                        reason: "translated keyword is not null-terminated",
+                    })?;
+                let text: Vec<u8> = if compressed {
+                    zlib::inflate(text, MAX_TEXT_SIZE)?
+                } else {
+                    text.to_vec()
+                };
+
+                Ok(TextChunk::International {
+                    keyword,
+                    compressed,
+                    language: String::from_utf8(language.to_vec())?,
+                    translated_keyword: String::from_utf8(translated.to_vec())?,
+                    text: String::from_utf8(text)?,
+                })
+            }
+            _ => Err(Error::InvalidTextChunk {
+                reason: "chunk type is not tEXt, zTXt or iTXt",
+            }),
+        }
+    }
+}
+
+impl Display for TextChunk {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            TextChunk::International {
+                language,
+                translated_keyword,
+                ..
+            } if !language.is_empty() || !translated_keyword.is_empty() => {
+                write!(
+                    f,
+                    "{} ({}, {}): {}",
+                    self.keyword(),
+                    language,
+                    translated_keyword,
+                    self.text()
+                )
+            }
+            _ => write!(f, "{}: {}", self.keyword(), self.text()),
+        }
+    }
+}
+
+fn split_at_null(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let end: usize = data.iter().position(|&b| b == 0)?;
+    Some((&data[..end], &data[end + 1..]))
+}
+
+fn check_method(method: u8) -> Result<()> {
+    if method != ZLIB {
+        return Err(Error::InvalidCompressionMethod { value: method });
+    }
+    Ok(())
+}
+
+/// Checks the spec's rules for keywords: 1 to 79 printable Latin-1
+/// characters, with no leading, trailing or consecutive spaces.
+fn check_keyword(keyword: &str) -> Result<()> {
+    let reason: Option<&'static str> =
+        if keyword.is_empty() || keyword.chars().count() > MAX_KEYWORD_LENGTH {
+            Some("keyword must be 1 to 79 characters long")
+        } else if !keyword
+            .chars()
+            .all(|c| matches!(c as u32, 0x20..=0x7e | 0xa1..=0xff))
+        {
+            Some("keyword must be printable Latin-1")
+        } else if keyword.starts_with(' ')
+            || keyword.ends_with(' ')
+            || keyword.contains("  ")
+        {
+            Some("keyword has leading, trailing or consecutive spaces")
+        } else {
+            None
+        };
+
+    match reason {
+        Some(reason) => Err(Error::InvalidTextChunk { reason }),
+        None => Ok(()),
+    }
+}
+
+fn encode_keyword(keyword: &str) -> Result<Vec<u8>> {
+    check_keyword(keyword)?;
+    encode_latin1(keyword)
+}
+
+fn is_latin1(text: &str) -> bool {
+    text.chars().all(|c| (c as u32) <= 0xff && c != '\0')
+}
+
+fn encode_latin1(text: &str) -> Result<Vec<u8>> {
+    if !is_latin1(text) {
+        return Err(Error::InvalidTextChunk {
+            reason: "tEXt and zTXt text must be Latin-1",
+        });
+    }
+    Ok(text.chars().map(|c| c as u8).collect())
+}
+
+fn decode_latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunk(name: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(name).unwrap(), data.to_vec())
+    }
+
+    fn round_trip(text: &TextChunk) -> TextChunk {
+        TextChunk::try_from(&text.to_chunk().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_text() {
+        let text =
+            TextChunk::try_from(&chunk("tEXt", b"Title\0Caf\xe9")).unwrap();
+        assert_eq!(
+            text,
+            TextChunk::Text {
+                keyword: "Title".to_string(),
+                text: "Café".to_string(),
+            }
+        );
+        assert_eq!(text.to_chunk().unwrap().data(), b"Title\0Caf\xe9");
+        assert_eq!(text.to_string(), "Title: Café");
+    }
+
+    #[test]
+    fn test_compressed() {
+        let text = TextChunk::new("Comment", &"pngme ".repeat(100), true);
+        assert_eq!(text.chunk_type().to_string(), "zTXt");
+
+        let chunk = text.to_chunk().unwrap();
+        assert!(chunk.data().len() < 100);
+        assert_eq!(&chunk.data()[..9], b"Comment\0\0");
+        assert_eq!(TextChunk::try_from(&chunk).unwrap(), text);
+    }
+
+    #[test]
+    fn test_international() {
+        for compressed in [false, true] {
+            let text = TextChunk::International {
+                keyword: "Title".to_string(),
+                compressed,
+                language: "ja-JP".to_string(),
+                translated_keyword: "タイトル".to_string(),
+                text: "秘密のメッセージ".to_string(),
+            };
+            assert_eq!(round_trip(&text), text);
+        }
+
+        let text = TextChunk::try_from(&chunk(
+            "iTXt",
+            b"Author\0\0\0en\0Writer\0Ferris",
+        ))
+        .unwrap();
+        assert_eq!(text.to_string(), "Author (en, Writer): Ferris");
+    }
+
+    #[test]
+    fn test_new_picks_the_type() {
+        assert_eq!(
+            TextChunk::new("a", "plain", false).chunk_type().to_string(),
+            "tEXt"
+        );
+        assert_eq!(
+            TextChunk::new("a", "Grüße", true).chunk_type().to_string(),
+            "zTXt"
+        );
+        let text = TextChunk::new("a", "🦀", false);
+        assert_eq!(text.chunk_type().to_string(), "iTXt");
+        assert_eq!(round_trip(&text), text);
+    }
+
+    #[test]
+    fn test_hidden_data() {
+        let data: Vec<u8> = (0..=255).collect();
+        let text = TextChunk::hide("Comment", &data);
+
+        assert_eq!(text.chunk_type().to_string(), "iTXt");
+        assert_eq!(round_trip(&text).reveal(), Some(data));
+        assert_eq!(TextChunk::new("Comment", "hi", false).reveal(), None);
+        assert_eq!(TextChunk::new("Comment", "🦀", false).reveal(), None);
+        // A comment that only looks like hex is not hidden data.
+        let comment = TextChunk::International {
+            keyword: "Comment".to_string(),
+            compressed: true,
+            language: String::new(),
+            translated_keyword: String::new(),
+            text: "cafe".to_string(),
+        };
+        assert_eq!(round_trip(&comment).reveal(), None);
+    }
+
+    #[test]
+    fn test_has_keyword() {
+        let text = chunk("tEXt", b"Title\0Sunset");
+        assert!(TextChunk::has_keyword(&text, "Title"));
+        assert!(!TextChunk::has_keyword(&text, "Titl"));
+        assert!(!TextChunk::has_keyword(&chunk("ruSt", b"Title\0"), "Title"));
+
+        // Broken text after the keyword still matches.
+        let broken = chunk("zTXt", b"Title\0\0not zlib");
+        assert!(TextChunk::try_from(&broken).is_err());
+        assert!(TextChunk::has_keyword(&broken, "Title"));
+    }
+
+    #[test]
+    fn test_invalid_keywords() {
+        for keyword in ["", " Title", "Title ", "Ti  tle", "Tab\t", "🦀"] {
+            assert!(
+                matches!(
+                    TextChunk::new(keyword, "text", false).to_chunk(),
+                    Err(Error::InvalidTextChunk { .. })
+                ),
+                "{:?}",
+                keyword
+            );
+        }
+        let long = "k".repeat(80);
+        assert!(TextChunk::new(&long, "text", false).to_chunk().is_err());
+        assert!(TextChunk::new(&long[1..], "text", false).to_chunk().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_chunks() {
+        let invalid = |name: &str, data: &[u8]| {
+            TextChunk::try_from(&chunk(name, data)).is_err()
+        };
+
+        assert!(invalid("tEXt", b"no separator"));
+        assert!(invalid("tEXt", b"\0no keyword"));
+        assert!(invalid("zTXt", b"Comment\0"));
+        assert!(invalid("zTXt", b"Comment\0\x01\x78\x9c"));
+        assert!(invalid("zTXt", b"Comment\0\0not zlib"));
+        assert!(invalid("iTXt", b"Comment\0\x02\0\0\0text"));
+        assert!(invalid("iTXt", b"Comment\0\0\0en"));
+        assert!(invalid("iTXt", b"Comment\0\0\0\0\0\xff"));
+        assert!(invalid("ruSt", b"Comment\0text"));
+        assert!(matches!(
+            TextChunk::try_from(&chunk("zTXt", b"Comment\0\x07")),
+            Err(Error::InvalidCompressionMethod { value: 7 })
+        ));
+    }
+
+    #[test]
+    fn test_latin1_only_in_text() {
+        let text = TextChunk::Text {
+            keyword: "Title".to_string(),
+            text: "🦀".to_string(),
+        };
+        assert!(matches!(
+            text.to_chunk(),
+            Err(Error::InvalidTextChunk { .. })
+        ));
+    }
+}
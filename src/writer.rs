@@ -0,0 +1,313 @@
+use crate::{
+    chunk::{Chunk, CRC_PNG},
+    chunk_type::ChunkType,
+    png::Png,
+    reader::{ChunkHeader, ChunkReader},
+    structure::StructureValidator,
+    Error, Result,
+};
+use crc::Digest;
+use std::io::{Read, Write};
+
+const COPY_BUFFER_SIZE: usize = 8 * 1024;
+
+struct Open {
+    offset: usize,
+    chunk_type: ChunkType,
+    length: u32,
+    written: u32,
+    digest: Digest<'static, u32>,
+}
+
+/// Writes a PNG stream one chunk at a time.
+///
+/// The signature goes out when the writer is created. Whole chunks can be
+/// written with [`ChunkWriter::write_chunk`], or streamed by declaring the
+/// length with [`ChunkWriter::start_chunk`], feeding the data through
+/// [`ChunkWriter::write_data`] and closing it with
+/// [`ChunkWriter::end_chunk`]. The CRC is computed as the data goes past.
+pub struct ChunkWriter<W: Write> {
+    writer: W,
+    offset: usize,
+    open: Option<Open>,
+    validator: Option<StructureValidator>,
+}
+
+impl<W: Write> ChunkWriter<W> {
+    pub fn new(mut writer: W) -> Result<ChunkWriter<W>> {
+        writer.write_all(&Png::STANDARD_HEADER)?;
+
+        Ok(ChunkWriter {
+            writer,
+            offset: Png::STANDARD_HEADER.len(),
+            open: None,
+            validator: None,
+        })
+    }
+
+    /// Like [`ChunkWriter::new`], but refuses chunks that would break an
+    /// ordering rule, and refuses to finish without IEND.
+    pub fn new_validated(writer: W) -> Result<ChunkWriter<W>> {
+        let mut new: ChunkWriter<W> = ChunkWriter::new(writer)?;
+        new.validator = Some(StructureValidator::new());
+        Ok(new)
+    }
+
+    /// Byte offset of the next byte to be written.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn write_chunk(&mut self, chunk: &Chunk) -> Result<()> {
+        self.start_chunk(*chunk.chunk_type(), chunk.length())?;
+        self.write_data(chunk.data())?;
+        self.end_chunk()
+    }
+
+    /// Writes the length and type of a chunk whose `length` bytes of data
+    /// will follow through [`ChunkWriter::write_data`].
+    pub fn start_chunk(
+        &mut self,
+        chunk_type: ChunkType,
+        length: u32,
+    ) -> Result<()> {
+        self.check_closed()?;
+
+        if length > Chunk::MAX_LENGTH {
+            return Err(Error::ChunkTooLong {
+                offset: self.offset,
+                length,
+            });
+        }
+
+        if let Some(validator) = &mut self.validator {
+            validator.push(&chunk_type)?;
+        }
+
+        let mut digest: Digest<'static, u32> = CRC_PNG.digest();
+        digest.update(&chunk_type.bytes());
+
+        self.writer.write_all(&length.to_be_bytes())?;
+        self.writer.write_all(&chunk_type.bytes())?;
+        self.open = Some(Open {
+            offset: self.offset,
+            chunk_type,
+            length,
+            written: 0,
+            digest,
+        });
+        self.offset += Chunk::LENGTH_SIZE + Chunk::TYPE_SIZE;
+
+        Ok(())
+    }
+
+    pub fn write_data(&mut self, data: &[u8]) -> Result<()> {
+        let open: &mut Open = match &mut self.open {
+            Some(open) => open,
+            None if data.is_empty() => return Ok(()),
+            None => {
+                return Err(Error::NoOpenChunk {
+                    offset: self.offset,
+                })
+            }
+        };
+
+        let actual: usize = open.written as usize + data.len();
+
+        if actual > open.length as usize {
+            return Err(Error::DataLengthMismatch {
+                offset: open.offset,
+                chunk_type: open.chunk_type,
+                declared: open.length,
+                actual,
+            });
+        }
+
+        self.writer.write_all(data)?;
+        open.digest.update(data);
+        open.written = actual as u32;
+        self.offset += data.len();
+
+        Ok(())
+    }
+
+    /// Writes the CRC of the chunk opened by [`ChunkWriter::start_chunk`].
+    pub fn end_chunk(&mut self) -> Result<()> {
+        let open: Open = match self.open.take() {
+            Some(open) => open,
+            None => return Ok(()),
+        };
+
+        if open.written != open.length {
+            return Err(Error::DataLengthMismatch {
+                offset: open.offset,
+                chunk_type: open.chunk_type,
+                declared: open.length,
+                actual: open.written as usize,
+            });
+        }
+
+        self.writer
+            .write_all(&open.digest.finalize().to_be_bytes())?;
+        self.offset += Chunk::CRC_SIZE;
+
+        Ok(())
+    }
+
+    /// Copies the chunk whose header `reader` just returned, streaming its
+    /// data so it is never held in memory as a whole.
+    pub fn copy_chunk<R: Read>(
+        &mut self,
+        reader: &mut ChunkReader<R>,
+        header: &ChunkHeader,
+    ) -> Result<()> {
+        let mut buf: [u8; COPY_BUFFER_SIZE] = [0; COPY_BUFFER_SIZE];
+
+        self.start_chunk(header.chunk_type, header.length)?;
+        loop {
+            let read: usize = reader.read_data(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            self.write_data(&buf[..read])?;
+        }
+        self.end_chunk()
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.check_closed()?;
+        if let Some(validator) = &self.validator {
+            validator.finish()?;
+        }
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+
+    fn check_closed(&self) -> Result<()> {
+        match &self.open {
+            Some(open) => Err(Error::DataLengthMismatch {
+                offset: open.offset,
+                chunk_type: open.chunk_type,
+                declared: open.length,
+                actual: open.written as usize,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{convert::TryFrom, str::FromStr};
+
+    fn testing_chunks() -> Vec<Chunk> {
+        vec![
+            Chunk::new(
+                ChunkType::from_str("FrSt").unwrap(),
+                b"I am the first chunk".to_vec(),
+            ),
+            Chunk::new(
+                ChunkType::from_str("miDl").unwrap(),
+                b"I am another chunk".to_vec(),
+            ),
+            Chunk::new(
+                ChunkType::from_str("LASt").unwrap(),
+                b"I am the last chunk".to_vec(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_write_chunks() {
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        for chunk in testing_chunks() {
+            writer.write_chunk(&chunk).unwrap();
+        }
+        let bytes: Vec<u8> = writer.finish().unwrap();
+
+        let png = Png::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(
+            png.chunks()[1].data_as_string().unwrap(),
+            "I am another chunk"
+        );
+    }
+
+    #[test]
+    fn test_streamed_chunk_matches_whole_chunk() {
+        let chunk: Chunk = testing_chunks().remove(0);
+
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        writer
+            .start_chunk(*chunk.chunk_type(), chunk.length())
+            .unwrap();
+        for piece in chunk.data().chunks(3) {
+            writer.write_data(piece).unwrap();
+        }
+        writer.end_chunk().unwrap();
+        let bytes: Vec<u8> = writer.finish().unwrap();
+
+        assert_eq!(&bytes[8..], chunk.as_bytes().as_slice());
+    }
+
+    #[test]
+    fn test_wrong_data_length() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        writer.start_chunk(chunk_type, 2).unwrap();
+        assert!(writer.write_data(b"abc").is_err());
+        writer.write_data(b"a").unwrap();
+        assert!(matches!(
+            writer.end_chunk(),
+            Err(Error::DataLengthMismatch {
+                declared: 2,
+                actual: 1,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_validated_writer() {
+        let header = ChunkType::from_str("IHDR").unwrap();
+        let end = ChunkType::from_str("IEND").unwrap();
+
+        let mut writer = ChunkWriter::new_validated(Vec::new()).unwrap();
+        writer
+            .write_chunk(&Chunk::new(header, vec![0; 13]))
+            .unwrap();
+        writer
+            .write_chunk(&Chunk::new(end, Vec::new()))
+            .unwrap_err();
+        assert!(matches!(
+            writer.finish(),
+            Err(Error::InvalidStructure { .. })
+        ));
+    }
+
+    #[test]
+    fn test_copy_from_reader() {
+        let mut source = ChunkWriter::new(Vec::new()).unwrap();
+        for chunk in testing_chunks() {
+            source.write_chunk(&chunk).unwrap();
+        }
+        let source: Vec<u8> = source.finish().unwrap();
+
+        let mut reader = ChunkReader::new(source.as_slice()).unwrap();
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        while let Some(header) = reader.next_header().unwrap() {
+            if header.chunk_type.to_string() != "miDl" {
+                writer.copy_chunk(&mut reader, &header).unwrap();
+            }
+        }
+        let bytes: Vec<u8> = writer.finish().unwrap();
+
+        let png = Png::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(png.chunks().len(), 2);
+        assert!(png.chunk_by_type("miDl").is_empty());
+    }
+}
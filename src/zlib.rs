@@ -0,0 +1,212 @@
+use crate::{Error, Result};
+use flate2::{Compress, Decompress, FlushCompress, FlushDecompress, Status};
+
+const HEADER_SIZE: usize = 2;
+const CHECKSUM_SIZE: usize = 4;
+// Deflate is the only compression method zlib defines.
+const DEFLATE: u8 = 8;
+// How much the output buffers grow by at a time.
+const BUFFER_SIZE: usize = 32 * 1024;
+// Largest prime below 2^16, the modulus of both Adler-32 sums.
+const ADLER_MODULUS: u32 = 65521;
+// Bytes that can be summed before the sums have to be reduced to stay
+// within 32 bits.
+const ADLER_BLOCK: usize = 5552;
+
+/// The Adler-32 checksum a zlib stream ends with.
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b): (u32, u32) = (1, 0);
+    for block in data.chunks(ADLER_BLOCK) {
+        for &byte in block {
+            a += byte as u32;
+            b += a;
+        }
+        a %= ADLER_MODULUS;
+        b %= ADLER_MODULUS;
+    }
+    (b << 16) | a
+}
+
+/// Inflates a zlib stream, checking its header and its Adler-32 checksum.
+///
+/// Fails with [`Error::DecompressedTooLarge`] instead of producing more than
+/// `limit` bytes. Anything after the checksum is ignored, as PNG decoders
+/// do.
+pub fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    if data.len() < HEADER_SIZE + CHECKSUM_SIZE {
+        return Err(Error::InvalidZlibStream {
+            reason: "stream is truncated",
+        });
+    }
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0f != DEFLATE || cmf >> 4 > 7 {
+        return Err(Error::InvalidZlibStream {
+            reason: "compression method is not deflate",
+        });
+    }
+    if !u16::from_be_bytes([cmf, flg]).is_multiple_of(31) {
+        return Err(Error::InvalidZlibStream {
+            reason: "header check bits are wrong",
+        });
+    }
+    if flg & 0x20 != 0 {
+        return Err(Error::InvalidZlibStream {
+            reason: "preset dictionaries are not supported",
+        });
+    }
+
+    let input: &[u8] = &data[HEADER_SIZE..];
+    let mut decompress: Decompress = Decompress::new(false);
+    let mut output: Vec<u8> = Vec::new();
+    loop {
+        let consumed: usize = decompress.total_in() as usize;
+        let produced: usize = output.len();
+        output.reserve(BUFFER_SIZE);
+        let status: Status = decompress
+            .decompress_vec(
+                &input[consumed..],
+                &mut output,
+                FlushDecompress::None,
+            )
+            .map_err(|_| Error::InvalidZlibStream {
+                reason: "compressed data is corrupt",
+            })?;
+
+        if output.len() > limit {
+            return Err(Error::DecompressedTooLarge { limit });
+        }
+        if status == Status::StreamEnd {
+            break;
+        }
+        if decompress.total_in() as usize == consumed
+            && output.len() == produced
+        {
+            return Err(Error::InvalidZlibStream {
+                reason: "stream is truncated",
+            });
+        }
+    }
+
+    let end: usize = decompress.total_in() as usize;
+    let checksum: [u8; CHECKSUM_SIZE] = input
+        .get(end..end + CHECKSUM_SIZE)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(Error::InvalidZlibStream {
+            reason: "Adler-32 checksum is missing",
+        })?;
+    if u32::from_be_bytes(checksum) != adler32(&output) {
+        return Err(Error::InvalidZlibStream {
+            reason: "Adler-32 checksum does not match",
+        });
+    }
+
+    Ok(output)
+}
+
+/// Deflates `data` into a zlib stream at the best compression level.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut compress: Compress =
+        Compress::new(flate2::Compression::best(), true);
+    let mut output: Vec<u8> = Vec::with_capacity(data.len() / 2 + 64);
+    loop {
+        let consumed: usize = compress.total_in() as usize;
+        output.reserve(BUFFER_SIZE);
+        let status: Status = compress
+            .compress_vec(&data[consumed..], &mut output, FlushCompress::Finish)
+            .expect("deflating into a growing buffer cannot fail");
+        if status == Status::StreamEnd {
+            return output;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_data() -> Vec<u8> {
+        (0..100_000u32)
+            .map(|i| ((i / 7) ^ (i % 13)) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        // Long enough for the sums to need reducing along the way.
+        assert_eq!(adler32(&[255; 10_000]), 0xB623_EB2B);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = testing_data();
+        let compressed = deflate(&data);
+        assert!(compressed.len() < data.len());
+        assert_eq!(inflate(&compressed, data.len()).unwrap(), data);
+        assert_eq!(inflate(&deflate(b""), 0).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_reads_other_encoders() {
+        use std::io::Write;
+        let data = testing_data();
+        let mut encoder = flate2::write::ZlibEncoder::new(
+            Vec::new(),
+            flate2::Compression::fast(),
+        );
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        assert_eq!(inflate(&compressed, usize::MAX).unwrap(), data);
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let mut compressed = deflate(b"some image data");
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+
+        assert!(matches!(
+            inflate(&compressed, 100),
+            Err(Error::InvalidZlibStream {
+                reason: "Adler-32 checksum does not match"
+            })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_streams() {
+        let compressed = deflate(&testing_data());
+        let invalid = |data: &[u8]| {
+            matches!(
+                inflate(data, usize::MAX),
+                Err(Error::InvalidZlibStream { .. })
+            )
+        };
+
+        assert!(invalid(&compressed[..compressed.len() / 2]));
+        assert!(invalid(&compressed[..compressed.len() - 2]));
+        assert!(invalid(&[0x78]));
+        let mut header = compressed.clone();
+        header[1] ^= 1;
+        assert!(invalid(&header));
+        let mut method = compressed.clone();
+        method[0] = 0x79;
+        assert!(invalid(&method));
+        let mut body = compressed;
+        body[2] = 0xff;
+        assert!(invalid(&body));
+    }
+
+    #[test]
+    fn test_limit() {
+        let data = testing_data();
+        let compressed = deflate(&data);
+
+        assert!(matches!(
+            inflate(&compressed, data.len() - 1),
+            Err(Error::DecompressedTooLarge { .. })
+        ));
+    }
+}